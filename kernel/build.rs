@@ -1,3 +1,5 @@
+use std::env;
+
 use build_target::target_arch;
 
 fn main() {
@@ -12,4 +14,18 @@ fn main() {
 fn x86_linker() {
     println!("cargo:rerun-if-changed=kernel/src/arch/x86/linker.ld");
     println!("cargo:rustc-link-arg=-Tkernel/src/arch/x86/linker.ld");
+    println!(
+        "cargo:rustc-link-arg=--defsym=__ksymtab_size={:#x}",
+        ksymtab_size()
+    );
+}
+
+/// 链接脚本为符号表预留的字节数。链接之后才知道有哪些函数，所以按实测的符号数量预留：debug 构建的内核约 2700 个
+/// 函数、340 KiB，集成测试中最多的（task）约 2900 个、360 KiB；release 构建约 380 个函数、36 KiB。
+/// 空间不够时 tools/ksymtab 会报错并给出实际需要的大小，需要调大这里的值。
+fn ksymtab_size() -> u64 {
+    match env::var("PROFILE").as_deref() {
+        Ok("release") => 0x10000,
+        _ => 0x70000,
+    }
 }
@@ -0,0 +1,180 @@
+//! MADT（多 APIC 描述表，签名 "APIC"）：描述处理器的 Local APIC、I/O APIC，以及 ISA 中断到全局中断号（GSI）
+//! 的重定向。
+
+use x86_64::PhysAddr;
+
+use super::{read_phys, SdtHeader};
+
+/// 最多记录的处理器数量。
+pub const MAX_PROCESSORS: usize = 64;
+/// 最多记录的 I/O APIC 数量。
+pub const MAX_IO_APICS: usize = 8;
+/// 最多记录的中断重定向数量，ISA 只有 16 个中断。
+pub const MAX_OVERRIDES: usize = 16;
+
+/// 一个处理器的 Local APIC。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessorInfo {
+    pub processor_id: u8,
+    pub apic_id: u8,
+    /// 处理器是否可用。
+    pub enabled: bool,
+}
+
+/// 一个 I/O APIC，负责从 gsi_base 开始的若干个全局中断。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    pub gsi_base: u32,
+}
+
+/// 中断信号的极性。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// 中断的触发方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// ISA 中断 irq 实际连接到全局中断 gsi。没有重定向的 ISA 中断与 GSI 一一对应，高电平边沿触发。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: TriggerMode,
+}
+
+/// 解析后的 MADT。
+#[derive(Debug, Clone)]
+pub struct Madt {
+    /// 所有处理器共用的 Local APIC 寄存器的物理地址。
+    pub local_apic_address: PhysAddr,
+    /// 系统中是否还有 8259 PIC（使用 APIC 之前需要屏蔽它）。
+    pub has_8259: bool,
+    processors: [Option<ProcessorInfo>; MAX_PROCESSORS],
+    io_apics: [Option<IoApicInfo>; MAX_IO_APICS],
+    overrides: [Option<InterruptOverride>; MAX_OVERRIDES],
+}
+
+/// 表项的类型。
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+fn push<T: Copy>(slots: &mut [Option<T>], value: T) {
+    if let Some(slot) = slots.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(value);
+    }
+}
+
+impl Madt {
+    /// 解析物理地址 addr 处长度为 len 的 MADT，超出容量的表项被忽略。
+    pub(super) fn parse(addr: PhysAddr, len: usize) -> Madt {
+        let body = addr + core::mem::size_of::<SdtHeader>() as u64;
+        let local_apic_address: u32 = unsafe { read_phys(body) };
+        let flags: u32 = unsafe { read_phys(body + 4u64) };
+        let mut madt = Madt {
+            local_apic_address: PhysAddr::new(local_apic_address as u64),
+            has_8259: flags & 1 != 0,
+            processors: [None; MAX_PROCESSORS],
+            io_apics: [None; MAX_IO_APICS],
+            overrides: [None; MAX_OVERRIDES],
+        };
+        let end = addr + len as u64;
+        let mut entry = body + 8u64;
+        while entry + 2u64 <= end {
+            let (kind, entry_len): (u8, u8) =
+                unsafe { (read_phys(entry), read_phys(entry + 1u64)) };
+            if entry_len < 2 || entry + entry_len as u64 > end {
+                break;
+            }
+            match kind {
+                ENTRY_LOCAL_APIC => {
+                    let flags: u32 = unsafe { read_phys(entry + 4u64) };
+                    push(
+                        &mut madt.processors,
+                        ProcessorInfo {
+                            processor_id: unsafe { read_phys(entry + 2u64) },
+                            apic_id: unsafe { read_phys(entry + 3u64) },
+                            enabled: flags & 1 != 0,
+                        },
+                    );
+                }
+                ENTRY_IO_APIC => {
+                    let address: u32 = unsafe { read_phys(entry + 4u64) };
+                    push(
+                        &mut madt.io_apics,
+                        IoApicInfo {
+                            id: unsafe { read_phys(entry + 2u64) },
+                            address: PhysAddr::new(address as u64),
+                            gsi_base: unsafe { read_phys(entry + 8u64) },
+                        },
+                    );
+                }
+                ENTRY_INTERRUPT_OVERRIDE => {
+                    let flags: u16 = unsafe { read_phys(entry + 8u64) };
+                    // 第 0-1 位是极性，第 2-3 位是触发方式，0 表示符合总线的默认值（ISA 为高电平边沿触发）。
+                    push(
+                        &mut madt.overrides,
+                        InterruptOverride {
+                            irq: unsafe { read_phys(entry + 3u64) },
+                            gsi: unsafe { read_phys(entry + 4u64) },
+                            polarity: match flags & 0b11 {
+                                0b11 => Polarity::ActiveLow,
+                                _ => Polarity::ActiveHigh,
+                            },
+                            trigger: match (flags >> 2) & 0b11 {
+                                0b11 => TriggerMode::Level,
+                                _ => TriggerMode::Edge,
+                            },
+                        },
+                    );
+                }
+                ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    let address: u64 = unsafe { read_phys(entry + 4u64) };
+                    // 不是合法物理地址的覆盖项视为损坏，继续使用 32 位的地址。
+                    if let Ok(address) = PhysAddr::try_new(address) {
+                        madt.local_apic_address = address;
+                    }
+                }
+                _ => {}
+            }
+            entry += entry_len as u64;
+        }
+        madt
+    }
+
+    pub fn processors(&self) -> impl Iterator<Item = &ProcessorInfo> {
+        self.processors.iter().flatten()
+    }
+
+    pub fn io_apics(&self) -> impl Iterator<Item = &IoApicInfo> {
+        self.io_apics.iter().flatten()
+    }
+
+    pub fn overrides(&self) -> impl Iterator<Item = &InterruptOverride> {
+        self.overrides.iter().flatten()
+    }
+
+    /// ISA 中断 irq 对应的全局中断及其极性和触发方式。
+    pub fn isa_interrupt(&self, irq: u8) -> InterruptOverride {
+        self.overrides()
+            .find(|o| o.irq == irq)
+            .copied()
+            .unwrap_or(InterruptOverride {
+                irq,
+                gsi: irq as u32,
+                polarity: Polarity::ActiveHigh,
+                trigger: TriggerMode::Edge,
+            })
+    }
+}
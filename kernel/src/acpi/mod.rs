@@ -0,0 +1,165 @@
+//! ACPI 表：从 RSDP 开始，通过 RSDT（ACPI 1.0）或 XSDT（ACPI 2.0+）找到各个系统描述表。
+//! 表都在物理内存中，通过物理内存映射只读地访问，解析结果放在固定大小的结构中，不依赖堆。
+
+mod madt;
+
+pub use madt::{
+    InterruptOverride, IoApicInfo, Madt, Polarity, ProcessorInfo, TriggerMode, MAX_IO_APICS,
+    MAX_OVERRIDES, MAX_PROCESSORS,
+};
+
+use core::ptr::read_unaligned;
+
+use spin::Once;
+use x86_64::PhysAddr;
+
+use crate::memory::phys_to_virt;
+
+/// 系统描述表的表头。
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// RSDP（ACPI 2.0 的扩展格式，ACPI 1.0 只有前 20 字节）。
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// 根表：RSDT 中的表地址是 32 位的，XSDT 中是 64 位的。
+#[derive(Debug, Clone, Copy)]
+enum RootTable {
+    Rsdt(PhysAddr),
+    Xsdt(PhysAddr),
+}
+
+static ROOT: Once<Option<RootTable>> = Once::new();
+
+/// 读取物理地址处的一个值，不要求对齐。
+unsafe fn read_phys<T: Copy>(addr: PhysAddr) -> T {
+    read_unaligned(phys_to_virt(addr).as_ptr())
+}
+
+/// 物理地址 [addr, addr + len) 的校验和，合法的表所有字节之和为 0。
+fn checksum(addr: PhysAddr, len: usize) -> u8 {
+    (0..len as u64).fold(0u8, |sum, i| {
+        sum.wrapping_add(unsafe { read_phys(addr + i) })
+    })
+}
+
+/// 在 [start, end) 中按 16 字节对齐查找 RSDP。
+fn scan_rsdp(start: u64, end: u64) -> Option<PhysAddr> {
+    (start..end).step_by(16).map(PhysAddr::new).find(
+        |&addr| unsafe { read_phys::<[u8; 8]>(addr) } == *b"RSD PTR " && checksum(addr, 20) == 0,
+    )
+}
+
+/// 按规范在 EBDA 的前 1 KiB 和 BIOS 只读区 0xE0000..0x100000 中查找 RSDP。
+fn find_rsdp() -> Option<PhysAddr> {
+    // 0x40E 处保存着 EBDA 的段地址。
+    let ebda = (unsafe { read_phys::<u16>(PhysAddr::new(0x40e)) } as u64) << 4;
+    let in_ebda = if (0x80000..0xa0000).contains(&ebda) {
+        scan_rsdp(ebda, ebda + 1024)
+    } else {
+        None
+    };
+    in_ebda.or_else(|| scan_rsdp(0xe0000, 0x100000))
+}
+
+fn root() -> Option<RootTable> {
+    *ROOT.call_once(|| {
+        let addr = find_rsdp()?;
+        let rsdp: Rsdp = unsafe { read_phys(addr) };
+        // XSDT 的地址损坏（不是合法的物理地址）时退回 RSDT。
+        let xsdt = PhysAddr::try_new(rsdp.xsdt_address).ok();
+        match xsdt {
+            Some(xsdt)
+                if rsdp.revision >= 2
+                    && !xsdt.is_null()
+                    && checksum(addr, rsdp.length as usize) == 0 =>
+            {
+                Some(RootTable::Xsdt(xsdt))
+            }
+            _ => Some(RootTable::Rsdt(PhysAddr::new(rsdp.rsdt_address as u64))),
+        }
+    })
+}
+
+/// 系统中是否有 ACPI 表。
+pub fn is_available() -> bool {
+    root().is_some()
+}
+
+/// 读取物理地址处的表头，校验和不正确时返回 None。
+fn read_header(addr: PhysAddr) -> Option<SdtHeader> {
+    let header: SdtHeader = unsafe { read_phys(addr) };
+    (checksum(addr, header.length as usize) == 0).then_some(header)
+}
+
+/// 查找签名为 signature 的表（例如 `b"APIC"`、`b"HPET"`），返回表的物理地址和表头。
+pub fn find_table(signature: &[u8; 4]) -> Option<(PhysAddr, SdtHeader)> {
+    let (root, entry_size) = match root()? {
+        RootTable::Rsdt(addr) => (addr, 4),
+        RootTable::Xsdt(addr) => (addr, 8),
+    };
+    let header = read_header(root)?;
+    let entries = (header.length as u64 - core::mem::size_of::<SdtHeader>() as u64) / entry_size;
+    let first = root + core::mem::size_of::<SdtHeader>() as u64;
+    (0..entries)
+        .filter_map(|i| {
+            let entry = first + i * entry_size;
+            let addr = match entry_size {
+                4 => unsafe { read_phys::<u32>(entry) as u64 },
+                _ => unsafe { read_phys::<u64>(entry) },
+            };
+            // 损坏的表项可能不是合法的物理地址（超过 52 位），跳过。
+            PhysAddr::try_new(addr).ok()
+        })
+        .find_map(|addr| {
+            let header = read_header(addr)?;
+            (header.signature == *signature).then_some((addr, header))
+        })
+}
+
+/// 解析 MADT（多 APIC 描述表），没有时返回 None。
+pub fn madt() -> Option<&'static Madt> {
+    static MADT: Once<Option<Madt>> = Once::new();
+    MADT.call_once(|| {
+        let (addr, header) = find_table(b"APIC")?;
+        Some(Madt::parse(addr, header.length as usize))
+    })
+    .as_ref()
+}
+
+/// FADT（签名 "FACP"）中 CMOS 世纪寄存器的下标，FADT 中没有（为 0）时返回 None。
+pub fn century_register() -> Option<u8> {
+    // 世纪字段在 FADT 的第 108 字节。
+    const FADT_CENTURY: u64 = 108;
+    let (addr, header) = find_table(b"FACP")?;
+    if (header.length as u64) <= FADT_CENTURY {
+        return None;
+    }
+    match unsafe { read_phys::<u8>(addr + FADT_CENTURY) } {
+        0 => None,
+        register => Some(register),
+    }
+}
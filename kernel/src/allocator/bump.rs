@@ -1,7 +1,7 @@
 //! BumpAllocator：只简单记录分配次数，并在分配次数减少到0（dealloc 会使技术减一）前，内存不进行复用。
 use core::alloc::GlobalAlloc;
 
-use super::{align_up, Locked};
+use super::{align_up, HeapStats, Locked};
 
 pub struct BumpAllocator {
     heap_start: usize,
@@ -30,6 +30,17 @@ impl BumpAllocator {
         self.heap_end = heap_start + heap_size;
         self.next = heap_start;
     }
+
+    /// 堆的末尾新映射了 by 字节，由于 bump 分配器只看 heap_end，直接后移即可。
+    pub unsafe fn extend(&mut self, by: usize) {
+        self.heap_end += by;
+    }
+
+    /// 只有 next 之后的部分是空闲的。
+    pub fn fill_stats(&self, stats: &mut HeapStats) {
+        stats.free_bytes = self.heap_end - self.next;
+        stats.largest_free_region = stats.free_bytes;
+    }
 }
 
 // GlobalAlloc特性定义了一个堆分配器必须提供的功能。该trait很特别，因为程序员几乎从不直接使用它。相反，编译器会在使用alloc的分配和集合类型时自动插入对该trait方法的适当调用。
@@ -44,8 +55,14 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
             None => return core::ptr::null_mut(),
         };
         if alloc_end > bump.heap_end {
-            // 堆空间不足。
-            return core::ptr::null_mut();
+            // 堆空间不足，尝试扩展堆。
+            match super::grow_heap(alloc_end - bump.heap_end) {
+                Some(by) => bump.extend(by),
+                None => return core::ptr::null_mut(),
+            }
+            if alloc_end > bump.heap_end {
+                return core::ptr::null_mut();
+            }
         }
         bump.next = alloc_end;
         bump.allocations += 1;
@@ -0,0 +1,208 @@
+//! 调试堆：包装任意一种分配器，用于定位堆破坏问题。通过命令行 `heap_debug` 或者在初始化堆之前调用
+//! [`enable_debug_heap`](super::enable_debug_heap) 启用，未启用时所有操作直接转发给内部分配器。
+//! 每次分配的实际布局如下：
+//! ```text
+//! | 填充 | Header | 前红区 | 用户数据 | 后红区 |
+//!                         ^ 返回给用户的地址，按 layout.align() 对齐
+//! ```
+//! 1. 分配时红区填充 REDZONE_BYTE，用户数据填充 UNINIT_BYTE，便于发现使用未初始化内存。
+//! 2. 释放时检查 Header 中记录的 Layout 是否与传入的一致、红区是否被改写，然后用 POISON_BYTE 填充用户数据。
+//! 3. 释放的块先放入隔离区（quarantine），隔离区满时才真正归还给内部分配器。这样被释放的内存不会马上被复用，
+//!    Header 也不会被内部分配器的链表节点覆盖，可以检测出重复释放；归还前再检查一次毒化数据，可以发现释放后写入。
+//! 发现问题时直接 panic，并输出分配的地址和 Layout。
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem,
+    ops::Deref,
+    ptr, slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use super::align_up;
+
+/// 每侧红区的大小。
+const REDZONE_SIZE: usize = 16;
+const REDZONE_BYTE: u8 = 0xfd;
+/// 新分配内存的填充字节。
+const UNINIT_BYTE: u8 = 0xcd;
+/// 已释放内存的填充字节。
+const POISON_BYTE: u8 = 0xdd;
+const ALLOCATED_MAGIC: usize = 0xa110_ca7e_d0d0_beef;
+const FREED_MAGIC: usize = 0xf7ee_d0d0_dead_beef;
+/// 隔离区可以容纳的块数。
+const QUARANTINE_SIZE: usize = 64;
+
+/// 位于前红区之前，记录分配时的信息。
+struct Header {
+    magic: usize,
+    size: usize,
+    align: usize,
+    /// 用户地址到内部分配器返回地址的偏移。
+    offset: usize,
+}
+
+const HEADER_SIZE: usize = mem::size_of::<Header>();
+
+/// 隔离区：已释放但还未归还内部分配器的块，环形缓冲。
+struct Quarantine {
+    /// 用户地址和分配时的 Layout。
+    blocks: [Option<(*mut u8, Layout)>; QUARANTINE_SIZE],
+    next: usize,
+}
+
+// 隔离区中只保存地址，只会在持有锁时访问。
+unsafe impl Send for Quarantine {}
+
+pub struct DebugHeap<A> {
+    inner: A,
+    enabled: AtomicBool,
+    quarantine: spin::Mutex<Quarantine>,
+}
+
+impl<A> DebugHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            enabled: AtomicBool::new(false),
+            quarantine: spin::Mutex::new(Quarantine {
+                blocks: [None; QUARANTINE_SIZE],
+                next: 0,
+            }),
+        }
+    }
+
+    /// 启用调试堆。必须在第一次分配之前调用，否则之前分配的块没有 Header，释放时会被当成非法释放。
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// 用户数据之前需要保留的字节数，保证用户地址按 align 对齐。
+    fn offset(layout: Layout) -> usize {
+        align_up(HEADER_SIZE + REDZONE_SIZE, layout.align())
+    }
+
+    /// 内部分配器实际分配的布局。
+    fn outer_layout(layout: Layout) -> Option<Layout> {
+        let size = Self::offset(layout)
+            .checked_add(layout.size())?
+            .checked_add(REDZONE_SIZE)?;
+        let align = layout.align().max(mem::align_of::<Header>());
+        Layout::from_size_align(size, align).ok()
+    }
+
+    unsafe fn header<'a>(ptr: *mut u8) -> &'a mut Header {
+        &mut *(ptr.sub(REDZONE_SIZE + HEADER_SIZE) as *mut Header)
+    }
+
+    /// 检查 ptr 处的 len 字节是否都是 byte。
+    unsafe fn is_filled(ptr: *const u8, len: usize, byte: u8) -> bool {
+        slice::from_raw_parts(ptr, len).iter().all(|&b| b == byte)
+    }
+}
+
+impl<A: GlobalAlloc> DebugHeap<A> {
+    /// 放入隔离区。隔离区满时，把最早放入的块归还给内部分配器，归还前检查毒化数据是否完好。
+    unsafe fn quarantine(&self, ptr: *mut u8, layout: Layout) {
+        let evicted = {
+            let mut quarantine = self.quarantine.lock();
+            let next = quarantine.next;
+            quarantine.next = (next + 1) % QUARANTINE_SIZE;
+            quarantine.blocks[next].replace((ptr, layout))
+        };
+        if let Some((ptr, layout)) = evicted {
+            if !Self::is_filled(ptr, layout.size(), POISON_BYTE) {
+                panic!("heap use after free at {:p}, {:?}", ptr, layout);
+            }
+            let base = ptr.sub(Self::header(ptr).offset);
+            // alloc 成功时 outer_layout 一定有效。
+            self.inner
+                .dealloc(base, Self::outer_layout(layout).unwrap());
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !self.is_enabled() {
+            return self.inner.alloc(layout);
+        }
+        let outer = match Self::outer_layout(layout) {
+            Some(outer) => outer,
+            None => return ptr::null_mut(),
+        };
+        let base = self.inner.alloc(outer);
+        if base.is_null() {
+            return base;
+        }
+        let offset = Self::offset(layout);
+        let ptr = base.add(offset);
+        (ptr.sub(REDZONE_SIZE + HEADER_SIZE) as *mut Header).write(Header {
+            magic: ALLOCATED_MAGIC,
+            size: layout.size(),
+            align: layout.align(),
+            offset,
+        });
+        ptr.sub(REDZONE_SIZE)
+            .write_bytes(REDZONE_BYTE, REDZONE_SIZE);
+        ptr.write_bytes(UNINIT_BYTE, layout.size());
+        ptr.add(layout.size())
+            .write_bytes(REDZONE_BYTE, REDZONE_SIZE);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.is_enabled() {
+            return self.inner.dealloc(ptr, layout);
+        }
+        let header = Self::header(ptr);
+        match header.magic {
+            ALLOCATED_MAGIC => {}
+            FREED_MAGIC => panic!("heap double free at {:p}, {:?}", ptr, layout),
+            _ => panic!("heap invalid free at {:p}, {:?}", ptr, layout),
+        }
+        if header.size != layout.size() || header.align != layout.align() {
+            panic!(
+                "heap layout mismatch at {:p}: allocated with {:?}, freed with {:?}",
+                ptr,
+                Layout::from_size_align_unchecked(header.size, header.align),
+                layout
+            );
+        }
+        if !Self::is_filled(ptr.sub(REDZONE_SIZE), REDZONE_SIZE, REDZONE_BYTE) {
+            panic!("heap buffer underflow at {:p}, {:?}", ptr, layout);
+        }
+        if !Self::is_filled(ptr.add(layout.size()), REDZONE_SIZE, REDZONE_BYTE) {
+            panic!("heap buffer overflow at {:p}, {:?}", ptr, layout);
+        }
+        header.magic = FREED_MAGIC;
+        ptr.write_bytes(POISON_BYTE, layout.size());
+        self.quarantine(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !self.is_enabled() {
+            return self.inner.realloc(ptr, layout, new_size);
+        }
+        // 启用时总是分配新块再释放旧块，这样旧块会进入隔离区，之后通过旧指针的访问也能被发现。
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+impl<A> Deref for DebugHeap<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.inner
+    }
+}
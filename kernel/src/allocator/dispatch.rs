@@ -0,0 +1,131 @@
+//! 启动时选择的全局分配器。所有分配器都静态地放在一起，init 时选定其中一个，之后的分配和释放都转发给它。
+//! 这样同一个内核镜像可以通过命令行 `allocator=<name>` 切换分配器，便于测试和比较。
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use super::{
+    bump::BumpAllocator,
+    fixed_size::FixedSizeBlockAllocator,
+    linked_list::{FitStrategy, LinkedListAllocator},
+    slab::SlabAllocator,
+    HeapStats, Locked,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AllocatorKind {
+    Bump,
+    LinkedList,
+    FixedSize,
+    Slab,
+}
+
+impl AllocatorKind {
+    pub const ALL: [AllocatorKind; 4] = [
+        AllocatorKind::Bump,
+        AllocatorKind::LinkedList,
+        AllocatorKind::FixedSize,
+        AllocatorKind::Slab,
+    ];
+
+    /// 命令行中使用的名字。
+    pub fn name(self) -> &'static str {
+        match self {
+            AllocatorKind::Bump => "bump",
+            AllocatorKind::LinkedList => "linked_list",
+            AllocatorKind::FixedSize => "fixed_size",
+            AllocatorKind::Slab => "slab",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    fn from_u8(value: u8) -> Self {
+        Self::ALL[value as usize]
+    }
+}
+
+pub struct Heap {
+    kind: AtomicU8,
+    bump: Locked<BumpAllocator>,
+    linked_list: Locked<LinkedListAllocator>,
+    fixed_size: Locked<FixedSizeBlockAllocator>,
+    slab: Locked<SlabAllocator>,
+}
+
+impl Heap {
+    pub const fn new() -> Self {
+        Self {
+            kind: AtomicU8::new(AllocatorKind::LinkedList as u8),
+            bump: Locked::new(BumpAllocator::new()),
+            linked_list: Locked::new(LinkedListAllocator::new()),
+            fixed_size: Locked::new(FixedSizeBlockAllocator::new()),
+            slab: Locked::new(SlabAllocator::new()),
+        }
+    }
+
+    pub fn kind(&self) -> AllocatorKind {
+        AllocatorKind::from_u8(self.kind.load(Ordering::Relaxed))
+    }
+
+    /// 选定分配器并初始化。
+    /// # Safety
+    /// 只能在第一次分配之前调用一次，并且 [heap_start, heap_start + heap_size) 已经映射且未被使用。
+    pub unsafe fn init(&self, kind: AllocatorKind, heap_start: usize, heap_size: usize) {
+        self.kind.store(kind as u8, Ordering::Relaxed);
+        match kind {
+            AllocatorKind::Bump => self.bump.lock().init(heap_start, heap_size),
+            AllocatorKind::LinkedList => self.linked_list.lock().init(heap_start, heap_size),
+            AllocatorKind::FixedSize => self.fixed_size.lock().init(heap_start, heap_size),
+            AllocatorKind::Slab => self.slab.lock().init(heap_start, heap_size),
+        }
+    }
+
+    /// 设置 linked_list 分配器查找空闲块的策略。
+    pub fn set_fit_strategy(&self, strategy: FitStrategy) {
+        self.linked_list.lock().set_strategy(strategy);
+    }
+
+    pub fn fill_stats(&self, stats: &mut HeapStats) {
+        match self.kind() {
+            AllocatorKind::Bump => self.bump.lock().fill_stats(stats),
+            AllocatorKind::LinkedList => self.linked_list.lock().fill_stats(stats),
+            AllocatorKind::FixedSize => self.fixed_size.lock().fill_stats(stats),
+            AllocatorKind::Slab => self.slab.lock().fill_stats(stats),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.kind() {
+            AllocatorKind::Bump => self.bump.alloc(layout),
+            AllocatorKind::LinkedList => self.linked_list.alloc(layout),
+            AllocatorKind::FixedSize => self.fixed_size.alloc(layout),
+            AllocatorKind::Slab => self.slab.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.kind() {
+            AllocatorKind::Bump => self.bump.dealloc(ptr, layout),
+            AllocatorKind::LinkedList => self.linked_list.dealloc(ptr, layout),
+            AllocatorKind::FixedSize => self.fixed_size.dealloc(ptr, layout),
+            AllocatorKind::Slab => self.slab.dealloc(ptr, layout),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match self.kind() {
+            AllocatorKind::Bump => self.bump.realloc(ptr, layout, new_size),
+            AllocatorKind::LinkedList => self.linked_list.realloc(ptr, layout, new_size),
+            AllocatorKind::FixedSize => self.fixed_size.realloc(ptr, layout, new_size),
+            AllocatorKind::Slab => self.slab.realloc(ptr, layout, new_size),
+        }
+    }
+}
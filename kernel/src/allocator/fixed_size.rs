@@ -16,19 +16,19 @@ use core::{
     ptr,
 };
 
-use super::Locked;
+use super::{linked_list::LinkedListAllocator, HeapStats, Locked};
 
 struct ListNode {
     next: Option<&'static mut ListNode>,
 }
 
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+pub(super) const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
 
 pub struct FixedSizeBlockAllocator {
     /// 每个固定块链表的头部。
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     /// 兜底分配器。超出固定块大小的分配，或者没有可用的固定块时，使用这个分配器。
-    fallback_allocator: linked_list_allocator::Heap,
+    fallback_allocator: LinkedListAllocator,
 }
 
 impl FixedSizeBlockAllocator {
@@ -36,7 +36,7 @@ impl FixedSizeBlockAllocator {
         const EMPTY: Option<&'static mut ListNode> = None;
         Self {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
-            fallback_allocator: linked_list_allocator::Heap::empty(),
+            fallback_allocator: LinkedListAllocator::new(),
         }
     }
 
@@ -44,6 +44,11 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    /// 堆的末尾新映射了 by 字节，交给兜底分配器管理。
+    pub unsafe fn extend(&mut self, by: usize) {
+        self.fallback_allocator.extend(by);
+    }
+
     /// 遍历确定最小的能容纳 `layout` 的固定块大小。
     fn list_index(layout: Layout) -> Option<usize> {
         let size = layout.size().max(layout.align());
@@ -58,14 +63,22 @@ impl FixedSizeBlockAllocator {
                     unsafe { Some(ptr::NonNull::new_unchecked(node as *mut _ as _)) }
                 }
                 None => {
-                    // 没有可用的固定块，从兜底分配器中分配。
-                    self.fallback_allocator.allocate_first_fit(layout).ok()
+                    // 没有可用的固定块，从兜底分配器中分配一个完整的块，这样释放后它可以放进对应的链表复用。
+                    let block_size = BLOCK_SIZES[index];
+                    let block_layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    self.fallback_alloc(block_layout)
                 }
             },
-            None => self.fallback_allocator.allocate_first_fit(layout).ok(),
+            None => self.fallback_alloc(layout),
         }
     }
 
+    fn fallback_alloc(&mut self, layout: Layout) -> Option<ptr::NonNull<u8>> {
+        self.fallback_allocator
+            .alloc_by_layout(layout)
+            .and_then(|addr| ptr::NonNull::new(addr as *mut u8))
+    }
+
     fn do_dealloc(&mut self, addr: *mut u8, layout: Layout) {
         match Self::list_index(layout) {
             Some(index) => {
@@ -75,15 +88,40 @@ impl FixedSizeBlockAllocator {
             }
             None => unsafe {
                 self.fallback_allocator
-                    .deallocate(ptr::NonNull::new_unchecked(addr), layout)
+                    .dealloc_by_layout(addr as usize, layout)
             },
         }
     }
+
+    /// 统计每个固定块链表的长度，以及兜底分配器的空闲情况。
+    pub fn fill_stats(&self, stats: &mut HeapStats) {
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let mut count = 0;
+            let mut current = head;
+            while let Some(node) = current {
+                count += 1;
+                current = &node.next;
+            }
+            stats.free_blocks[index] = (BLOCK_SIZES[index], count);
+            stats.free_bytes += count * BLOCK_SIZES[index];
+        }
+        stats.size_classes = BLOCK_SIZES.len();
+        self.fallback_allocator.fill_stats(stats);
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.lock()
+        let mut allocator = self.lock();
+        if let Some(ptr) = allocator.do_alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // 兜底分配器也没有空间了，扩展堆后重试。
+        match super::grow_heap(layout.size() + layout.align()) {
+            Some(by) => allocator.extend(by),
+            None => return ptr::null_mut(),
+        }
+        allocator
             .do_alloc(layout)
             .map_or(ptr::null_mut(), |x| x.as_ptr())
     }
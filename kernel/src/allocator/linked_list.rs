@@ -4,9 +4,41 @@
 //! 2. 分配大小与请求一样，不会浪费内存。
 //! 缺点：
 //! 1. 慢。需要遍历链表
-use core::alloc::{GlobalAlloc, Layout};
+//!
+//! 所有空闲块的地址和大小都是 ListNode 大小的整数倍，这样分割后剩下的部分要么为空，要么一定能放下 ListNode header。
+//! 查找空闲块的策略可以通过 set_strategy 选择，见 FitStrategy。
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, ptr,
+};
 
-use super::{align_up, Locked};
+use super::{align_up, HeapStats, Locked};
+
+/// 分配和分割的最小单位，也是空闲块地址的对齐要求。
+const GRANULE: usize = mem::size_of::<ListNode>();
+
+/// 查找空闲块的策略。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitStrategy {
+    /// 使用地址最低的足够大的空闲块。实现简单，但低地址处容易积累小碎片。
+    FirstFit,
+    /// 使用能满足请求的最小空闲块，尽量保留大块，代价是每次都要遍历整个链表。
+    BestFit,
+    /// 从上次分配的位置开始查找，到达末尾后从头开始。分配更均匀，但大块更容易被切碎。
+    NextFit,
+}
+
+impl FitStrategy {
+    /// 命令行中使用的名字。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "first" => Some(FitStrategy::FirstFit),
+            "best" => Some(FitStrategy::BestFit),
+            "next" => Some(FitStrategy::NextFit),
+            _ => None,
+        }
+    }
+}
 
 /// A node in a linked list.
 /// 实际管理的内存块在这个 header 的后面。
@@ -31,33 +63,68 @@ impl ListNode {
     }
 }
 
+/// 按地址顺序遍历空闲块。
+struct Regions<'a> {
+    current: Option<&'a ListNode>,
+}
+
+impl<'a> Iterator for Regions<'a> {
+    type Item = &'a ListNode;
+
+    fn next(&mut self) -> Option<&'a ListNode> {
+        let region = self.current?;
+        self.current = region.next.as_deref();
+        Some(region)
+    }
+}
+
 pub struct LinkedListAllocator {
     /// 指向链表的头部。即第一个空闲的内存块。
     head: ListNode,
+    /// 堆的结束地址，扩展堆时新的内存块从这里开始。
+    heap_end: usize,
+    strategy: FitStrategy,
+    /// NextFit 下次开始查找的地址。
+    cursor: usize,
 }
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
         Self {
             head: ListNode::new(0), // 头部并不是真正的内存块，所以大小为 0。
+            heap_end: 0,
+            strategy: FitStrategy::FirstFit,
+            cursor: 0,
         }
     }
 
+    pub fn set_strategy(&mut self, strategy: FitStrategy) {
+        self.strategy = strategy;
+    }
+
     ///
     /// # Safety
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         // 初始化时，只有一个内存块，且这个内存块的大小就是整个堆的大小。
-        self.add_free_region(heap_start, heap_size)
+        self.add_free_region(heap_start, heap_size);
+        self.heap_end = heap_start + heap_size;
+    }
+
+    /// 堆的末尾新映射了 by 字节，作为空闲块加入链表（如果紧邻最后一个空闲块会被合并）。
+    /// # Safety
+    /// 调用者需要保证 [heap_end, heap_end + by) 已经映射且未被使用。
+    pub unsafe fn extend(&mut self, by: usize) {
+        self.add_free_region(self.heap_end, by);
+        self.heap_end += by;
     }
 
     /// 从头部插入空闲的内存块。
     /// # unsafe
     /// 由于调用方需要保证分配的内存是有效的，因此这个函数是 unsafe 的。
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
-        // addr 必须是按 ListNode 对齐的。
-        assert_eq!(addr, align_up(addr, core::mem::align_of::<ListNode>()));
-        // 空闲内存必须至少能装下一个 ListNode header。
-        assert!(size >= core::mem::size_of::<ListNode>());
+        // addr 和 size 都必须是 GRANULE 的整数倍，这样空闲内存一定能装下一个 ListNode header。
+        assert_eq!(addr % GRANULE, 0);
+        assert!(size >= GRANULE && size % GRANULE == 0);
 
         let mut current = &mut self.head;
         while let Some(ref mut region) = current.next {
@@ -89,16 +156,41 @@ impl LinkedListAllocator {
         }
     }
 
-    /// 从头部开始查找第一个能够满足 size 和 align 的内存块。
-    /// 返回的是一个 Option，如果找到了，就返回这个内存块的指针和内存块的起始地址。
+    fn regions(&self) -> Regions<'_> {
+        Regions {
+            current: self.head.next.as_deref(),
+        }
+    }
+
+    /// 按照当前的策略查找能够满足 size 和 align 的内存块，并从链表中摘下。
+    /// 返回的是一个 Option，如果找到了，就返回这个内存块的指针和分配的起始地址。
     fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let fits = |region: &&ListNode| Self::alloc_from_region(region, size, align).is_ok();
+        let region_start = match self.strategy {
+            FitStrategy::FirstFit => self.regions().find(fits),
+            FitStrategy::BestFit => self.regions().filter(fits).min_by_key(|r| r.size),
+            FitStrategy::NextFit => self
+                .regions()
+                .filter(fits)
+                .find(|r| r.end_addr() > self.cursor)
+                .or_else(|| self.regions().find(fits)),
+        }?
+        .start_addr();
+        let region = self.remove_region(region_start)?;
+        let alloc_start = Self::alloc_from_region(region, size, align).ok()?;
+        self.cursor = alloc_start + size;
+        Some((region, alloc_start))
+    }
+
+    /// 从链表中摘下起始地址为 addr 的空闲块。
+    fn remove_region(&mut self, addr: usize) -> Option<&'static mut ListNode> {
         let mut current = &mut self.head;
         while let Some(ref mut region) = current.next {
-            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+            if region.start_addr() == addr {
                 let next = region.next.take();
-                let ret = Some((current.next.take().unwrap(), alloc_start));
+                let region = current.next.take();
                 current.next = next;
-                return ret;
+                return region;
             }
             current = current.next.as_mut().unwrap();
         }
@@ -107,37 +199,39 @@ impl LinkedListAllocator {
 
     // 使用 Result 的原因是可以方便地使用 ? 运算符。
     fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        // 区域的起始地址和 align 都是 GRANULE 的倍数，所以对齐产生的前部空隙要么为空，要么能放下 ListNode。
+        // 同理，分割后剩余的部分也一定能放下 ListNode，不会因为剩余太小而放弃这个区域。
         let alloc_start = align_up(region.start_addr(), align);
         let alloc_end = alloc_start.checked_add(size).ok_or(())?;
         if alloc_end > region.end_addr() {
             // 内存不够
             return Err(());
         }
-        let excess_size = region.end_addr() - alloc_end;
-        if excess_size > 0 && excess_size < core::mem::size_of::<ListNode>() {
-            // 剩余的内存不够放 ListNode header, 无法分割成两个内存块。
-            return Err(());
-        }
         Ok(alloc_start)
     }
 
-    /// 计算对齐后的 size 和 align.
+    /// 计算对齐后的 size 和 align，两者都向上取整到 GRANULE 的倍数。
     fn size_align(layout: Layout) -> (usize, usize) {
         let layout = layout
-            .align_to(core::mem::align_of::<ListNode>())
+            .align_to(GRANULE)
             .expect("adjusting alignment failed")
             .pad_to_align();
-        let size = layout.size().max(core::mem::size_of::<ListNode>());
-        (size, layout.align())
+        (layout.size().max(GRANULE), layout.align())
     }
 
     pub fn alloc_by_layout(&mut self, layout: Layout) -> Option<usize> {
         let (size, align) = LinkedListAllocator::size_align(layout);
         self.find_region(size, align).map(|(region, alloc_start)| {
+            // 先记下区域的范围，add_free_region 可能会覆盖 region 所在的 header。
+            let (region_start, region_end) = (region.start_addr(), region.end_addr());
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
-            let excess_size = region.end_addr() - alloc_end;
-            if excess_size > 0 {
-                unsafe {
+            let excess_size = region_end - alloc_end;
+            unsafe {
+                if alloc_start > region_start {
+                    // 对齐产生的前部空隙放回链表。
+                    self.add_free_region(region_start, alloc_start - region_start);
+                }
+                if excess_size > 0 {
                     // 分割成两个内存块
                     self.add_free_region(alloc_end, excess_size);
                 }
@@ -145,21 +239,189 @@ impl LinkedListAllocator {
             alloc_start
         })
     }
+
+    /// 释放 alloc_by_layout 分配的内存。
+    /// # Safety
+    /// addr 和 layout 必须与分配时一致。
+    pub unsafe fn dealloc_by_layout(&mut self, addr: usize, layout: Layout) {
+        let (size, _) = LinkedListAllocator::size_align(layout);
+        self.add_free_region(addr, size);
+    }
+
+    /// 尝试原地调整 alloc_by_layout 分配的内存块的大小：缩小时把尾部归还链表；扩大时使用紧邻其后的空闲块。
+    /// 成功返回 true，此时块的 Layout 变为 (new_size, layout.align())；失败时什么都不做。
+    /// # Safety
+    /// addr 和 layout 必须与分配时一致。
+    pub unsafe fn realloc_in_place(
+        &mut self,
+        addr: usize,
+        layout: Layout,
+        new_size: usize,
+    ) -> bool {
+        let (old_size, align) = Self::size_align(layout);
+        let new_size = match Layout::from_size_align(new_size, align) {
+            Ok(new_layout) => Self::size_align(new_layout).0,
+            Err(_) => return false,
+        };
+        if new_size <= old_size {
+            if new_size < old_size {
+                self.add_free_region(addr + new_size, old_size - new_size);
+            }
+            return true;
+        }
+        let needed = new_size - old_size;
+        let end = addr + old_size;
+        match self.regions().find(|r| r.start_addr() == end) {
+            Some(next) if next.size >= needed => {}
+            _ => return false,
+        }
+        let next = self.remove_region(end).unwrap();
+        let excess = next.size - needed;
+        if excess > 0 {
+            self.add_free_region(addr + new_size, excess);
+        }
+        true
+    }
+
+    /// 遍历空闲链表，统计空闲字节数和最大的空闲块。
+    pub fn fill_stats(&self, stats: &mut HeapStats) {
+        for region in self.regions() {
+            stats.free_bytes += region.size;
+            stats.largest_free_region = stats.largest_free_region.max(region.size);
+        }
+    }
 }
 
 unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         let mut allocator = self.lock();
         if let Some(addr) = allocator.alloc_by_layout(layout) {
-            addr as _
-        } else {
-            core::ptr::null_mut()
+            return addr as _;
+        }
+        // 没有足够大的空闲块，扩展堆后重试。多留出对齐和分割剩余空间所需的大小。
+        let (size, align) = LinkedListAllocator::size_align(layout);
+        match super::grow_heap(size + align + 2 * GRANULE) {
+            Some(by) => allocator.extend(by),
+            None => return core::ptr::null_mut(),
         }
+        allocator
+            .alloc_by_layout(layout)
+            .map_or(core::ptr::null_mut(), |addr| addr as _)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let (size, _) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
-        allocator.add_free_region(ptr as usize, size);
+        self.lock().dealloc_by_layout(ptr as usize, layout)
+    }
+
+    /// 优先原地扩展，避免 Vec 等容器增长时总是复制。
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        {
+            let mut allocator = self.lock();
+            if allocator.realloc_in_place(ptr as usize, layout, new_size) {
+                return ptr;
+            }
+            // 位于堆末尾的块可以扩展堆后原地增长。
+            let (size, _) = LinkedListAllocator::size_align(layout);
+            if ptr as usize + size == allocator.heap_end {
+                if let Some(by) = super::grow_heap(new_size.saturating_sub(size)) {
+                    allocator.extend(by);
+                    if allocator.realloc_in_place(ptr as usize, layout, new_size) {
+                        return ptr;
+                    }
+                }
+            }
+        }
+        // 无法原地调整：分配新块、复制、释放旧块。alloc 和 dealloc 会重新加锁。
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试用的堆，不使用全局堆，避免扩展堆等副作用。
+    #[repr(align(4096))]
+    struct TestHeap([u8; 4096]);
+
+    static mut TEST_HEAP: TestHeap = TestHeap([0; 4096]);
+
+    fn allocator(strategy: FitStrategy) -> LinkedListAllocator {
+        let mut allocator = LinkedListAllocator::new();
+        allocator.set_strategy(strategy);
+        unsafe {
+            let start = (*ptr::addr_of_mut!(TEST_HEAP)).0.as_mut_ptr() as usize;
+            allocator.init(start, mem::size_of::<TestHeap>());
+        }
+        allocator
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 8).unwrap()
+    }
+
+    /// 在 [a: 64][空闲 128][b: 32][空闲 64][c: 32][空闲 ...] 中分配 48 字节。
+    fn alloc_after_holes(strategy: FitStrategy) -> (usize, usize) {
+        let mut allocator = allocator(strategy);
+        let start = allocator.alloc_by_layout(layout(64)).unwrap();
+        let hole1 = allocator.alloc_by_layout(layout(128)).unwrap();
+        allocator.alloc_by_layout(layout(32)).unwrap();
+        let hole2 = allocator.alloc_by_layout(layout(64)).unwrap();
+        allocator.alloc_by_layout(layout(32)).unwrap();
+        unsafe {
+            allocator.dealloc_by_layout(hole1, layout(128));
+            allocator.dealloc_by_layout(hole2, layout(64));
+        }
+        let addr = allocator.alloc_by_layout(layout(48)).unwrap();
+        (addr - start, hole2 - start)
+    }
+
+    #[test_case]
+    fn first_fit_uses_lowest_region() {
+        let (offset, _) = alloc_after_holes(FitStrategy::FirstFit);
+        assert_eq!(offset, 64);
+    }
+
+    #[test_case]
+    fn best_fit_uses_smallest_region() {
+        let (offset, hole2) = alloc_after_holes(FitStrategy::BestFit);
+        assert_eq!(offset, hole2);
+    }
+
+    #[test_case]
+    fn next_fit_continues_after_last_allocation() {
+        let (offset, hole2) = alloc_after_holes(FitStrategy::NextFit);
+        assert!(offset > hole2);
+    }
+
+    #[test_case]
+    fn small_leftover_is_not_wasted() {
+        let mut allocator = allocator(FitStrategy::FirstFit);
+        let a = allocator.alloc_by_layout(layout(48)).unwrap();
+        allocator.alloc_by_layout(layout(16)).unwrap();
+        unsafe { allocator.dealloc_by_layout(a, layout(48)) };
+        // 空闲块为 48 字节，分配 40 字节后剩余的 8 字节放不下 ListNode，但大小向上取整后整个块都可以使用。
+        assert_eq!(allocator.alloc_by_layout(layout(40)), Some(a));
+    }
+
+    #[test_case]
+    fn realloc_grows_in_place() {
+        let mut allocator = allocator(FitStrategy::FirstFit);
+        let a = allocator.alloc_by_layout(layout(32)).unwrap();
+        unsafe {
+            assert!(allocator.realloc_in_place(a, layout(32), 256));
+            // 缩小后，尾部归还给链表，可以被下一次分配使用。
+            assert!(allocator.realloc_in_place(a, layout(256), 64));
+        }
+        assert_eq!(allocator.alloc_by_layout(layout(16)), Some(a + 64));
+        let mut stats = HeapStats::default();
+        allocator.fill_stats(&mut stats);
+        assert_eq!(stats.free_bytes, mem::size_of::<TestHeap>() - 64 - 16);
     }
 }
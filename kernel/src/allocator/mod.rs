@@ -1,14 +1,32 @@
 mod bump;
+mod debug;
+mod dispatch;
 pub mod fixed_size;
 mod linked_list;
+mod slab;
+mod stats;
 
-use core::alloc::GlobalAlloc;
+pub use dispatch::AllocatorKind;
+use dispatch::Heap;
+pub use linked_list::FitStrategy;
+pub use stats::HeapStats;
+use stats::Tracked;
+
+use core::{
+    alloc::GlobalAlloc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use x86_64::{
-    structures::paging::{mapper::MapToError, FrameAllocator, Mapper, PageTableFlags, Size4KiB},
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size2MiB,
+        Size4KiB,
+    },
     VirtAddr,
 };
 
+use crate::{cmdline, memory, println, serial_println};
+
 /// 测试接口。
 pub struct Dummy;
 
@@ -37,17 +55,13 @@ unsafe impl GlobalAlloc for Dummy {
 //         static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
 //     }
 // }
-#[cfg(allocator = "bump")]
-#[global_allocator]
-static ALLOCATOR: Locked<bump::BumpAllocator> = Locked::new(bump::BumpAllocator::new());
-#[cfg(allocator = "linked_list")]
+// 分配器外面包一层调试堆，调试堆默认不启用，见 enable_debug_heap。
 #[global_allocator]
-static ALLOCATOR: Locked<linked_list::LinkedListAllocator> =
-    Locked::new(linked_list::LinkedListAllocator::new());
-#[cfg(all(not(allocator = "linked_list"), not(allocator = "bump")))]
-#[global_allocator]
-static ALLOCATOR: Locked<fixed_size::FixedSizeBlockAllocator> =
-    Locked::new(fixed_size::FixedSizeBlockAllocator::new());
+static ALLOCATOR: Tracked<debug::DebugHeap<Heap>> =
+    Tracked::new(debug::DebugHeap::new(Heap::new()));
+
+/// 命令行中没有指定 allocator 时使用的分配器。
+const DEFAULT_ALLOCATOR: AllocatorKind = AllocatorKind::LinkedList;
 
 /// #[alloc_error_handler] 用于处理 alloc crate 的分配失败，当使用 extern crate alloc 时，必须由用户提供一个 alloc_error_handler。参数 layout 是传入 alloc 的 layout。
 #[alloc_error_handler]
@@ -57,40 +71,216 @@ fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
 
 /// 任意选择的堆起始地址（虚拟）和大小，只要不与内核代码重叠即可。
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// 初始化时映射的堆大小。之后堆空间不足时会按需扩展。
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// 默认的堆大小上限，可以通过 set_heap_limit 调整。
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+/// 每次扩展堆时至少映射的大小，避免频繁扩展。
+const HEAP_GROW_SIZE: usize = 64 * 1024; // 64 KiB
+/// slab 分配器专用的虚拟地址区域，每个 slab 单独映射一个页帧，变空时取消映射并归还页帧。
+pub const SLAB_AREA_START: usize = 0x_5000_0000_0000;
+/// slab 区域的结束地址（不含），共 64 MiB。
+pub const SLAB_AREA_END: usize = SLAB_AREA_START + 64 * 1024 * 1024;
+
+/// 堆当前已映射部分的结束地址。
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START);
+/// 堆大小上限。
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
 
+/// 初始化堆，使用的分配器由命令行中的 `allocator=<name>` 选择，
+/// linked_list 分配器查找空闲块的策略由 `heap_fit=first|best|next` 选择，指定 `heap_debug` 时启用调试堆。
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
-    let page_range = {
-        use x86_64::structures::paging::Page;
+    let kind = match cmdline::get("allocator") {
+        Some(name) => AllocatorKind::from_name(name).unwrap_or_else(|| {
+            println!(
+                "unknown allocator {:?}, using {}",
+                name,
+                DEFAULT_ALLOCATOR.name()
+            );
+            DEFAULT_ALLOCATOR
+        }),
+        None => DEFAULT_ALLOCATOR,
+    };
+    if let Some(name) = cmdline::get("heap_fit") {
+        match FitStrategy::from_name(name) {
+            Some(strategy) => set_fit_strategy(strategy),
+            None => println!("unknown heap_fit {:?}, using first", name),
+        }
+    }
+    if cmdline::get("heap_debug").is_some() {
+        enable_debug_heap();
+    }
+    init_heap_with(kind, mapper, frame_allocator)
+}
+
+/// 使用指定的分配器初始化堆。
+pub fn init_heap_with(
+    kind: AllocatorKind,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    map_heap_pages(mapper, frame_allocator, HEAP_START, HEAP_SIZE)?;
+    HEAP_END.store(HEAP_START + HEAP_SIZE, Ordering::Relaxed);
+    // 指定堆的起始地址和大小。堆是向上增长的。
+    unsafe { heap().init(kind, HEAP_START, HEAP_SIZE) };
+    Ok(())
+}
+
+/// 全局分配器中实际负责分配的部分。
+fn heap() -> &'static Heap {
+    &ALLOCATOR
+}
+
+/// 启用调试堆，检测越界写、重复释放、释放后写入等堆破坏问题。必须在初始化堆之前调用。
+pub fn enable_debug_heap() {
+    assert_eq!(
+        heap_size(),
+        0,
+        "debug heap must be enabled before the heap is initialized"
+    );
+    ALLOCATOR.enable();
+}
+
+/// 调试堆是否已启用。
+pub fn debug_heap_enabled() -> bool {
+    ALLOCATOR.is_enabled()
+}
 
+/// 设置 linked_list 分配器查找空闲块的策略，默认为 FirstFit。
+pub fn set_fit_strategy(strategy: FitStrategy) {
+    heap().set_fit_strategy(strategy);
+}
+
+/// 当前使用的分配器。
+pub fn allocator_kind() -> AllocatorKind {
+    heap().kind()
+}
+
+/// 堆中只有数据，不可执行。
+const HEAP_PAGE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// 为 [start, start + size) 分配页帧并映射。
+fn map_heap_pages(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    start: usize,
+    size: usize,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page_range = {
         // 返回包含指定虚拟地址的页。实际上就是做一个页对齐操作。
-        let heap_start_page = Page::containing_address(VirtAddr::new(HEAP_START as u64));
-        let heap_end_page =
-            Page::containing_address(VirtAddr::new((HEAP_START + HEAP_SIZE - 1) as u64));
+        let heap_start_page = Page::containing_address(VirtAddr::new(start as u64));
+        let heap_end_page = Page::containing_address(VirtAddr::new((start + size - 1) as u64));
         Page::range_inclusive(heap_start_page, heap_end_page)
     };
     for page in page_range {
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
         unsafe {
             mapper
-                .map_to(page, frame, flags, frame_allocator)? //
+                .map_to(page, frame, HEAP_PAGE_FLAGS, frame_allocator)? //
                 .flush(); // 刷新 TLB，使得新的映射生效。
         }
     }
-    unsafe {
-        ALLOCATOR
-            .lock() // 可能并发分配，所以需要加锁。
-            .init(HEAP_START, HEAP_SIZE); // 指定堆的起始地址和大小。堆是向上增长的。
-    }
     Ok(())
 }
 
+/// 堆空间不足时由分配器调用：从全局的页帧分配器申请新页，映射到堆的末尾。
+/// 返回实际扩展的字节数（可能小于 min_size），达到上限或者没有可用内存时返回 None。
+/// 调用时分配器的锁已被持有，所以这里不能进行堆分配。
+fn grow_heap(min_size: usize) -> Option<usize> {
+    let page_size = Size4KiB::SIZE as usize;
+    let end = HEAP_END.load(Ordering::Relaxed);
+    let limit = HEAP_START + HEAP_LIMIT.load(Ordering::Relaxed);
+    let size = align_up(min_size.max(HEAP_GROW_SIZE), page_size).min(limit.saturating_sub(end));
+    let mapped = memory::with_kernel_memory(|mem| {
+        // 逐页映射，这样即使中途物理内存耗尽，已经映射的部分也可以使用。
+        let mut mapped = 0;
+        while mapped < size {
+            let page_start = end + mapped;
+            // 大块的扩展在 2 MiB 边界上使用大页，减少页表和 TLB 的占用；没有连续的 2 MiB 物理内存时退回 4 KiB 页。
+            let huge_size = Size2MiB::SIZE as usize;
+            if page_start % huge_size == 0
+                && size - mapped >= huge_size
+                && memory::map_huge_pages(
+                    &mut mem.mapper,
+                    VirtAddr::new(page_start as u64),
+                    huge_size as u64,
+                    HEAP_PAGE_FLAGS,
+                    &mut mem.frame_allocator,
+                )
+                .is_ok()
+            {
+                mapped += huge_size;
+                continue;
+            }
+            if map_heap_pages(
+                &mut mem.mapper,
+                &mut mem.frame_allocator,
+                page_start,
+                page_size,
+            )
+            .is_err()
+            {
+                break;
+            }
+            mapped += page_size;
+        }
+        mapped
+    })?;
+    if mapped == 0 {
+        return None;
+    }
+    HEAP_END.store(end + mapped, Ordering::Relaxed);
+    Some(mapped)
+}
+
+/// 设置堆大小的上限，已经映射的部分不受影响。
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(align_up(limit, Size4KiB::SIZE as usize), Ordering::Relaxed);
+}
+
+/// 堆当前已映射的大小。
+pub fn heap_size() -> usize {
+    HEAP_END.load(Ordering::Relaxed) - HEAP_START
+}
+
+/// 获取当前的堆统计信息。
+pub fn stats() -> HeapStats {
+    let mut stats = HeapStats {
+        heap_size: heap_size(),
+        ..HeapStats::default()
+    };
+    ALLOCATOR.fill_stats(&mut stats);
+    // 持有分配器锁时不能被中断，否则中断处理中的分配会死锁。
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        heap().fill_stats(&mut stats);
+    });
+    stats
+}
+
+/// 通过串口输出堆统计信息，用于调试。
+pub fn dump_meminfo() {
+    let stats = stats();
+    serial_println!("Allocator:      {:>10}", allocator_kind().name());
+    serial_println!("HeapSize:       {:>10} B", stats.heap_size);
+    serial_println!("InUse:          {:>10} B", stats.bytes_in_use);
+    serial_println!("PeakInUse:      {:>10} B", stats.peak_bytes_in_use);
+    serial_println!("Allocations:    {:>10}", stats.allocations);
+    serial_println!("Deallocations:  {:>10}", stats.deallocations);
+    serial_println!("Free:           {:>10} B", stats.free_bytes);
+    serial_println!("LargestFree:    {:>10} B", stats.largest_free_region);
+    serial_println!("Fragmentation:  {:>10} %", stats.fragmentation());
+    for &(size, count) in &stats.free_blocks[..stats.size_classes] {
+        serial_println!("FreeBlocks[{:>4}]: {:>8}", size, count);
+    }
+}
+
 pub struct Locked<T> {
     inner: spin::Mutex<T>,
 }
@@ -0,0 +1,359 @@
+//! Slab 分配器：每个尺寸类别维护一个 slab 缓存，每个 slab 是一个页大小（4 KiB）、按页对齐的内存块，头部存放
+//! slab 的元数据，其余空间被切分为相同大小的对象，空闲对象组成 slab 内部的链表。
+//! 分配时：从对应缓存中取一个有空闲对象的 slab，弹出一个对象；没有这样的 slab 时申请新的 slab。
+//! 释放时：对象地址按页对齐即可找到所属 slab，把对象放回 slab 的空闲链表；slab 完全空闲时释放。
+//! 每个 slab 是 [SLAB_AREA_START, SLAB_AREA_END) 中单独映射的一页，释放时取消映射并把页帧还给页帧分配器。
+//! 全局页帧分配器可用之前（init_global 之前）或者 slab 区域用完时，slab 从堆中分配，释放时还给堆。
+//! 超出最大尺寸类别的分配直接交给堆，堆使用 LinkedListAllocator。
+//! 优点：
+//! 1. 与固定块分配器一样，分配和释放都只需要操作链表头部。
+//! 2. 空闲的 slab 会被回收，不会出现固定块分配器中某个尺寸的块只增不减的问题。
+//! 3. 初始化时为每个尺寸类别预先准备一个 slab，早期分配更快。
+//! 缺点：
+//! 1. 每个 slab 有头部开销，且对象按尺寸对齐，仍然有内碎片。
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, ptr,
+};
+
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, Size4KiB},
+    VirtAddr,
+};
+
+use super::{
+    linked_list::LinkedListAllocator, HeapStats, Locked, HEAP_PAGE_FLAGS, SLAB_AREA_END,
+    SLAB_AREA_START,
+};
+use crate::memory;
+
+/// 每个 slab 的大小，也是 slab 的对齐要求。
+const SLAB_SIZE: usize = 4096;
+/// 尺寸类别。更大的对象每个 slab 放不下几个，直接交给堆。
+const SLAB_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024];
+/// 每个缓存最多保留的空闲 slab 数，避免在边界上反复申请和归还。
+const MAX_EMPTY_SLABS: usize = 1;
+/// slab 区域能容纳的 slab 数。
+const AREA_SLABS: usize = (SLAB_AREA_END - SLAB_AREA_START) / SLAB_SIZE;
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// slab 头部，位于 slab 的起始处。
+struct Slab {
+    /// slab 内的空闲对象链表。
+    free: *mut FreeObject,
+    /// 已分配出去的对象数。
+    in_use: usize,
+    /// slab 能容纳的对象数。
+    capacity: usize,
+    /// 缓存中有空闲对象的 slab 组成双向链表，使得 slab 变空时可以直接摘除。
+    prev: *mut Slab,
+    next: *mut Slab,
+}
+
+struct SlabCache {
+    object_size: usize,
+    /// 有空闲对象（部分使用或者完全空闲）的 slab 链表。用满的 slab 不在链表中。
+    partial: *mut Slab,
+    /// partial 中完全空闲的 slab 数。
+    empty_slabs: usize,
+}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            partial: ptr::null_mut(),
+            empty_slabs: 0,
+        }
+    }
+
+    /// 第一个对象的偏移：头部之后，按对象大小对齐。
+    fn objects_offset(&self) -> usize {
+        super::align_up(mem::size_of::<Slab>(), self.object_size)
+    }
+
+    /// 申请一个新的 slab，并切分为对象。
+    fn grow(&mut self, pages: &mut Pages) -> Option<()> {
+        let slab = pages.alloc_slab()?;
+        let offset = self.objects_offset();
+        let capacity = (SLAB_SIZE - offset) / self.object_size;
+        let mut free = ptr::null_mut();
+        // 倒序串起来，使得链表头是地址最低的对象。
+        for i in (0..capacity).rev() {
+            let object = (slab as usize + offset + i * self.object_size) as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = object;
+        }
+        unsafe {
+            slab.write(Slab {
+                free,
+                in_use: 0,
+                capacity,
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+            });
+        }
+        self.push(slab);
+        self.empty_slabs += 1;
+        Some(())
+    }
+
+    fn alloc(&mut self, pages: &mut Pages) -> Option<ptr::NonNull<u8>> {
+        if self.partial.is_null() {
+            self.grow(pages)?;
+        }
+        let slab = unsafe { &mut *self.partial };
+        let object = slab.free;
+        slab.free = unsafe { (*object).next };
+        if slab.in_use == 0 {
+            self.empty_slabs -= 1;
+        }
+        slab.in_use += 1;
+        if slab.free.is_null() {
+            // 用满了，从 partial 中移除。
+            self.remove(slab);
+        }
+        ptr::NonNull::new(object as *mut u8)
+    }
+
+    /// # Safety
+    /// addr 必须是该缓存分配出去的对象。
+    unsafe fn dealloc(&mut self, addr: *mut u8, pages: &mut Pages) {
+        // slab 按 SLAB_SIZE 对齐，所以对象地址向下对齐就是 slab 头部。
+        let slab = (addr as usize & !(SLAB_SIZE - 1)) as *mut Slab;
+        let object = addr as *mut FreeObject;
+        object.write(FreeObject { next: (*slab).free });
+        (*slab).free = object;
+        if (*slab).in_use == (*slab).capacity {
+            // 之前是满的，重新放回 partial。
+            self.push(slab);
+        }
+        (*slab).in_use -= 1;
+        if (*slab).in_use == 0 {
+            if self.empty_slabs < MAX_EMPTY_SLABS {
+                self.empty_slabs += 1;
+            } else {
+                // 完全空闲，释放整个 slab。
+                self.remove(slab);
+                pages.free_slab(slab);
+            }
+        }
+    }
+
+    /// 遍历 partial 链表，统计空闲对象数。用满的 slab 不在链表中，也没有空闲对象。
+    fn free_objects(&self) -> usize {
+        let mut objects = 0;
+        let mut slab = self.partial;
+        while !slab.is_null() {
+            unsafe {
+                objects += (*slab).capacity - (*slab).in_use;
+                slab = (*slab).next;
+            }
+        }
+        objects
+    }
+
+    fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = ptr::null_mut();
+            (*slab).next = self.partial;
+            if !self.partial.is_null() {
+                (*self.partial).prev = slab;
+            }
+        }
+        self.partial = slab;
+    }
+
+    fn remove(&mut self, slab: *mut Slab) {
+        unsafe {
+            let (prev, next) = ((*slab).prev, (*slab).next);
+            if prev.is_null() {
+                self.partial = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+    }
+}
+
+/// slab 所用的页。
+struct Pages {
+    /// slab 区域中每一页是否已映射，每一位对应一页。
+    used: [u64; AREA_SLABS / 64],
+    /// 堆。超出尺寸类别的大对象从这里分配，slab 区域不可用时 slab 也从这里分配。
+    heap: LinkedListAllocator,
+}
+
+impl Pages {
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    fn alloc_slab(&mut self) -> Option<*mut Slab> {
+        match self.map_slab() {
+            Some(addr) => Some(addr as *mut Slab),
+            None => self
+                .heap
+                .alloc_by_layout(Self::slab_layout())
+                .map(|addr| addr as *mut Slab),
+        }
+    }
+
+    /// 在 slab 区域中找一个空闲的页，分配页帧并映射。全局页帧分配器还不可用、区域已满或者没有可用的页帧时返回 None。
+    /// 调用时分配器的锁已被持有，这里不能进行堆分配。
+    fn map_slab(&mut self) -> Option<usize> {
+        let (word, bits) = self
+            .used
+            .iter_mut()
+            .enumerate()
+            .find(|(_, w)| **w != u64::MAX)?;
+        let bit = bits.trailing_ones() as usize;
+        let addr = SLAB_AREA_START + (word * 64 + bit) * SLAB_SIZE;
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr as u64));
+        memory::with_kernel_memory(|mem| {
+            let frame = mem.frame_allocator.allocate_frame()?;
+            let result = unsafe {
+                mem.mapper
+                    .map_to(page, frame, HEAP_PAGE_FLAGS, &mut mem.frame_allocator)
+            };
+            match result {
+                Ok(flush) => {
+                    flush.flush();
+                    Some(())
+                }
+                Err(_) => {
+                    unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                    None
+                }
+            }
+        })??;
+        *bits |= 1 << bit;
+        Some(addr)
+    }
+
+    /// # Safety
+    /// slab 必须是 alloc_slab 返回的、不再使用的 slab。
+    unsafe fn free_slab(&mut self, slab: *mut Slab) {
+        let addr = slab as usize;
+        if !(SLAB_AREA_START..SLAB_AREA_END).contains(&addr) {
+            self.heap.dealloc_by_layout(addr, Self::slab_layout());
+            return;
+        }
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr as u64));
+        memory::with_kernel_memory(|mem| {
+            if let Ok((frame, flush)) = mem.mapper.unmap(page) {
+                flush.ignore();
+                unsafe { mem.frame_allocator.deallocate_frame(frame) };
+            }
+        });
+        // 其他地址空间（PCID）的 TLB 中可能还缓存着这一页。
+        memory::flush_all();
+        let index = (addr - SLAB_AREA_START) / SLAB_SIZE;
+        self.used[index / 64] &= !(1 << (index % 64));
+    }
+}
+
+pub struct SlabAllocator {
+    caches: [SlabCache; SLAB_SIZES.len()],
+    pages: Pages,
+}
+
+// slab 链表使用裸指针，这些内存只会在持有锁时访问，所以可以在线程间传递。
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: SlabCache = SlabCache::new(0);
+        let mut caches = [EMPTY; SLAB_SIZES.len()];
+        let mut i = 0;
+        while i < SLAB_SIZES.len() {
+            caches[i] = SlabCache::new(SLAB_SIZES[i]);
+            i += 1;
+        }
+        Self {
+            caches,
+            pages: Pages {
+                used: [0; AREA_SLABS / 64],
+                heap: LinkedListAllocator::new(),
+            },
+        }
+    }
+
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.pages.heap.init(heap_start, heap_size);
+        // 预先为每个尺寸类别准备一个 slab。
+        for cache in self.caches.iter_mut() {
+            if cache.grow(&mut self.pages).is_none() {
+                break;
+            }
+        }
+    }
+
+    /// 堆的末尾新映射了 by 字节。
+    pub unsafe fn extend(&mut self, by: usize) {
+        self.pages.heap.extend(by);
+    }
+
+    /// 统计每个缓存的空闲对象数，以及堆的空闲情况。
+    pub fn fill_stats(&self, stats: &mut HeapStats) {
+        for (index, cache) in self.caches.iter().enumerate() {
+            let objects = cache.free_objects();
+            stats.free_blocks[index] = (cache.object_size, objects);
+            stats.free_bytes += objects * cache.object_size;
+        }
+        stats.size_classes = SLAB_SIZES.len();
+        self.pages.heap.fill_stats(stats);
+    }
+
+    /// 确定能容纳 `layout` 的最小尺寸类别。
+    fn cache_index(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SLAB_SIZES.iter().position(|&s| s >= size)
+    }
+
+    fn do_alloc(&mut self, layout: Layout) -> Option<ptr::NonNull<u8>> {
+        match Self::cache_index(layout) {
+            Some(index) => self.caches[index].alloc(&mut self.pages),
+            None => self
+                .pages
+                .heap
+                .alloc_by_layout(layout)
+                .and_then(|addr| ptr::NonNull::new(addr as *mut u8)),
+        }
+    }
+
+    unsafe fn do_dealloc(&mut self, addr: *mut u8, layout: Layout) {
+        match Self::cache_index(layout) {
+            Some(index) => self.caches[index].dealloc(addr, &mut self.pages),
+            None => self.pages.heap.dealloc_by_layout(addr as usize, layout),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        if let Some(ptr) = allocator.do_alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // 没有可用的页帧时 slab 也从堆中分配，扩展堆后重试。申请新 slab 时需要一整页。
+        match super::grow_heap(layout.size().max(SLAB_SIZE) + layout.align().max(SLAB_SIZE)) {
+            Some(by) => allocator.extend(by),
+            None => return ptr::null_mut(),
+        }
+        allocator
+            .do_alloc(layout)
+            .map_or(ptr::null_mut(), |x| x.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().do_dealloc(ptr, layout)
+    }
+}
@@ -0,0 +1,121 @@
+//! 堆统计：Tracked 包装实际的全局分配器，记录分配/释放次数和使用量；各分配器再通过 fill_stats 补充
+//! 空闲链表、最大空闲块等与实现相关的信息。
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::fixed_size::BLOCK_SIZES;
+
+/// 尺寸类别数量的上限，fixed_size 和 slab 分配器的尺寸类别都不超过这个数。
+pub const MAX_SIZE_CLASSES: usize = BLOCK_SIZES.len();
+
+/// 堆的统计信息。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    /// 堆当前已映射的大小。
+    pub heap_size: usize,
+    /// 已分配出去的字节数（按请求的 Layout 大小计算）。
+    pub bytes_in_use: usize,
+    /// bytes_in_use 的历史最大值。
+    pub peak_bytes_in_use: usize,
+    /// 累计分配次数。
+    pub allocations: usize,
+    /// 累计释放次数。
+    pub deallocations: usize,
+    /// 分配器中空闲的字节数，包括缓存在各尺寸类别链表中的空闲块。
+    pub free_bytes: usize,
+    /// 最大的连续空闲区域，超过它的分配需要扩展堆。
+    pub largest_free_region: usize,
+    /// 每个尺寸类别的 (块大小, 空闲块数)，只有前 size_classes 项有效。
+    pub free_blocks: [(usize, usize); MAX_SIZE_CLASSES],
+    /// 尺寸类别数，没有尺寸类别的分配器（bump、linked_list）为 0。
+    pub size_classes: usize,
+}
+
+impl HeapStats {
+    /// 内碎片之外，空闲内存中无法满足大块分配的比例（百分比）。
+    pub fn fragmentation(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        100 - self.largest_free_region * 100 / self.free_bytes
+    }
+}
+
+/// 包装全局分配器，统计分配次数和使用量。通过 Deref 访问内部分配器。
+pub struct Tracked<A> {
+    inner: A,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    bytes_in_use: AtomicUsize,
+    peak_bytes_in_use: AtomicUsize,
+}
+
+impl<A> Tracked<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            bytes_in_use: AtomicUsize::new(0),
+            peak_bytes_in_use: AtomicUsize::new(0),
+        }
+    }
+
+    /// 将计数类的统计写入 stats。
+    pub fn fill_stats(&self, stats: &mut HeapStats) {
+        stats.allocations = self.allocations.load(Ordering::Relaxed);
+        stats.deallocations = self.deallocations.load(Ordering::Relaxed);
+        stats.bytes_in_use = self.bytes_in_use.load(Ordering::Relaxed);
+        stats.peak_bytes_in_use = self.peak_bytes_in_use.load(Ordering::Relaxed);
+    }
+}
+
+impl<A> Deref for Tracked<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.inner
+    }
+}
+
+// 各分配器的锁都不关中断，这里在关闭中断时调用它们，中断处理函数（例如通过 Spawner 创建任务）也可以分配内存。
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tracked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = without_interrupts(|| self.inner.alloc(layout));
+        if !ptr.is_null() {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            let in_use = self
+                .bytes_in_use
+                .fetch_add(layout.size(), Ordering::Relaxed);
+            self.peak_bytes_in_use
+                .fetch_max(in_use + layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.inner.dealloc(ptr, layout));
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in_use
+            .fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    /// 必须转发给内部分配器，否则默认实现会绕过它的原地扩展。
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = without_interrupts(|| self.inner.realloc(ptr, layout, new_size));
+        if !new_ptr.is_null() {
+            let in_use = self.bytes_in_use.fetch_add(new_size, Ordering::Relaxed) + new_size;
+            self.bytes_in_use
+                .fetch_sub(layout.size(), Ordering::Relaxed);
+            self.peak_bytes_in_use
+                .fetch_max(in_use - layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
@@ -0,0 +1,144 @@
+//! 内核栈回溯。
+//!
+//! 内核以 frame-pointer = always 编译（见 targets/x86_64-myos.json），每个函数的栈帧都以 rbp 链接起来：
+//! [rbp] 是调用者的 rbp，[rbp + 8] 是返回地址。沿着这条链就能找到每一层调用的返回地址，再用构建时嵌入的符号表
+//! 解析成 函数名+偏移。
+//!
+//! 回溯在 panic 和异常中使用，栈可能已经损坏，所以每次读取之前都通过页表确认地址已经映射；页表要在
+//! memory::init 之后才能访问，在此之前无法回溯。
+
+mod symbols;
+
+pub use symbols::{is_available, lookup, Symbol};
+
+use core::{arch::asm, fmt};
+
+use x86_64::VirtAddr;
+
+use crate::{
+    memory::{self, KernelSection},
+    print, serial_print,
+};
+
+/// 最多回溯的栈帧数量，防止损坏的 rbp 链形成环。
+pub const MAX_FRAMES: usize = 32;
+
+/// 一层调用。
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// 返回地址，即调用指令的下一条指令。
+    pub return_address: VirtAddr,
+    /// 这一层栈帧的 rbp。
+    pub frame_pointer: VirtAddr,
+}
+
+impl Frame {
+    /// 调用指令所在的函数。用返回地址减 1 查找，否则调用不返回的函数（例如 panic）时，返回地址可能已经越过了
+    /// 函数的末尾。
+    pub fn symbol(&self) -> Option<Symbol> {
+        let symbol = lookup(self.return_address - 1u64)?;
+        Some(Symbol {
+            offset: symbol.offset + 1,
+            ..symbol
+        })
+    }
+}
+
+/// 沿着 rbp 链遍历栈帧的迭代器。
+pub struct Frames {
+    frame_pointer: u64,
+    remaining: usize,
+}
+
+/// 从当前位置开始回溯，第一帧是调用 capture 的函数的调用者。
+#[inline(never)]
+pub fn capture() -> Frames {
+    let frame_pointer: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack)) };
+    // capture 返回后它的栈帧就失效了，所以从调用者的栈帧开始。
+    Frames::from_frame_pointer(unsafe { *(frame_pointer as *const u64) })
+}
+
+impl Frames {
+    /// 从 frame_pointer 指向的栈帧开始回溯。
+    pub fn from_frame_pointer(frame_pointer: u64) -> Frames {
+        Frames {
+            frame_pointer,
+            remaining: MAX_FRAMES,
+        }
+    }
+}
+
+/// [addr, addr + 16) 是否可以读取。
+fn is_readable(addr: u64) -> bool {
+    let offset = memory::phys_offset();
+    offset != 0
+        && addr % 8 == 0
+        && [addr, addr.wrapping_add(8)].into_iter().all(|addr| {
+            VirtAddr::try_new(addr)
+                .map_or(false, |addr| memory::translate_addr(addr, offset).is_some())
+        })
+}
+
+impl Iterator for Frames {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        while self.remaining > 0 && self.frame_pointer != 0 && is_readable(self.frame_pointer) {
+            self.remaining -= 1;
+            let frame_pointer = self.frame_pointer;
+            let (caller, return_address) = unsafe {
+                let ptr = frame_pointer as *const u64;
+                (ptr.read(), ptr.add(1).read())
+            };
+            self.frame_pointer = caller;
+            // 异常处理函数的 [rbp + 8] 可能是 CPU 压入的错误码而不是返回地址，跳过不在内核代码中的地址。
+            let (start, end) = KernelSection::Text.range();
+            if (start.as_u64()..end.as_u64()).contains(&return_address) {
+                return Some(Frame {
+                    return_address: VirtAddr::new(return_address),
+                    frame_pointer: VirtAddr::new(frame_pointer),
+                });
+            }
+        }
+        None
+    }
+}
+
+/// 同时输出到 VGA 和串口。
+fn emit(args: fmt::Arguments) {
+    print!("{}", args);
+    serial_print!("{}", args);
+}
+
+/// 输出当前调用栈。
+pub fn print() {
+    print_frames(capture());
+}
+
+/// 输出 frames 中的各层调用，每层一行：序号、返回地址和 函数名+偏移。
+pub fn print_frames(frames: Frames) {
+    emit(format_args!("Backtrace:\n"));
+    if memory::phys_offset() == 0 {
+        emit(format_args!("  <unavailable before memory::init>\n"));
+        return;
+    }
+    if !is_available() {
+        emit(format_args!("  <no symbol table, see tools/ksymtab>\n"));
+    }
+    for (i, frame) in frames.enumerate() {
+        match frame.symbol() {
+            Some(symbol) => emit(format_args!(
+                "  #{:<2} {:#018x} {}\n",
+                i,
+                frame.return_address.as_u64(),
+                symbol
+            )),
+            None => emit(format_args!(
+                "  #{:<2} {:#018x} ??\n",
+                i,
+                frame.return_address.as_u64()
+            )),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! 内核符号表。
+//!
+//! 链接脚本在 .rodata 中预留了 __ksymtab_start..__ksymtab_end，链接之后由 tools/ksymtab 把内核 ELF 中的函数符号
+//! 写进去（cargo run / cargo test 的 runner 会自动执行）。格式：
+//!
+//! - 表头：魔数 `KSYMTAB\0`，符号数量（u64）；
+//! - 按地址排序的符号：起始地址（u64）、大小（u32）、名字在字符串区中的偏移（u32）；
+//! - 字符串区：去掉哈希后的函数名，以 0 结尾。
+//!
+//! 没有经过 ksymtab 处理的镜像（例如直接 cargo bootimage）中这块区域全是 0，此时查不到任何符号。
+
+use core::{fmt, mem::size_of, ptr::addr_of, slice};
+
+use x86_64::VirtAddr;
+
+const MAGIC: [u8; 8] = *b"KSYMTAB\0";
+
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    count: u64,
+}
+
+#[repr(C)]
+struct RawSymbol {
+    addr: u64,
+    size: u32,
+    name: u32,
+}
+
+extern "C" {
+    static __ksymtab_start: u8;
+    static __ksymtab_end: u8;
+}
+
+/// 地址所在的函数。
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// 函数的起始地址。
+    pub start: VirtAddr,
+    /// 地址相对于函数起始地址的偏移。
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// 预留区域中的符号和字符串区，没有符号表时返回 None。
+fn table() -> Option<(&'static [RawSymbol], &'static [u8])> {
+    #[allow(unused_unsafe)]
+    let (start, end) = unsafe { (addr_of!(__ksymtab_start), addr_of!(__ksymtab_end)) };
+    let bytes = unsafe { slice::from_raw_parts(start, end as usize - start as usize) };
+    let header = unsafe { &*(start as *const Header) };
+    if header.magic != MAGIC {
+        return None;
+    }
+    let names = size_of::<Header>() + header.count as usize * size_of::<RawSymbol>();
+    if names > bytes.len() {
+        return None;
+    }
+    let symbols = unsafe {
+        slice::from_raw_parts(
+            start.add(size_of::<Header>()) as *const RawSymbol,
+            header.count as usize,
+        )
+    };
+    Some((symbols, &bytes[names..]))
+}
+
+/// 镜像中是否嵌入了符号表。
+pub fn is_available() -> bool {
+    table().is_some()
+}
+
+/// 字符串区中 offset 处以 0 结尾的名字。
+fn name_at(names: &'static [u8], offset: u32) -> &'static str {
+    let name = names.get(offset as usize..).unwrap_or(&[]);
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    core::str::from_utf8(&name[..len]).unwrap_or("<invalid symbol name>")
+}
+
+/// 查找包含 addr 的函数。
+pub fn lookup(addr: VirtAddr) -> Option<Symbol> {
+    let (symbols, names) = table()?;
+    let addr = addr.as_u64();
+    // 最后一个起始地址不大于 addr 的符号。
+    let index = symbols.partition_point(|symbol| symbol.addr <= addr);
+    let symbol = symbols.get(index.checked_sub(1)?)?;
+    // 大小为 0 的符号（例如汇编中定义的）认为一直延伸到下一个符号。
+    if symbol.size != 0 && addr >= symbol.addr + symbol.size as u64 {
+        return None;
+    }
+    Some(Symbol {
+        name: name_at(names, symbol.name),
+        start: VirtAddr::new(symbol.addr),
+        offset: addr - symbol.addr,
+    })
+}
@@ -0,0 +1,122 @@
+//! 内核命令行，格式为空格分隔的 `key=value` 或 `flag`。
+//! bootloader 0.9 不支持传递命令行，所以通过 QEMU 的 fw_cfg 设备读取名为 `opt/kernel/cmdline` 的文件：
+//! ```bash
+//! cargo run -- -fw_cfg name=opt/kernel/cmdline,string="allocator=bump"
+//! ```
+//! 不在 QEMU 中运行或没有提供该文件时，命令行为空。
+//! 命令行在初始化堆之前就需要读取，所以保存在固定大小的静态缓冲区中，不进行堆分配。
+
+use spin::Once;
+use x86_64::instructions::port::Port;
+
+/// fw_cfg 的选择端口（写入 16 位的 key）和数据端口（逐字节读取）。
+const FW_CFG_SELECTOR: u16 = 0x510;
+const FW_CFG_DATA: u16 = 0x511;
+const FW_CFG_SIGNATURE: u16 = 0x0000;
+/// 文件目录的 key，其中列出了所有 `-fw_cfg name=...` 提供的文件。
+const FW_CFG_FILE_DIR: u16 = 0x0019;
+const FW_CFG_FILE_NAME_LEN: usize = 56;
+const CMDLINE_FILE: &[u8] = b"opt/kernel/cmdline";
+/// 命令行的最大长度，超出的部分被截断。
+const CMDLINE_MAX_LEN: usize = 256;
+
+struct Cmdline {
+    buf: [u8; CMDLINE_MAX_LEN],
+    len: usize,
+}
+
+static CMDLINE: Once<Cmdline> = Once::new();
+
+/// 完整的命令行。第一次调用时从 fw_cfg 读取。
+pub fn as_str() -> &'static str {
+    let cmdline = CMDLINE.call_once(read_cmdline);
+    core::str::from_utf8(&cmdline.buf[..cmdline.len]).unwrap_or("")
+}
+
+/// 返回 `key=value` 中的 value；只有 `key` 时返回空字符串；不存在时返回 None。
+pub fn get(key: &str) -> Option<&'static str> {
+    as_str().split_ascii_whitespace().find_map(|arg| {
+        let (name, value) = arg.split_once('=').unwrap_or((arg, ""));
+        (name == key).then_some(value)
+    })
+}
+
+fn read_cmdline() -> Cmdline {
+    let mut cmdline = Cmdline {
+        buf: [0; CMDLINE_MAX_LEN],
+        len: 0,
+    };
+    let mut fw_cfg = FwCfg::new();
+    if let Some((select, size)) = fw_cfg.find_file(CMDLINE_FILE) {
+        cmdline.len = size.min(CMDLINE_MAX_LEN);
+        fw_cfg.select(select);
+        fw_cfg.read(&mut cmdline.buf[..cmdline.len]);
+        // string= 提供的内容可能以 '\0' 结尾。
+        while cmdline.len > 0 && cmdline.buf[cmdline.len - 1] == 0 {
+            cmdline.len -= 1;
+        }
+    }
+    cmdline
+}
+
+/// QEMU fw_cfg 设备的端口访问接口，参考 https://www.qemu.org/docs/master/specs/fw_cfg.html
+struct FwCfg {
+    selector: Port<u16>,
+    data: Port<u8>,
+}
+
+impl FwCfg {
+    fn new() -> Self {
+        Self {
+            selector: Port::new(FW_CFG_SELECTOR),
+            data: Port::new(FW_CFG_DATA),
+        }
+    }
+
+    fn select(&mut self, key: u16) {
+        unsafe { self.selector.write(key) }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = unsafe { self.data.read() };
+        }
+    }
+
+    /// 目录中的整数都是大端序。
+    fn read_be_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.read(&mut bytes);
+        u32::from_be_bytes(bytes)
+    }
+
+    /// 在文件目录中查找文件，返回它的 key 和大小。没有 fw_cfg 设备时读到的签名不是 "QEMU"。
+    fn find_file(&mut self, name: &[u8]) -> Option<(u16, usize)> {
+        let mut signature = [0; 4];
+        self.select(FW_CFG_SIGNATURE);
+        self.read(&mut signature);
+        if &signature != b"QEMU" {
+            return None;
+        }
+        self.select(FW_CFG_FILE_DIR);
+        let count = self.read_be_u32();
+        for _ in 0..count {
+            // 每一项为：size: u32, select: u16, reserved: u16, name: [u8; 56]。
+            let size = self.read_be_u32() as usize;
+            let mut select = [0; 2];
+            self.read(&mut select);
+            let mut reserved = [0; 2];
+            self.read(&mut reserved);
+            let mut file_name = [0; FW_CFG_FILE_NAME_LEN];
+            self.read(&mut file_name);
+            let len = file_name
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(file_name.len());
+            if &file_name[..len] == name {
+                return Some((u16::from_be_bytes(select), size));
+            }
+        }
+        None
+    }
+}
@@ -1,5 +1,7 @@
+use core::ptr::{addr_of, addr_of_mut};
+
 use x86_64::{
-    instructions::tables::load_tss,
+    instructions::{interrupts::without_interrupts, tables::load_tss},
     registers::segmentation::{Segment, CS},
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable},
@@ -8,38 +10,26 @@ use x86_64::{
     VirtAddr,
 };
 
+use crate::memory::KernelStack;
+
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static::lazy_static! {
-    // TSS: 在32位模式下，TSS的作用是存储一些用于上下切换的零碎信息，如处理器的寄存器状态等。
-    // 而在 64 位模式下，由于信息多，上下文切换已经不能使用硬件完成，所以 TSS 在64位模式已经没啥用了。
-    pub static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        // ist 是一个数组，每个元素都是一个栈指针，用于处理特定的中断
-        // ist 的作用是防止内核栈溢出时中断处理无法正常工作。比如，在内核中发生中断时，如果不进行栈切换，则一直在内核栈上递归触发中断的话，可能造成内核栈溢出，这是不可恢复的。所以 IST 实际上是定义了几个确定可用(预留)的栈供中断使用，当发生中断时，CPU 会自动切换到这些栈上，在进行中断处理程序调用，从而避免内核栈溢出。
-
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            // 由于目前还不能分配空间，所以先用一个固定的栈
-            const STACK_SIZE: usize = 4096*5;
-            // 使用 mut 是为了让 bootloader 将它放到非只读空间
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            // 栈：从高地址向低地址增长
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
+/// double fault 使用的 IST 栈的页数。
+const DOUBLE_FAULT_STACK_PAGES: usize = 5;
 
-    };
+// TSS: 在32位模式下，TSS的作用是存储一些用于上下切换的零碎信息，如处理器的寄存器状态等。
+// 而在 64 位模式下，由于信息多，上下文切换已经不能使用硬件完成，所以 TSS 在64位模式已经没啥用了。
+// CPU 在发生中断时才读取 TSS 中的栈指针，所以加载之后仍然可以通过 set_interrupt_stack 修改。
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
+lazy_static::lazy_static! {
     /// GDT: 全局描述符表，用于存储内核的段描述符。X86 CPU 由于兼容历史的原因，仍然是以段方式进行内存访问。
     /// 在页模式成为标准前，主要使用 GDT 来进行段访问控制。
     /// 所以当前使用 GDT 来进行对 TSS 栈进行加载
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
         (gdt, Selectors { code_selector, tss_selector })
     };
 }
@@ -50,6 +40,20 @@ struct Selectors {
 }
 
 pub fn init() {
+    // ist 是一个数组，每个元素都是一个栈指针，用于处理特定的中断
+    // ist 的作用是防止内核栈溢出时中断处理无法正常工作。比如，在内核中发生中断时，如果不进行栈切换，则一直在内核栈上递归触发中断的话，可能造成内核栈溢出，这是不可恢复的。所以 IST 实际上是定义了几个确定可用(预留)的栈供中断使用，当发生中断时，CPU 会自动切换到这些栈上，在进行中断处理程序调用，从而避免内核栈溢出。
+    set_interrupt_stack(DOUBLE_FAULT_IST_INDEX, {
+        // 此时还不能分配内存，所以先用一个固定的栈，内存初始化之后由 init_interrupt_stacks 换成带保护页的栈。
+        const STACK_SIZE: usize = 4096 * DOUBLE_FAULT_STACK_PAGES;
+        // 使用 mut 是为了让 bootloader 将它放到非只读空间
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        // 栈：从高地址向低地址增长
+        // 较新的编译器中取 static mut 的地址不再需要 unsafe。
+        #[allow(unused_unsafe)]
+        let stack_start = VirtAddr::from_ptr(unsafe { addr_of!(STACK) });
+        stack_start + STACK_SIZE
+    });
     // lgdt 指令
     GDT.0.load();
     unsafe {
@@ -57,3 +61,17 @@ pub fn init() {
         load_tss(GDT.1.tss_selector);
     }
 }
+
+/// 设置 IST 中第 index 个栈的栈顶。
+pub fn set_interrupt_stack(index: u16, stack_top: VirtAddr) {
+    without_interrupts(|| unsafe {
+        (*addr_of_mut!(TSS)).interrupt_stack_table[index as usize] = stack_top;
+    });
+}
+
+/// 为 IST 分配带保护页的内核栈，替换启动时使用的静态栈。需要在 memory::init_global 之后调用。
+pub fn init_interrupt_stacks() {
+    let stack = KernelStack::new(DOUBLE_FAULT_STACK_PAGES)
+        .expect("failed to allocate the double fault stack");
+    set_interrupt_stack(DOUBLE_FAULT_IST_INDEX, stack.leak());
+}
@@ -0,0 +1,253 @@
+//! Local APIC 和 I/O APIC。
+//!
+//! 每个处理器有一个 Local APIC，负责接收中断、向处理器投递，以及处理器自己的定时器和 EOI；I/O APIC 接收外部设备
+//! 的中断（全局中断号 GSI），按重定向表把它们转发到指定处理器的 Local APIC。两者的寄存器都是内存映射的，
+//! 用 ioremap 以不缓存的方式映射。
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts::without_interrupts, registers::model_specific::Msr, PhysAddr,
+    VirtAddr,
+};
+
+use crate::{
+    acpi::{IoApicInfo, Polarity, TriggerMode, MAX_IO_APICS},
+    memory::{self, CacheMode, VmallocError},
+};
+
+/// IA32_APIC_BASE 寄存器，第 11 位是 APIC 的全局使能位。
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// Local APIC 寄存器的偏移。
+mod reg {
+    pub const ID: usize = 0x20;
+    pub const TASK_PRIORITY: usize = 0x80;
+    pub const EOI: usize = 0xb0;
+    pub const SPURIOUS: usize = 0xf0;
+    /// 正在服务的中断（ISR），8 个 32 位寄存器，间隔 0x10。
+    pub const IN_SERVICE: usize = 0x100;
+    pub const LVT_TIMER: usize = 0x320;
+    pub const LVT_LINT0: usize = 0x350;
+    pub const LVT_LINT1: usize = 0x360;
+    pub const LVT_ERROR: usize = 0x370;
+    pub const TIMER_INITIAL_COUNT: usize = 0x380;
+    pub const TIMER_CURRENT_COUNT: usize = 0x390;
+    pub const TIMER_DIVIDE: usize = 0x3e0;
+}
+
+/// LVT 中的屏蔽位。
+const LVT_MASKED: u32 = 1 << 16;
+/// LVT 中的 NMI 投递模式。
+const LVT_NMI: u32 = 0b100 << 8;
+/// LVT 定时器的周期模式，计数到 0 后重新装入初值。
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+/// 定时器的分频：总线频率除以 16。
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+/// 伪中断寄存器中的 APIC 软件使能位。
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+/// Local APIC 寄存器映射后的虚拟地址，0 表示还没有启用。
+static LOCAL_APIC: AtomicU64 = AtomicU64::new(0);
+
+/// CPU 是否有 APIC（CPUID.01H:EDX 的第 9 位）。
+pub fn is_supported() -> bool {
+    // 较新的编译器中 __cpuid 不再是 unsafe 的。
+    #[allow(unused_unsafe)]
+    let edx = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+    edx & (1 << 9) != 0
+}
+
+fn local_apic_base() -> u64 {
+    LOCAL_APIC.load(Ordering::Relaxed)
+}
+
+unsafe fn read_local(offset: usize) -> u32 {
+    ((local_apic_base() as usize + offset) as *const u32).read_volatile()
+}
+
+unsafe fn write_local(offset: usize, value: u32) {
+    ((local_apic_base() as usize + offset) as *mut u32).write_volatile(value)
+}
+
+/// 映射并启用当前处理器的 Local APIC，伪中断使用 spurious_vector。
+/// LINT0（与 8259 相连的 ExtINT）被屏蔽，LINT1 作为 NMI。
+pub(super) fn init_local(address: PhysAddr, spurious_vector: u8) -> Result<(), VmallocError> {
+    let base = unsafe { memory::ioremap(address, 4096, CacheMode::Uncached)? };
+    LOCAL_APIC.store(base.as_u64(), Ordering::Relaxed);
+    unsafe {
+        let mut msr = Msr::new(IA32_APIC_BASE);
+        let value = msr.read();
+        msr.write(value | APIC_BASE_ENABLE);
+        write_local(reg::TASK_PRIORITY, 0);
+        write_local(reg::LVT_TIMER, LVT_MASKED);
+        write_local(reg::LVT_LINT0, LVT_MASKED);
+        write_local(reg::LVT_LINT1, LVT_NMI);
+        write_local(reg::LVT_ERROR, LVT_MASKED);
+        write_local(reg::SPURIOUS, SPURIOUS_ENABLE | spurious_vector as u32);
+    }
+    Ok(())
+}
+
+/// 当前处理器的 Local APIC ID。
+pub fn local_apic_id() -> u8 {
+    (unsafe { read_local(reg::ID) } >> 24) as u8
+}
+
+/// 以最大初值启动不产生中断的单次定时器，用于校准：读取 timer_count 计算一段时间内减少的计数。
+pub(crate) fn start_timer_calibration() {
+    unsafe {
+        write_local(reg::TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        write_local(reg::LVT_TIMER, LVT_MASKED);
+        write_local(reg::TIMER_INITIAL_COUNT, u32::MAX);
+    }
+}
+
+/// 定时器的当前计数，从初值开始递减。
+pub(crate) fn timer_count() -> u32 {
+    unsafe { read_local(reg::TIMER_CURRENT_COUNT) }
+}
+
+/// 让定时器每减少 initial_count 个计数（使用与校准时相同的分频）就在 vector 上产生一次中断。
+pub(crate) fn start_timer_periodic(vector: u8, initial_count: u32) {
+    unsafe {
+        write_local(reg::TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        write_local(reg::LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+        write_local(reg::TIMER_INITIAL_COUNT, initial_count);
+    }
+}
+
+/// 通知 Local APIC 中断已经处理完毕。
+pub(super) fn end_of_interrupt() {
+    unsafe { write_local(reg::EOI, 0) };
+}
+
+/// 向量 vector 是否正在服务。Local APIC 投递的中断（包括来自 I/O APIC 的）在处理期间置位，直到 EOI；没有经过
+/// Local APIC 的中断（例如 8259 的伪中断）不会置位。
+pub(super) fn is_in_service(vector: u8) -> bool {
+    let offset = reg::IN_SERVICE + 0x10 * (vector as usize / 32);
+    unsafe { read_local(offset) & (1 << (vector % 32)) != 0 }
+}
+
+/// I/O APIC 寄存器：先把寄存器号写入 IOREGSEL，再通过 IOWIN 读写。
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+/// 重定向表项的低 32 位中的标志。
+const REDIRECT_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECT_LEVEL: u32 = 1 << 15;
+const REDIRECT_MASKED: u32 = 1 << 16;
+
+#[derive(Debug, Clone, Copy)]
+struct IoApic {
+    base: VirtAddr,
+    gsi_base: u32,
+    /// 重定向表项的数量。
+    entries: u32,
+}
+
+impl IoApic {
+    unsafe fn read(&self, reg: u32) -> u32 {
+        (self.base + IOREGSEL)
+            .as_mut_ptr::<u32>()
+            .write_volatile(reg);
+        (self.base + IOWIN).as_ptr::<u32>().read_volatile()
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        (self.base + IOREGSEL)
+            .as_mut_ptr::<u32>()
+            .write_volatile(reg);
+        (self.base + IOWIN)
+            .as_mut_ptr::<u32>()
+            .write_volatile(value)
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn redirection_reg(&self, gsi: u32) -> u32 {
+        IOAPIC_REDIRECTION + 2 * (gsi - self.gsi_base)
+    }
+}
+
+static IO_APICS: Mutex<[Option<IoApic>; MAX_IO_APICS]> = Mutex::new([None; MAX_IO_APICS]);
+
+/// 映射一个 I/O APIC，并屏蔽它的所有输入。
+pub(super) fn add_io_apic(info: &IoApicInfo) -> Result<(), VmallocError> {
+    let base = unsafe { memory::ioremap(info.address, 0x20, CacheMode::Uncached)? };
+    let mut io_apic = IoApic {
+        base,
+        gsi_base: info.gsi_base,
+        entries: 0,
+    };
+    // 版本寄存器的第 16-23 位是最大的重定向表项下标。
+    io_apic.entries = ((unsafe { io_apic.read(IOAPIC_VERSION) } >> 16) & 0xff) + 1;
+    for gsi in io_apic.gsi_base..io_apic.gsi_base + io_apic.entries {
+        unsafe { io_apic.write(io_apic.redirection_reg(gsi), REDIRECT_MASKED) };
+    }
+    without_interrupts(|| {
+        let mut io_apics = IO_APICS.lock();
+        if let Some(slot) = io_apics.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(io_apic);
+        }
+    });
+    Ok(())
+}
+
+fn with_io_apic<R>(gsi: u32, f: impl FnOnce(&IoApic) -> R) -> Option<R> {
+    without_interrupts(|| {
+        let io_apics = IO_APICS.lock();
+        io_apics.iter().flatten().find(|io| io.handles(gsi)).map(f)
+    })
+}
+
+/// 把全局中断 gsi 以固定投递模式转发到 Local APIC ID 为 dest 的处理器的 vector 上，初始为屏蔽状态。
+/// 没有 I/O APIC 负责这个 GSI 时返回 false。
+pub(super) fn route(
+    gsi: u32,
+    vector: u8,
+    dest: u8,
+    polarity: Polarity,
+    trigger: TriggerMode,
+) -> bool {
+    let mut low = REDIRECT_MASKED | vector as u32;
+    if polarity == Polarity::ActiveLow {
+        low |= REDIRECT_ACTIVE_LOW;
+    }
+    if trigger == TriggerMode::Level {
+        low |= REDIRECT_LEVEL;
+    }
+    with_io_apic(gsi, |io| unsafe {
+        let reg = io.redirection_reg(gsi);
+        io.write(reg + 1, (dest as u32) << 24);
+        io.write(reg, low);
+    })
+    .is_some()
+}
+
+/// 屏蔽或者取消屏蔽全局中断 gsi。
+pub(super) fn set_masked(gsi: u32, masked: bool) {
+    with_io_apic(gsi, |io| unsafe {
+        let reg = io.redirection_reg(gsi);
+        let low = io.read(reg);
+        let low = if masked {
+            low | REDIRECT_MASKED
+        } else {
+            low & !REDIRECT_MASKED
+        };
+        io.write(reg, low);
+    });
+}
+
+/// 全局中断 gsi 是否被屏蔽，没有 I/O APIC 负责这个 GSI 时返回 None。
+pub fn is_masked(gsi: u32) -> Option<bool> {
+    with_io_apic(gsi, |io| unsafe {
+        io.read(io.redirection_reg(gsi)) & REDIRECT_MASKED != 0
+    })
+}
@@ -0,0 +1,201 @@
+//! 中断控制器：启动时使用 8259 PIC，内存管理初始化之后通过 ACPI 的 MADT 查找 Local APIC 和 I/O APIC，找到时切换到
+//! APIC 并屏蔽 8259；没有 APIC（或者命令行中有 `noapic`）时继续使用 PIC。
+//!
+//! 无论使用哪一种控制器，ISA 中断 irq 都投递到向量 PIC_1_OFFSET + irq，IDT 不需要改变。中断处理函数通过
+//! end_of_interrupt 通知控制器，不需要知道当前使用的是哪一种。
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+use super::{apic, InterruptIndex, PICS, PIC_1_OFFSET};
+use crate::{acpi, cmdline, println};
+
+/// ISA 中断的数量。
+pub const ISA_IRQS: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ControllerKind {
+    /// 主从两片 8259 PIC。
+    Pic,
+    /// Local APIC + I/O APIC。
+    Apic,
+}
+
+static KIND: AtomicU8 = AtomicU8::new(ControllerKind::Pic as u8);
+
+/// 当前使用的中断控制器。
+pub fn controller() -> ControllerKind {
+    match KIND.load(Ordering::Relaxed) {
+        0 => ControllerKind::Pic,
+        _ => ControllerKind::Apic,
+    }
+}
+
+/// ISA 中断 irq 使用的中断向量。
+pub fn irq_vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+/// 8259 的命令端口。
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xa0;
+/// 8259 的数据端口，读写中断屏蔽字（IMR），置 1 的位被屏蔽。
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_DATA: u16 = 0xa1;
+/// OCW3：之后读命令端口得到正在服务的中断（ISR）。
+const PIC_READ_ISR: u8 = 0x0b;
+const PIC_EOI: u8 = 0x20;
+
+fn read_pic_masks() -> [u8; 2] {
+    unsafe { [Port::new(PIC_1_DATA).read(), Port::new(PIC_2_DATA).read()] }
+}
+
+fn write_pic_masks(masks: [u8; 2]) {
+    unsafe {
+        Port::new(PIC_1_DATA).write(masks[0]);
+        Port::new(PIC_2_DATA).write(masks[1]);
+    }
+}
+
+/// 使用 APIC 时 ISA 中断 irq 连接的全局中断。其他 ISA 中断被重定向到了同一个 GSI 时（例如 QEMU 中 IRQ 0 连接到
+/// GSI 2，而 IRQ 2 是 8259 的级联引脚），irq 本身不存在，返回 None。
+fn isa_gsi(madt: &acpi::Madt, irq: u8) -> Option<u32> {
+    let gsi = madt.isa_interrupt(irq).gsi;
+    let shadowed = madt.overrides().any(|o| o.gsi == gsi && o.irq != irq);
+    (!shadowed).then_some(gsi)
+}
+
+/// ISA 中断 irq 是否被屏蔽。
+pub fn is_irq_masked(irq: u8) -> bool {
+    assert!(irq < ISA_IRQS, "invalid ISA irq {}", irq);
+    match controller() {
+        ControllerKind::Pic => read_pic_masks()[irq as usize / 8] & (1 << (irq % 8)) != 0,
+        ControllerKind::Apic => acpi::madt()
+            .and_then(|madt| isa_gsi(madt, irq))
+            .and_then(apic::is_masked)
+            .unwrap_or(true),
+    }
+}
+
+fn set_irq_masked(irq: u8, masked: bool) {
+    assert!(irq < ISA_IRQS, "invalid ISA irq {}", irq);
+    without_interrupts(|| match controller() {
+        ControllerKind::Pic => {
+            let mut masks = read_pic_masks();
+            let bit = 1 << (irq % 8);
+            let mask = &mut masks[irq as usize / 8];
+            *mask = if masked { *mask | bit } else { *mask & !bit };
+            // 从片连接在主片的 2 号引脚上，从片有中断未屏蔽时主片的 2 号引脚也不能屏蔽。
+            if masks[1] != 0xff {
+                masks[0] &= !(1 << 2);
+            }
+            write_pic_masks(masks);
+        }
+        ControllerKind::Apic => {
+            if let Some(gsi) = acpi::madt().and_then(|madt| isa_gsi(madt, irq)) {
+                apic::set_masked(gsi, masked);
+            }
+        }
+    });
+}
+
+/// 屏蔽所有 ISA 中断。
+pub(super) fn mask_all() {
+    for irq in 0..ISA_IRQS {
+        set_irq_masked(irq, true);
+    }
+}
+
+/// 允许 ISA 中断 irq。
+pub fn enable_irq(irq: u8) {
+    set_irq_masked(irq, false);
+}
+
+/// 屏蔽 ISA 中断 irq。
+pub fn disable_irq(irq: u8) {
+    set_irq_masked(irq, true);
+}
+
+/// 判断 irq 是否是 8259 的伪中断。中断请求在 CPU 响应之前消失时，8259 会以最低优先级的 IRQ 7（从片为 IRQ 15）
+/// 发出中断，但不会设置 ISR 中对应的位。伪中断不能发送 EOI，但从片的伪中断仍然占用了主片的 2 号引脚，需要通知主片。
+///
+/// 切换到 APIC 之后 8259 被屏蔽，但屏蔽的 8259 仍然可能发出伪中断，向量与经 I/O APIC 投递的 IRQ 7、IRQ 15 相同。
+/// 这时看 Local APIC 的 ISR：经 I/O APIC 投递的中断会置位，8259 的伪中断不会。
+pub(super) fn is_spurious(irq: u8) -> bool {
+    if irq != 7 && irq != 15 {
+        return false;
+    }
+    if controller() == ControllerKind::Apic {
+        return !apic::is_in_service(irq_vector(irq));
+    }
+    let command = if irq == 7 {
+        PIC_1_COMMAND
+    } else {
+        PIC_2_COMMAND
+    };
+    let isr: u8 = unsafe {
+        let mut port = Port::new(command);
+        port.write(PIC_READ_ISR);
+        port.read()
+    };
+    if isr & (1 << 7) != 0 {
+        return false;
+    }
+    if irq == 15 {
+        unsafe { Port::new(PIC_1_COMMAND).write(PIC_EOI) };
+    }
+    true
+}
+
+/// 通知中断控制器向量 vector 上的中断已经处理完毕，否则后续中断会一直排队。
+pub fn end_of_interrupt(vector: u8) {
+    match controller() {
+        // notify_end_of_interrupt 会根据中断号判断需要通知主片还是主从两片。
+        ControllerKind::Pic => unsafe { PICS.lock().notify_end_of_interrupt(vector) },
+        ControllerKind::Apic => apic::end_of_interrupt(),
+    }
+}
+
+/// 尝试切换到 APIC，返回最终使用的中断控制器。必须在 memory::init_global 之后调用（需要 ioremap 映射寄存器）。
+/// 切换前后被允许的 ISA 中断保持不变。
+pub fn init_controller() -> ControllerKind {
+    if cmdline::get("noapic").is_some() || !apic::is_supported() {
+        return controller();
+    }
+    let madt = match acpi::madt() {
+        Some(madt) if madt.io_apics().next().is_some() => madt,
+        _ => return controller(),
+    };
+    without_interrupts(|| {
+        let enabled: [bool; ISA_IRQS as usize] =
+            core::array::from_fn(|irq| !is_irq_masked(irq as u8));
+        if let Err(err) =
+            apic::init_local(madt.local_apic_address, InterruptIndex::Spurious.as_u8())
+        {
+            println!(
+                "failed to map the local APIC: {:?}, using the 8259 PIC",
+                err
+            );
+            return;
+        }
+        for info in madt.io_apics() {
+            if let Err(err) = apic::add_io_apic(info) {
+                println!("failed to map I/O APIC {}: {:?}", info.id, err);
+            }
+        }
+        let dest = apic::local_apic_id();
+        for irq in (0..ISA_IRQS).filter(|&irq| isa_gsi(madt, irq).is_some()) {
+            let isa = madt.isa_interrupt(irq);
+            apic::route(isa.gsi, irq_vector(irq), dest, isa.polarity, isa.trigger);
+        }
+        // 屏蔽 8259 的所有中断，之后它只会发出伪中断，由 is_spurious 过滤。
+        write_pic_masks([0xff, 0xff]);
+        KIND.store(ControllerKind::Apic as u8, Ordering::Relaxed);
+        for irq in (0..ISA_IRQS).filter(|&irq| enabled[irq as usize]) {
+            enable_irq(irq);
+        }
+    });
+    controller()
+}
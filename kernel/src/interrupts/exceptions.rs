@@ -0,0 +1,326 @@
+//! CPU 异常（向量 0-31）的处理函数。
+//!
+//! 除了 breakpoint、double fault 和 page fault（在上级模块中处理）之外，所有异常都在这里安装，避免未处理的异常升级
+//! 为 double fault 而丢失真正的原因。debug 和 NMI 输出信息后继续执行；其余异常都是致命的：输出异常名、解码后的错误码、
+//! 出错的指令地址和寄存器之后 panic。
+//!
+//! 异常经过同一段入口代码（见 global_asm!）进入 Rust：每个向量有一个 16 字节的小入口，没有错误码的异常压入一个 0
+//! 作为占位，然后压入向量号，跳到公共入口；公共入口保存 RAX-R15，把栈上的现场交给 exception_dispatch，返回后恢复
+//! 寄存器并 iretq。x86-interrupt 调用约定不把被打断代码的通用寄存器交给处理函数，所以这里不用它。
+
+use core::{arch::global_asm, fmt};
+
+use x86_64::{
+    registers::control::{Cr0, Cr2, Cr3, Cr4},
+    structures::idt::{
+        DescriptorTable, Entry, HandlerFunc, InterruptDescriptorTable, InterruptStackFrameValue,
+        SelectorErrorCode,
+    },
+    VirtAddr,
+};
+
+use crate::{backtrace, vga_buffer};
+
+/// 异常的错误码。
+#[derive(Debug, Clone, Copy)]
+enum ErrorCode {
+    /// 异常没有错误码。
+    None,
+    Raw(u64),
+    /// #TS、#NP、#SS、#GP 的错误码是出错的段选择子。
+    Selector(u64),
+}
+
+/// 公共入口保存的通用寄存器，顺序与压栈顺序相反。
+#[repr(C)]
+struct SavedRegisters {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+}
+
+/// 入口代码在栈上构造的现场，从低地址到高地址。
+#[repr(C)]
+struct ExceptionFrame {
+    registers: SavedRegisters,
+    vector: u64,
+    /// 没有错误码的异常为 0。
+    error_code: u64,
+    stack_frame: InterruptStackFrameValue,
+}
+
+/// 异常的诊断信息，用于输出和 panic。
+struct ExceptionReport<'a> {
+    vector: u8,
+    error_code: ErrorCode,
+    frame: &'a ExceptionFrame,
+}
+
+/// 向量对应的异常名和助记符。
+fn exception_name(vector: u8) -> (&'static str, &'static str) {
+    match vector {
+        0 => ("DIVIDE ERROR", "#DE"),
+        1 => ("DEBUG", "#DB"),
+        2 => ("NON-MASKABLE INTERRUPT", "NMI"),
+        3 => ("BREAKPOINT", "#BP"),
+        4 => ("OVERFLOW", "#OF"),
+        5 => ("BOUND RANGE EXCEEDED", "#BR"),
+        6 => ("INVALID OPCODE", "#UD"),
+        7 => ("DEVICE NOT AVAILABLE", "#NM"),
+        8 => ("DOUBLE FAULT", "#DF"),
+        9 => ("COPROCESSOR SEGMENT OVERRUN", "reserved"),
+        10 => ("INVALID TSS", "#TS"),
+        11 => ("SEGMENT NOT PRESENT", "#NP"),
+        12 => ("STACK-SEGMENT FAULT", "#SS"),
+        13 => ("GENERAL PROTECTION FAULT", "#GP"),
+        14 => ("PAGE FAULT", "#PF"),
+        16 => ("X87 FLOATING-POINT EXCEPTION", "#MF"),
+        17 => ("ALIGNMENT CHECK", "#AC"),
+        18 => ("MACHINE CHECK", "#MC"),
+        19 => ("SIMD FLOATING-POINT EXCEPTION", "#XM"),
+        20 => ("VIRTUALIZATION EXCEPTION", "#VE"),
+        21 => ("CONTROL PROTECTION EXCEPTION", "#CP"),
+        28 => ("HYPERVISOR INJECTION EXCEPTION", "#HV"),
+        29 => ("VMM COMMUNICATION EXCEPTION", "#VC"),
+        30 => ("SECURITY EXCEPTION", "#SX"),
+        _ => ("RESERVED EXCEPTION", "reserved"),
+    }
+}
+
+impl fmt::Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, mnemonic) = exception_name(self.vector);
+        writeln!(
+            f,
+            "EXCEPTION: {} ({}, vector {})",
+            name, mnemonic, self.vector
+        )?;
+        match self.error_code {
+            ErrorCode::None => {}
+            ErrorCode::Raw(code) => writeln!(f, "Error Code: {:#x}", code)?,
+            ErrorCode::Selector(0) => writeln!(f, "Error Code: 0 (not caused by a selector)")?,
+            ErrorCode::Selector(code) => {
+                let selector = SelectorErrorCode::new_truncate(code);
+                let table = match selector.descriptor_table() {
+                    DescriptorTable::Gdt => "GDT",
+                    DescriptorTable::Idt => "IDT",
+                    DescriptorTable::Ldt => "LDT",
+                };
+                writeln!(
+                    f,
+                    "Error Code: {:#x} (selector: {} index {:#x}{})",
+                    code,
+                    table,
+                    selector.index(),
+                    if selector.external() {
+                        ", external"
+                    } else {
+                        ""
+                    }
+                )?;
+            }
+        }
+        let frame = &self.frame.stack_frame;
+        write!(
+            f,
+            "Instruction Pointer: {:#x}",
+            frame.instruction_pointer.as_u64()
+        )?;
+        match backtrace::lookup(frame.instruction_pointer) {
+            Some(symbol) => writeln!(f, " ({})", symbol)?,
+            None => writeln!(f)?,
+        }
+        writeln!(
+            f,
+            "RSP={:#018x} RFLAGS={:#010x} CS={:#06x} SS={:#06x}",
+            frame.stack_pointer.as_u64(),
+            frame.cpu_flags,
+            frame.code_segment,
+            frame.stack_segment
+        )?;
+        let regs = &self.frame.registers;
+        writeln!(
+            f,
+            "RAX={:#018x} RBX={:#018x} RCX={:#018x} RDX={:#018x}",
+            regs.rax, regs.rbx, regs.rcx, regs.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI={:#018x} RDI={:#018x} RBP={:#018x} R8 ={:#018x}",
+            regs.rsi, regs.rdi, regs.rbp, regs.r8
+        )?;
+        writeln!(
+            f,
+            "R9 ={:#018x} R10={:#018x} R11={:#018x} R12={:#018x}",
+            regs.r9, regs.r10, regs.r11, regs.r12
+        )?;
+        writeln!(
+            f,
+            "R13={:#018x} R14={:#018x} R15={:#018x}",
+            regs.r13, regs.r14, regs.r15
+        )?;
+        write!(
+            f,
+            "CR0={:#010x} CR2={:#018x} CR3={:#018x} CR4={:#010x}",
+            Cr0::read_raw(),
+            Cr2::read().as_u64(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
+}
+
+// 每个向量的入口占 16 字节，按向量号排列，向量 N 的入口在 exception_stubs + 16 * N。
+// 8、10-14、17、21、29、30 号异常由 CPU 压入错误码，其余的压入 0 占位，使栈上的现场布局一致。
+// CPU 压栈前把 RSP 对齐到 16 字节，栈帧、错误码、向量号和 15 个寄存器共 22 个 8 字节，call 之前 RSP 仍然对齐。
+global_asm!(
+    ".macro exception_stub vector, error_code",
+    "    .balign 16",
+    "    .if \\error_code == 0",
+    "    push 0",
+    "    .endif",
+    "    push \\vector",
+    "    jmp .Lexception_common",
+    ".endm",
+    "",
+    ".balign 16",
+    ".global exception_stubs",
+    "exception_stubs:",
+    "    exception_stub 0, 0",
+    "    exception_stub 1, 0",
+    "    exception_stub 2, 0",
+    "    exception_stub 3, 0",
+    "    exception_stub 4, 0",
+    "    exception_stub 5, 0",
+    "    exception_stub 6, 0",
+    "    exception_stub 7, 0",
+    "    exception_stub 8, 1",
+    "    exception_stub 9, 0",
+    "    exception_stub 10, 1",
+    "    exception_stub 11, 1",
+    "    exception_stub 12, 1",
+    "    exception_stub 13, 1",
+    "    exception_stub 14, 1",
+    "    exception_stub 15, 0",
+    "    exception_stub 16, 0",
+    "    exception_stub 17, 1",
+    "    exception_stub 18, 0",
+    "    exception_stub 19, 0",
+    "    exception_stub 20, 0",
+    "    exception_stub 21, 1",
+    "    exception_stub 22, 0",
+    "    exception_stub 23, 0",
+    "    exception_stub 24, 0",
+    "    exception_stub 25, 0",
+    "    exception_stub 26, 0",
+    "    exception_stub 27, 0",
+    "    exception_stub 28, 0",
+    "    exception_stub 29, 1",
+    "    exception_stub 30, 1",
+    "    exception_stub 31, 0",
+    "",
+    ".Lexception_common:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    "    call {dispatch}",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // 丢掉向量号和错误码。
+    "    add rsp, 16",
+    "    iretq",
+    dispatch = sym exception_dispatch,
+);
+
+extern "C" {
+    fn exception_stubs();
+}
+
+/// 向量 vector 的入口地址。
+fn stub_addr(vector: usize) -> VirtAddr {
+    let base = exception_stubs as unsafe extern "C" fn() as usize as u64;
+    VirtAddr::new(base + 16 * vector as u64)
+}
+
+/// 公共入口调用的 Rust 处理函数。debug 和 NMI 输出后返回，入口代码恢复寄存器后继续执行；其余异常都是致命的。
+extern "C" fn exception_dispatch(frame: &ExceptionFrame) {
+    let vector = frame.vector as u8;
+    let error_code = match vector {
+        // #TS、#NP、#SS、#GP 的错误码是出错的段选择子。
+        10..=13 => ErrorCode::Selector(frame.error_code),
+        17 | 21 | 29 | 30 => ErrorCode::Raw(frame.error_code),
+        _ => ErrorCode::None,
+    };
+    let report = ExceptionReport {
+        vector,
+        error_code,
+        frame,
+    };
+    match vector {
+        // debug：单步、硬件断点等，内核目前不使用。NMI：通常表示硬件错误或者看门狗。
+        1 | 2 => report_and_continue(&report),
+        // 其余异常（包括机器检查和保留的向量）无法恢复。
+        _ => panic!("{}", report),
+    }
+}
+
+/// 输出可以继续执行的异常。debug 和 NMI 可能打断正在持有 WRITER 锁的代码（NMI 不受 cli 影响），等待锁会死锁，
+/// 所以锁被占用时丢弃这条信息。
+fn report_and_continue(report: &ExceptionReport) {
+    vga_buffer::try_print(format_args!("{}\n", report));
+}
+
+/// x86_64 crate 没有公开保留向量（9、15、21-28、31）的表项。IDT 在内存中就是 256 个相同大小的表项，按下标访问。
+fn raw_entry(idt: &mut InterruptDescriptorTable, vector: usize) -> &mut Entry<HandlerFunc> {
+    assert!(vector < 32);
+    unsafe { &mut *(idt as *mut InterruptDescriptorTable as *mut Entry<HandlerFunc>).add(vector) }
+}
+
+/// 安装 breakpoint、double fault 和 page fault 之外所有异常的入口。
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    for vector in 0..32 {
+        if matches!(vector, 3 | 8 | 14) {
+            continue;
+        }
+        unsafe { raw_entry(idt, vector).set_handler_addr(stub_addr(vector)) };
+    }
+}
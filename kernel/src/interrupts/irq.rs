@@ -0,0 +1,180 @@
+//! 硬件中断的动态注册。
+//!
+//! 每个 ISA 中断向量都安装同一个泛型入口 irq_stub::<IRQ>，它依次调用这个 IRQ 上注册的所有处理函数（多个设备可以
+//! 共享一个 IRQ），最后自动通知中断控制器（EOI）。设备驱动只需要调用 register_irq，不需要修改 IDT。
+//!
+//! 处理函数在中断上下文中执行（中断是关闭的），不能阻塞，也不能注册或注销处理函数。
+//! 处理函数表是固定大小的，不依赖堆：不捕获变量的闭包和函数装箱后大小为 0，不会分配内存，所以内置的时钟和键盘
+//! 处理函数可以在堆初始化之前注册。
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{instructions::interrupts::without_interrupts, structures::idt::InterruptStackFrame};
+
+use super::controller::{self, irq_vector, ISA_IRQS};
+
+/// 一个 IRQ 最多可以注册的处理函数数量。
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// 处理函数的返回值，用于统计没有设备认领的中断。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// 中断来自这个处理函数的设备，已经处理。
+    Handled,
+    /// 中断不是这个设备产生的（共享 IRQ 时）。
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// 不是 ISA 中断号。
+    InvalidIrq,
+    /// 这个 IRQ 上注册的处理函数已经达到上限。
+    TooManyHandlers,
+}
+
+/// register_irq 的返回值，用于注销处理函数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandlerId {
+    irq: u8,
+    id: u64,
+}
+
+impl IrqHandlerId {
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+type Handler = Box<dyn Fn() -> IrqReturn + Send + Sync>;
+
+struct IrqAction {
+    id: u64,
+    name: &'static str,
+    handler: Handler,
+}
+
+const NO_ACTION: Option<IrqAction> = None;
+const NO_ACTIONS: [Option<IrqAction>; MAX_SHARED_HANDLERS] = [NO_ACTION; MAX_SHARED_HANDLERS];
+
+/// 每个 IRQ 上注册的处理函数，按注册顺序调用。只在关闭中断时加锁。
+static ACTIONS: Mutex<[[Option<IrqAction>; MAX_SHARED_HANDLERS]; ISA_IRQS as usize]> =
+    Mutex::new([NO_ACTIONS; ISA_IRQS as usize]);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+/// 每个 IRQ 发生的次数。
+static COUNTS: [AtomicU64; ISA_IRQS as usize] = [ZERO; ISA_IRQS as usize];
+/// 每个 IRQ 上没有处理函数认领的次数。
+static UNHANDLED: [AtomicU64; ISA_IRQS as usize] = [ZERO; ISA_IRQS as usize];
+
+/// 在 irq 上注册一个处理函数，第一个处理函数注册时允许这个中断。name 只用于调试输出。
+pub fn register_irq(
+    irq: u8,
+    name: &'static str,
+    handler: impl Fn() -> IrqReturn + Send + Sync + 'static,
+) -> Result<IrqHandlerId, IrqError> {
+    if irq >= ISA_IRQS {
+        return Err(IrqError::InvalidIrq);
+    }
+    let handler: Handler = Box::new(handler);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let first = without_interrupts(|| {
+        let mut actions = ACTIONS.lock();
+        let actions = &mut actions[irq as usize];
+        let first = actions.iter().all(|action| action.is_none());
+        let slot = actions
+            .iter_mut()
+            .find(|action| action.is_none())
+            .ok_or(IrqError::TooManyHandlers)?;
+        *slot = Some(IrqAction { id, name, handler });
+        Ok(first)
+    })?;
+    if first {
+        controller::enable_irq(irq);
+    }
+    Ok(IrqHandlerId { irq, id })
+}
+
+/// 注销处理函数，最后一个处理函数注销后屏蔽这个中断。处理函数不存在时返回 false。
+pub fn unregister_irq(handler: IrqHandlerId) -> bool {
+    let irq = handler.irq;
+    let (action, last) = without_interrupts(|| {
+        let mut actions = ACTIONS.lock();
+        let actions = &mut actions[irq as usize];
+        let action = actions
+            .iter_mut()
+            .find(|action| action.as_ref().map_or(false, |a| a.id == handler.id))
+            .and_then(Option::take);
+        (action, actions.iter().all(|action| action.is_none()))
+    });
+    if action.is_some() && last {
+        controller::disable_irq(irq);
+    }
+    // 处理函数在锁外释放。
+    action.is_some()
+}
+
+/// irq 上注册的处理函数的名字，按调用顺序排列。
+pub fn irq_handlers(irq: u8) -> [Option<&'static str>; MAX_SHARED_HANDLERS] {
+    without_interrupts(|| {
+        let actions = ACTIONS.lock();
+        core::array::from_fn(|i| actions[irq as usize][i].as_ref().map(|a| a.name))
+    })
+}
+
+/// irq 发生的次数。
+pub fn irq_count(irq: u8) -> u64 {
+    COUNTS[irq as usize].load(Ordering::Relaxed)
+}
+
+/// irq 上没有处理函数认领的中断次数。
+pub fn unhandled_irq_count(irq: u8) -> u64 {
+    UNHANDLED[irq as usize].load(Ordering::Relaxed)
+}
+
+fn dispatch(irq: u8) {
+    if controller::is_spurious(irq) {
+        return;
+    }
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    let mut handled = false;
+    {
+        // 中断处理期间中断是关闭的，注册和注销也只在关闭中断时加锁，这里不会死锁。
+        let actions = ACTIONS.lock();
+        for action in actions[irq as usize].iter().flatten() {
+            handled |= (action.handler)() == IrqReturn::Handled;
+        }
+    }
+    if !handled {
+        UNHANDLED[irq as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    controller::end_of_interrupt(irq_vector(irq));
+}
+
+/// 所有 ISA 中断向量共用的入口。
+extern "x86-interrupt" fn irq_stub<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(IRQ);
+}
+
+/// irq_stub 的各个实例，下标是 IRQ。
+pub(super) const STUBS: [extern "x86-interrupt" fn(InterruptStackFrame); ISA_IRQS as usize] = [
+    irq_stub::<0>,
+    irq_stub::<1>,
+    irq_stub::<2>,
+    irq_stub::<3>,
+    irq_stub::<4>,
+    irq_stub::<5>,
+    irq_stub::<6>,
+    irq_stub::<7>,
+    irq_stub::<8>,
+    irq_stub::<9>,
+    irq_stub::<10>,
+    irq_stub::<11>,
+    irq_stub::<12>,
+    irq_stub::<13>,
+    irq_stub::<14>,
+    irq_stub::<15>,
+];
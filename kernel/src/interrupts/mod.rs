@@ -16,27 +16,50 @@ lazy_static! {
     // 前32个是中断，后32个是异常.
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+        exceptions::install(&mut idt);
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         unsafe {
         idt.double_fault.set_handler_fn(double_fault_handler)
             .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
         idt.page_fault.set_handler_fn(page_fault_handler);
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
-        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        // 硬件中断统一由 irq 模块分发，设备通过 register_irq 注册处理函数。
+        for irq in 0..ISA_IRQS {
+            idt[irq_vector(irq) as usize].set_handler_fn(irq::STUBS[irq as usize]);
+        }
+        idt[InterruptIndex::Spurious.as_usize()].set_handler_fn(spurious_interrupt_handler);
         idt
     };
 }
 
-use crate::{gdt, hlt_loop, println, task::keyboard::add_scan_code};
+mod apic;
+mod controller;
+mod exceptions;
+mod irq;
+
+pub(crate) use apic::{start_timer_calibration, start_timer_periodic, timer_count};
+pub use controller::{
+    controller, disable_irq, enable_irq, end_of_interrupt, init_controller, irq_vector,
+    is_irq_masked, ControllerKind, ISA_IRQS,
+};
+pub use irq::{
+    irq_count, irq_handlers, register_irq, unhandled_irq_count, unregister_irq, IrqError,
+    IrqHandlerId, IrqReturn, MAX_SHARED_HANDLERS,
+};
+
+use crate::{backtrace, gdt, hlt_loop, memory, println, task::keyboard::add_scan_code, time};
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin::Mutex;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
-/// 初始化中断描述符表
+/// 初始化中断描述符表，并注册内置的时钟和键盘中断处理函数。
 pub fn init_idt() {
     IDT.load();
+    controller::mask_all();
+    register_irq(0, "timer", timer_interrupt).expect("failed to register the timer interrupt");
+    register_irq(1, "keyboard", keyboard_interrupt)
+        .expect("failed to register the keyboard interrupt");
 }
 
 /// extern "x86-interrupt" 表示这是一个中断处理函数，而不是一个普通函数。
@@ -52,6 +75,15 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    use x86_64::registers::control::Cr2;
+    // 内核栈溢出时，访问保护页引发的 page fault 无法在同一个栈上压栈，升级为 double fault。
+    let addr = Cr2::read();
+    if memory::is_guard_page(addr) {
+        panic!(
+            "EXCEPTION: DOUBLE FAULT (kernel stack overflow at {:?})\n\n{:#?}",
+            addr, stack_frame
+        );
+    }
     panic!(
         "EXCEPTION: DOUBLE FAULT
 
@@ -92,7 +124,7 @@ pub enum InterruptIndex {
     Timer = PIC_1_OFFSET, // Timer 在 master 的第0个引脚，所以中断号为 32(0x20)
     Keyboard,             // Keyboard 在 master 的第1个引脚，所以中断号为 33(0x21)
     HardDisk = PIC_2_OFFSET + 6, // HardDisk 在 slave 的第6个引脚，所以中断号为 46(0x2E)
-    SystemCall = 0x80,    // SystemCall 中断号为 0x80
+    Spurious = 0xff,      // Local APIC 的伪中断
 }
 
 impl InterruptIndex {
@@ -105,20 +137,20 @@ impl InterruptIndex {
     }
 }
 
-/// Timer 中断处理函数。
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    unsafe {
-        // 要通知 PIC 中断已经处理完毕，否则后续中断会一直排队。
-        // notify_end_of_interrupt 会自行判断中断信号发送的源头（主PIC或者副PIC），并使用指令和数据端口将信号发送到目标控制器。当然，如果是要发送到副PIC，那么结果上必然等同于同时发送到两个PIC，因为副PIC的输入管脚连在主PIC上面。
-        // 这里的中断编码一定不可以写错，不然可能会导致某个中断信号迟迟得不到回应导致系统整体挂起。这也是该函数被标记为不安全的原因。
-        // print!(".");
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+/// 启动以来时钟中断的次数，与 time::ticks 相同。
+pub fn timer_ticks() -> u64 {
+    time::ticks()
 }
 
-/// 键盘中断处理函数。
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+/// 时钟中断，来自 PIT 或者 Local APIC 定时器，见 time 模块。
+fn timer_interrupt() -> IrqReturn {
+    // print!(".");
+    time::handle_tick();
+    IrqReturn::Handled
+}
+
+/// 键盘中断。
+fn keyboard_interrupt() -> IrqReturn {
     use x86_64::instructions::port::Port;
 
     // 0x60 是键盘控制器的数据端口。需要从这个端口读取扫描码，才能知道用户按下了什么键。键盘中断只是通知我们有键盘输入，但是并不会告诉我们具体是什么键。
@@ -127,27 +159,48 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     // let mut keyboard = KEYBOARD.lock();
     let scan_code: u8 = unsafe { port.read() };
     add_scan_code(scan_code);
-
-    // 通知 PIC 中断已经处理完毕。否则后续中断会一直排队。
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    IrqReturn::Handled
 }
 
+/// Local APIC 的伪中断：中断在投递前被撤销时产生，不需要（也不能）发送 EOI。
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 // todo: 自己实现 x86 的页表
 /// page fault 中断处理函数。
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
+    // 在 page fault 发生时, x86 会自动将出错的地址写入到 CR2 寄存器中。
+    let addr = Cr2::read();
+    // 已保留但还没有分配页帧的区域（VMA）：分配并映射后返回，CPU 会重新执行出错的指令。
+    let reason = match memory::vma::handle_page_fault(addr, error_code) {
+        Ok(()) => return,
+        Err(reason) => reason,
+    };
+    // copy_from_user 等访问用户内存的指令出错：跳到异常修复表中登记的修复代码，由它返回错误。
+    if let Some(fixup) = memory::search_exception_table(stack_frame.instruction_pointer) {
+        unsafe {
+            stack_frame
+                .as_mut()
+                .update(|frame| frame.instruction_pointer = fixup)
+        };
+        return;
+    }
     // 此处能工作的原因：x86强制要求内存模式必须是分页模式，所以在进入内核之前，bootloader 已经将页表激活了。
     // 除了 vga 外，其它目前使用的地址都是虚拟地址。vga 使用了一致映射，即虚拟地址和物理地址是一样的。
     println!("EXCEPTION: PAGE FAULT");
-    // 在 page fault 发生时, x86 会自动将出错的地址写入到 CR2 寄存器中。
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Accessed Address: {:?}", addr);
     println!("Error Code: {:?}", error_code);
+    println!("Reason: {:?}", reason);
+    if memory::is_guard_page(addr) {
+        println!("Kernel stack overflow");
+    }
+    if let Some(symbol) = backtrace::lookup(stack_frame.instruction_pointer) {
+        println!("Faulting Instruction: {}", symbol);
+    }
     println!("{:#?}", stack_frame);
+    backtrace::print();
     hlt_loop();
 }
@@ -18,10 +18,15 @@ use bootloader::entry_point;
 
 pub mod interrupts;
 
+pub mod acpi;
+pub mod backtrace;
+pub mod cmdline;
 pub mod gdt;
 pub mod memory;
 pub mod qemu;
 pub mod serial;
+pub mod test_support;
+pub mod time;
 pub mod vga_buffer;
 // alloc 是标准库的一部分，所以不应该在 Cargo.toml 中添加依赖
 // 但是由于我们是在为一个自定义的目标进行编译，所以不能直接使用标准库中的alloc，所以需要使用 extern crate 语法。（以前所有的依赖都需要 extern crate，现在只在这种情况下需要。）
@@ -52,6 +57,10 @@ fn test_kernel_main(_boot_info: &'static bootloader::BootInfo) -> ! {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    if let Some(task) = task::current_task() {
+        serial_println!("in task {}\n", task);
+    }
+    backtrace::print();
     qemu::exit_qemu(qemu::QemuExitCode::Failed);
     hlt_loop()
 }
@@ -101,6 +110,7 @@ pub fn init() {
         // todo 了解PIC初始化过程
         interrupts::PICS.lock().initialize();
     }
+    time::init();
     // 启动中断。
     x86_64::instructions::interrupts::enable();
 }
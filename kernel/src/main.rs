@@ -13,8 +13,9 @@ use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use kernel::{
-    allocator, memory, println,
+    allocator, interrupts, memory, println,
     task::{executor::Executor, keyboard, simple_executor::SimpleExecutor, Task},
+    time,
 };
 use x86_64::{
     structures::paging::{Page, PageTable, Translate},
@@ -48,9 +49,24 @@ fn kernel_entry(boot_info: &'static BootInfo) -> ! {
     println!("Level 4 page table at: {:?}", level_4_table.start_address());
     println!("flags: {:?}", flags);
     let mut mapper = memory::init(boot_info.physical_memory_offset);
-    let mut frame_allocator =
-        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator);
+    let mut frame_allocator = unsafe {
+        memory::BuddyFrameAllocator::init(&boot_info.memory_map, boot_info.physical_memory_offset)
+    };
+    memory::print_memory_map();
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    // 交给全局管理，之后堆可以按需扩展。
+    memory::init_global(mapper, frame_allocator);
+    // 有 APIC 时从 8259 切换过去。
+    let controller = interrupts::init_controller();
+    println!("interrupt controller: {:?}", controller);
+    // 需要知道中断控制器，才能决定是否使用 Local APIC 定时器。
+    let clock = time::init_clocksource();
+    println!(
+        "clock source: {:?}, tick source: {:?}",
+        clock,
+        time::tick_source()
+    );
+    println!("date: {} UTC", time::init_wall_clock());
     let x = Box::new(1);
     println!("x: {} @ {:p}", x, x);
 
@@ -98,6 +114,10 @@ fn panic(info: &PanicInfo) -> ! {
     #[cfg(not(test))]
     {
         println!("panic: {}", info);
+        if let Some(task) = kernel::task::current_task() {
+            println!("in task {}", task);
+        }
+        kernel::backtrace::print();
         kernel::hlt_loop()
     }
 }
@@ -0,0 +1,491 @@
+//! 地址空间：每个地址空间有自己的 4 级页表，用户区域 [USER_SPACE_START, USER_SPACE_END) 是私有的，其余的 P4 项
+//! 在创建时从内核页表复制，因此所有地址空间共享同一份内核映射（共享的是下级页表，之后在已有 P4 项下新增的内核映射
+//! 对所有地址空间可见）。
+//!
+//! 切换地址空间需要重新加载 CR3。CPU 支持 PCID 时，每个地址空间分配一个 PCID，TLB 项按 PCID 区分，切换时不需要
+//! 清空 TLB；不支持时每次切换都会清空非全局的 TLB 项。
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::tlb::Pcid,
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, MappedFrame, TranslateResult, UnmapError},
+        FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+        PageTableFlags, PageTableIndex, PhysFrame, Size4KiB, Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    kernel_level_4_frame, phys_offset, phys_to_virt, try_with_kernel_memory,
+    vma::{self, FaultError, Vma, VmaError, VmaKind},
+    with_kernel_memory,
+};
+
+/// 用户区域的起始地址，对应 P4 的第 32 项。低于它的部分留给内核镜像和 bootloader 的映射。
+pub const USER_SPACE_START: u64 = 0x0000_1000_0000_0000;
+/// 用户区域的结束地址（不含），对应 P4 的第 128 项。
+pub const USER_SPACE_END: u64 = 0x0000_4000_0000_0000;
+
+/// 用户区域在 P4 中的下标范围。
+const USER_P4_START: usize = (USER_SPACE_START >> 39) as usize;
+const USER_P4_END: usize = (USER_SPACE_END >> 39) as usize;
+
+/// 页表项中由软件使用的位：置位表示页帧由 map 分配，属于这个地址空间，unmap 或销毁时需要释放。
+pub(super) const OWNED: PageTableFlags = PageTableFlags::BIT_9;
+/// 写时复制：页帧被多个地址空间共享，页表项是只读的，写入时复制出私有的页帧再恢复写权限。
+pub const COW: PageTableFlags = PageTableFlags::BIT_10;
+
+/// CR3 的第 63 位：启用 PCID 时，加载 CR3 不清空该 PCID 的 TLB 项。
+const CR3_NOFLUSH: u64 = 1 << 63;
+
+const PCID_WORDS: usize = 4096 / 64;
+/// PCID 0 留给内核页表，其余按位图分配。
+static PCIDS: Mutex<[u64; PCID_WORDS]> = Mutex::new({
+    let mut pcids = [0; PCID_WORDS];
+    pcids[0] = 1;
+    pcids
+});
+/// 是否已经启用 CR4.PCIDE。
+static PCID_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// CPU 支持 PCID 时启用它。必须在 CR3 的低 12 位为 0 时调用（即还没有使用过 PCID）。
+pub(super) fn init_pcid() {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    // CPUID.01H:ECX 的第 17 位表示支持 PCID。较新的编译器中 __cpuid 不再是 unsafe 的。
+    #[allow(unused_unsafe)]
+    let supported = unsafe { core::arch::x86_64::__cpuid(1) }.ecx & (1 << 17) != 0;
+    if supported && Cr3::read_raw().1 == 0 {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::PCID)) };
+        PCID_ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// 清空所有 PCID 的 TLB 项（包括全局页），取消内核映射后需要调用，因为其他地址空间的 TLB 中也可能缓存着它。
+pub fn flush_all() {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    if !PCID_ENABLED.load(Ordering::Relaxed) {
+        x86_64::instructions::tlb::flush_all();
+        return;
+    }
+    // 修改 CR4.PGE 会清空所有 PCID 的 TLB 项。
+    unsafe {
+        Cr4::update(|flags| flags.toggle(Cr4Flags::PAGE_GLOBAL));
+        Cr4::update(|flags| flags.toggle(Cr4Flags::PAGE_GLOBAL));
+    }
+}
+
+fn alloc_pcid() -> Option<Pcid> {
+    if !PCID_ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut pcids = PCIDS.lock();
+    let (word, bits) = pcids
+        .iter_mut()
+        .enumerate()
+        .find(|(_, w)| **w != u64::MAX)?;
+    let bit = bits.trailing_ones() as usize;
+    *bits |= 1 << bit;
+    Pcid::new((word * 64 + bit) as u16).ok()
+}
+
+fn free_pcid(pcid: Pcid) {
+    let value = pcid.value() as usize;
+    PCIDS.lock()[value / 64] &= !(1 << (value % 64));
+}
+
+/// 判断页是否在用户区域内。
+fn is_user_page(page: Page) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&page.start_address().as_u64())
+}
+
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    pcid: Option<Pcid>,
+    /// 不活跃时修改了页表，TLB 中可能还有这个 PCID 的旧项，下次切换时需要清空。
+    needs_flush: AtomicBool,
+}
+
+impl AddressSpace {
+    /// 创建新的地址空间：分配一个 4 级页表，复制内核的 P4 项，用户区域为空。
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        let frame = with_kernel_memory(|mem| mem.frame_allocator.allocate_frame())
+            .flatten()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let table = unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() };
+        let kernel_table =
+            unsafe { &*phys_to_virt(kernel_level_4_frame().start_address()).as_ptr::<PageTable>() };
+        for (index, entry) in table.iter_mut().enumerate() {
+            if (USER_P4_START..USER_P4_END).contains(&index) {
+                assert!(
+                    kernel_table[index].is_unused(),
+                    "kernel mapping in user space P4 entry {}",
+                    index
+                );
+                entry.set_unused();
+            } else {
+                *entry = kernel_table[index].clone();
+            }
+        }
+        Ok(Self {
+            level_4_frame: frame,
+            pcid: alloc_pcid(),
+            needs_flush: AtomicBool::new(true),
+        })
+    }
+
+    /// 4 级页表所在的页帧，即加载到 CR3 中的值。
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    pub fn pcid(&self) -> Option<Pcid> {
+        self.pcid
+    }
+
+    /// 是否是当前 CR3 指向的地址空间。
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// # Safety
+    /// 返回的 mapper 不能与同一地址空间的其他 mapper 同时使用。
+    unsafe fn mapper(&self) -> OffsetPageTable<'static> {
+        let table = &mut *phys_to_virt(self.level_4_frame.start_address()).as_mut_ptr();
+        OffsetPageTable::new(table, VirtAddr::new(phys_offset()))
+    }
+
+    /// 修改了页表项后刷新 TLB：活跃的地址空间直接刷新这一页，否则等到下次切换时清空。
+    fn flush(&self, page: Page) {
+        if self.is_active() {
+            x86_64::instructions::tlb::flush(page.start_address());
+        } else {
+            self.needs_flush.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// 分配一个新的页帧并映射到 page，页帧归这个地址空间所有。page 必须在用户区域内。
+    pub fn map(&mut self, page: Page, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+        assert!(is_user_page(page), "{:?} is not in user space", page);
+        with_kernel_memory(|mem| {
+            let frame = mem
+                .frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let result = unsafe {
+                self.mapper()
+                    .map_to(page, frame, flags | OWNED, &mut mem.frame_allocator)
+            };
+            match result {
+                Ok(flush) => {
+                    flush.ignore();
+                    Ok(())
+                }
+                Err(err) => {
+                    unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                    Err(err)
+                }
+            }
+        })
+        .unwrap_or(Err(MapToError::FrameAllocationFailed))?;
+        self.flush(page);
+        Ok(())
+    }
+
+    /// 将已有的页帧映射到 page，unmap 时不会释放这个页帧。page 必须在用户区域内。
+    /// # Safety
+    /// 调用者需要保证页帧在映射期间有效，并且不会因为新的映射产生别名问题。
+    pub unsafe fn map_to(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert!(is_user_page(page), "{:?} is not in user space", page);
+        with_kernel_memory(|mem| {
+            self.mapper()
+                .map_to(page, frame, flags - OWNED, &mut mem.frame_allocator)
+                .map(|flush| flush.ignore())
+        })
+        .unwrap_or(Err(MapToError::FrameAllocationFailed))?;
+        self.flush(page);
+        Ok(())
+    }
+
+    /// 取消映射。由 map 分配的页帧会被释放，返回的页帧不能再使用；由 map_to 映射的页帧则交还给调用者。
+    pub fn unmap(&mut self, page: Page) -> Result<PhysFrame, UnmapError> {
+        assert!(is_user_page(page), "{:?} is not in user space", page);
+        let owned = match unsafe { self.mapper() }.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags.contains(OWNED),
+            _ => return Err(UnmapError::PageNotMapped),
+        };
+        let (frame, flush) = unsafe { self.mapper() }.unmap(page)?;
+        flush.ignore();
+        self.flush(page);
+        if owned {
+            with_kernel_memory(|mem| unsafe { mem.frame_allocator.deallocate_frame(frame) });
+        }
+        Ok(frame)
+    }
+
+    /// 保留 [start, start + size) 作为按需分页的区域，页在第一次访问时才分配，归这个地址空间所有。
+    pub fn add_vma(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+        kind: VmaKind,
+    ) -> Result<(), VmaError> {
+        vma::register_user_vma(self.level_4_frame, start, size, flags, kind)
+    }
+
+    /// 移除以 start 开始的区域，并取消其中已经分配的页的映射。
+    pub fn remove_vma(&mut self, start: VirtAddr) -> Option<Vma> {
+        let vma = vma::unregister_user_vma(self.level_4_frame, start)?;
+        for page in vma.pages() {
+            let _ = self.unmap(page);
+        }
+        Some(vma)
+    }
+
+    /// 修改页的访问权限。
+    pub fn protect(&mut self, page: Page, flags: PageTableFlags) -> Result<(), FlagUpdateError> {
+        assert!(is_user_page(page), "{:?} is not in user space", page);
+        let mut mapper = unsafe { self.mapper() };
+        let (frame, old) = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { frame, flags, .. } => {
+                (PhysFrame::containing_address(frame.start_address()), flags)
+            }
+            _ => return Err(FlagUpdateError::PageNotMapped),
+        };
+        let mut flags = (flags - OWNED - COW) | (old & OWNED);
+        // 共享的页帧不能直接变成可写，保留写时复制，第一次写入时再复制。
+        let shared = old.contains(OWNED)
+            && with_kernel_memory(|mem| mem.frame_allocator.ref_count(frame) > 1).unwrap_or(false);
+        if shared && flags.contains(PageTableFlags::WRITABLE) {
+            flags = (flags - PageTableFlags::WRITABLE) | COW;
+        }
+        unsafe { mapper.update_flags(page, flags)? }.ignore();
+        self.flush(page);
+        Ok(())
+    }
+
+    /// 创建这个地址空间的写时复制副本。用户区域的页表被复制，map 分配的页帧由两个地址空间共享，可写的页在双方都
+    /// 变成只读，任何一方写入时才复制出私有的页帧；map_to 映射的页帧直接共享。用户 VMA 也会被复制，其中还没有
+    /// 访问过的页在副本中同样按需分配。
+    pub fn fork(&mut self) -> Result<AddressSpace, MapToError<Size4KiB>> {
+        let child = AddressSpace::new()?;
+        let mut child_mapper = unsafe { child.mapper() };
+        let table = |frame: PhysFrame| unsafe {
+            &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>()
+        };
+        // 失败时 child 被销毁，已经共享的页帧会减少引用计数，父地址空间中残留的 COW 标志只会在写入时多一次 page fault。
+        with_kernel_memory(|mem| {
+            let level_4 = table(self.level_4_frame);
+            for p4 in USER_P4_START..USER_P4_END {
+                let p3_frame = match level_4[p4].frame() {
+                    Ok(frame) => frame,
+                    Err(_) => continue,
+                };
+                for (p3, p3_entry) in table(p3_frame).iter().enumerate() {
+                    let p2_frame = match p3_entry.frame() {
+                        Ok(frame) => frame,
+                        Err(_) => continue,
+                    };
+                    for (p2, p2_entry) in table(p2_frame).iter().enumerate() {
+                        let p1_frame = match p2_entry.frame() {
+                            Ok(frame) => frame,
+                            Err(_) => continue,
+                        };
+                        for (p1, p1_entry) in table(p1_frame).iter_mut().enumerate() {
+                            let mut flags = p1_entry.flags();
+                            if !flags.contains(PageTableFlags::PRESENT) {
+                                continue;
+                            }
+                            let frame = PhysFrame::<Size4KiB>::containing_address(p1_entry.addr());
+                            if flags.contains(OWNED) {
+                                if flags.contains(PageTableFlags::WRITABLE) {
+                                    flags = (flags - PageTableFlags::WRITABLE) | COW;
+                                    p1_entry.set_flags(flags);
+                                }
+                                mem.frame_allocator.add_ref(frame);
+                            }
+                            let page = Page::from_page_table_indices(
+                                PageTableIndex::new(p4 as u16),
+                                PageTableIndex::new(p3 as u16),
+                                PageTableIndex::new(p2 as u16),
+                                PageTableIndex::new(p1 as u16),
+                            );
+                            unsafe {
+                                child_mapper
+                                    .map_to(page, frame, flags, &mut mem.frame_allocator)?
+                                    .ignore();
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+        .unwrap_or(Err(MapToError::FrameAllocationFailed))?;
+        // 父地址空间中的页变成了只读，TLB 中可能还有可写的旧项。活跃时通过 switch 重新加载 CR3，直接写回 Cr3::read()
+        // 的结果会丢掉其中的 PCID。
+        self.needs_flush.store(true, Ordering::Relaxed);
+        if self.is_active() {
+            unsafe { self.switch() };
+        }
+        vma::copy_user_vmas(self.level_4_frame, child.level_4_frame)
+            .map_err(|_| MapToError::FrameAllocationFailed)?;
+        Ok(child)
+    }
+
+    /// 在这个地址空间中将虚拟地址转换为物理地址。
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        unsafe { self.mapper() }.translate_addr(addr)
+    }
+
+    /// 切换到这个地址空间。
+    /// # Safety
+    /// 调用者需要保证当前正在使用的内存（栈、代码等）在新的地址空间中仍然有效，内核区域总是满足这一点。
+    pub unsafe fn switch(&self) {
+        let frame = self.level_4_frame.start_address().as_u64();
+        match self.pcid {
+            Some(pcid) => {
+                let mut value = frame | pcid.value() as u64;
+                if !self.needs_flush.swap(false, Ordering::Relaxed) {
+                    value |= CR3_NOFLUSH;
+                }
+                asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+            }
+            None => {
+                self.needs_flush.store(false, Ordering::Relaxed);
+                Cr3::write(self.level_4_frame, Cr3Flags::empty());
+            }
+        }
+    }
+}
+
+/// 处理写时复制页上的写入：页帧仍被共享时复制一份私有的页帧，否则直接恢复写权限。
+/// 不是写时复制页时返回 None，由调用者按普通的权限错误处理。
+pub(super) fn resolve_cow(
+    level_4_frame: PhysFrame,
+    page: Page,
+    user_mode: bool,
+) -> Option<Result<(), FaultError>> {
+    let table = unsafe { &mut *phys_to_virt(level_4_frame.start_address()).as_mut_ptr() };
+    let mut mapper = unsafe { OffsetPageTable::new(table, VirtAddr::new(phys_offset())) };
+    let (frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } if flags.contains(COW) => (frame, flags),
+        _ => return None,
+    };
+    if user_mode && !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+        return None;
+    }
+    let flags = (flags - COW) | PageTableFlags::WRITABLE;
+    let result = try_with_kernel_memory(|mem| {
+        if mem.frame_allocator.ref_count(frame) > 1 {
+            let copy = mem
+                .frame_allocator
+                .allocate_frame()
+                .ok_or(FaultError::OutOfMemory)?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+                    phys_to_virt(copy.start_address()).as_mut_ptr::<u8>(),
+                    Size4KiB::SIZE as usize,
+                );
+                // 页表已经存在，重新映射不会分配新的页表。
+                mapper
+                    .unmap(page)
+                    .map_err(|_| FaultError::ProtectionViolation)?
+                    .1
+                    .ignore();
+                mapper
+                    .map_to(page, copy, flags, &mut mem.frame_allocator)
+                    .map_err(|_| FaultError::OutOfMemory)?
+                    .ignore();
+                // 释放这个地址空间对共享页帧的引用。
+                mem.frame_allocator.deallocate_frame(frame);
+            }
+        } else {
+            // 其他所有者都已经复制或释放了，不需要再复制。
+            unsafe { mapper.update_flags(page, flags) }
+                .map_err(|_| FaultError::ProtectionViolation)?
+                .ignore();
+        }
+        x86_64::instructions::tlb::flush(page.start_address());
+        Ok(())
+    })
+    .unwrap_or(Err(FaultError::Busy));
+    Some(result)
+}
+
+/// 切换回内核页表。
+/// # Safety
+/// 与 [`AddressSpace::switch`] 相同。
+pub unsafe fn switch_to_kernel() {
+    // 内核页表使用 PCID 0，用户区域为空，所以没有需要清空的用户映射。
+    let mut value = kernel_level_4_frame().start_address().as_u64();
+    if PCID_ENABLED.load(Ordering::Relaxed) {
+        value |= CR3_NOFLUSH;
+    }
+    asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+impl Drop for AddressSpace {
+    /// 释放用户区域的页表和 map 分配的页帧。内核区域的页表是共享的，不能释放。
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+        vma::unregister_user_vmas(self.level_4_frame);
+        let table = |frame: PhysFrame| unsafe {
+            &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>()
+        };
+        with_kernel_memory(|mem| {
+            let level_4 = table(self.level_4_frame);
+            for p4_entry in level_4.iter().take(USER_P4_END).skip(USER_P4_START) {
+                let p3_frame = match p4_entry.frame() {
+                    Ok(frame) => frame,
+                    Err(_) => continue,
+                };
+                for p3_entry in table(p3_frame).iter() {
+                    let p2_frame = match p3_entry.frame() {
+                        Ok(frame) => frame,
+                        Err(_) => continue,
+                    };
+                    for p2_entry in table(p2_frame).iter() {
+                        let p1_frame = match p2_entry.frame() {
+                            Ok(frame) => frame,
+                            Err(_) => continue,
+                        };
+                        for p1_entry in table(p1_frame).iter() {
+                            if p1_entry.flags().contains(PageTableFlags::PRESENT | OWNED) {
+                                let frame =
+                                    PhysFrame::<Size4KiB>::containing_address(p1_entry.addr());
+                                unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                            }
+                        }
+                        unsafe { mem.frame_allocator.deallocate_frame(p1_frame) };
+                    }
+                    unsafe { mem.frame_allocator.deallocate_frame(p2_frame) };
+                }
+                unsafe { mem.frame_allocator.deallocate_frame(p3_frame) };
+            }
+            unsafe { mem.frame_allocator.deallocate_frame(self.level_4_frame) };
+        });
+        if let Some(pcid) = self.pcid {
+            free_pcid(pcid);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! 伙伴系统（buddy system）物理页帧分配器。
+//! 物理内存被划分为大小为 2^order 个页帧的块，每个 order 维护一个空闲链表：
+//! 1. 分配：从能满足请求的最小 order 开始找空闲块，找到的块如果比请求的大，就不断一分为二，高半部分放回低一级的链表。
+//! 2. 释放：检查伙伴块（块地址异或块大小）是否空闲，空闲则摘下来合并成更大的块，直到无法合并或达到 MAX_ORDER。
+//! 分配和释放最多拆分/合并 MAX_ORDER 次，即 O(log n)。
+//!
+//! 空闲链表的节点直接写在空闲页帧内（通过物理内存偏移映射访问），因此不依赖堆。
+//! 另外使用一个位图记录每个 order 上哪些块在空闲链表中，释放时可以 O(1) 判断伙伴是否空闲。位图本身在初始化时从
+//! 第一个足够大的可用区域头部划出。
+//!
+//! 单个页帧可以被多个所有者共享（例如写时复制），每个页帧有一个引用计数，与位图放在一起。释放共享的页帧只会减少
+//! 引用计数，最后一个所有者释放时才真正回到空闲链表。
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::{
+    structures::paging::{
+        frame::PhysFrameRange, FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB,
+        Size4KiB,
+    },
+    PhysAddr,
+};
+
+use super::phys_map;
+use crate::allocator::align_up;
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+/// 最大块为 2^MAX_ORDER 个页帧，即 4 MiB。
+pub const MAX_ORDER: usize = 10;
+/// 2 MiB 大页对应的 order。
+const HUGE_ORDER: usize = 9;
+const ORDER_COUNT: usize = MAX_ORDER + 1;
+
+/// 空闲块头部的链表节点。使用双向链表，合并时可以直接摘除伙伴块，不需要遍历链表。
+struct FreeBlock {
+    prev: Option<PhysAddr>,
+    next: Option<PhysAddr>,
+}
+
+pub struct BuddyFrameAllocator {
+    /// 物理内存在虚拟地址空间中的偏移，用于读写空闲块中的链表节点。
+    phys_offset: u64,
+    /// 管理的页帧数量，从物理地址 0 开始计算，到最高的可用地址为止。
+    frame_count: usize,
+    /// 每个 order 的空闲链表头部。
+    free_lists: [Option<PhysAddr>; ORDER_COUNT],
+    /// 空闲位图，每个 order 占一段，置 1 表示对应的块在空闲链表中。
+    bitmap: &'static mut [u64],
+    /// 每个 order 在位图中的起始位。
+    bitmap_offsets: [usize; ORDER_COUNT],
+    /// 每个页帧除第一个所有者之外的引用数，只对单个 4 KiB 页帧有意义。
+    refs: &'static mut [u16],
+    /// 当前空闲的页帧数。
+    free_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// 根据 bootloader 提供的内存映射表创建分配器，所有 Usable 区域中没有被保留（见 [`phys_map::reserve`]）的部分
+    /// 都会加入空闲链表。
+    /// # Safety
+    /// 调用者需要保证 memory_map 有效，Usable 区域确实未被使用，并且整个物理内存已经映射到 phys_offset 处。
+    pub unsafe fn init(memory_map: &'static MemoryMap, phys_offset: u64) -> Self {
+        phys_map::init(memory_map);
+        let usable = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+        let max_addr = usable().map(|r| r.range.end_addr()).max().unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+
+        // order 为 k 时共有 frame_count >> k 个块，多留一位避免边界处理。
+        let mut bitmap_offsets = [0; ORDER_COUNT];
+        let mut bits = 0;
+        for (order, offset) in bitmap_offsets.iter_mut().enumerate() {
+            *offset = bits;
+            bits += (frame_count >> order) + 1;
+        }
+        let words = (bits + 63) / 64;
+        // 位图之后紧跟着引用计数数组。
+        let bitmap_bytes = align_up(words * 8 + frame_count * 2, FRAME_SIZE as usize) as u64;
+        let mut bitmap_start = None;
+        for region in usable() {
+            let (start, end) = (region.range.start_addr(), region.range.end_addr());
+            phys_map::for_each_unreserved(start, end, |s, e| {
+                if bitmap_start.is_none() && e - s >= bitmap_bytes {
+                    bitmap_start = Some(s);
+                }
+            });
+        }
+        let bitmap_start =
+            bitmap_start.expect("no usable region large enough for the frame bitmap");
+        let bitmap =
+            core::slice::from_raw_parts_mut((phys_offset + bitmap_start) as *mut u64, words);
+        bitmap.fill(0);
+        let refs = core::slice::from_raw_parts_mut(
+            (phys_offset + bitmap_start + words as u64 * 8) as *mut u16,
+            frame_count,
+        );
+        refs.fill(0);
+
+        let mut allocator = Self {
+            phys_offset,
+            frame_count,
+            free_lists: [None; ORDER_COUNT],
+            bitmap,
+            bitmap_offsets,
+            refs,
+            free_frames: 0,
+        };
+        for region in usable() {
+            let (start, end) = (region.range.start_addr(), region.range.end_addr());
+            phys_map::for_each_unreserved(start, end, |mut s, e| {
+                if s == bitmap_start {
+                    // 跳过位图占用的页帧。
+                    s += bitmap_bytes;
+                }
+                allocator.free_range(s, e);
+            });
+        }
+        allocator
+    }
+
+    /// 当前空闲的页帧数。
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// [start, end) 中的页帧是否都是空闲的。
+    pub fn is_range_free(&self, start: PhysAddr, end: PhysAddr) -> bool {
+        (start.as_u64()..end.as_u64())
+            .step_by(FRAME_SIZE as usize)
+            .all(|addr| self.free_block_containing(PhysAddr::new(addr)).is_some())
+    }
+
+    /// 将 [start, end) 中空闲的页帧从空闲链表中移除，之后它们不会被分配，也不应该被释放。用于保留物理内存。
+    pub fn take_range(&mut self, start: PhysAddr, end: PhysAddr) {
+        for addr in (start.as_u64()..end.as_u64()).step_by(FRAME_SIZE as usize) {
+            let addr = PhysAddr::new(addr);
+            let (mut block, mut order) = match self.free_block_containing(addr) {
+                Some(found) => found,
+                None => continue,
+            };
+            // 摘下包含这个页帧的空闲块，拆分后不包含它的一半放回空闲链表。
+            self.remove(block, order);
+            self.free_frames -= 1 << order;
+            while order > 0 {
+                order -= 1;
+                let high = block + block_size(order);
+                if addr >= high {
+                    self.push(block, order);
+                    block = high;
+                } else {
+                    self.push(high, order);
+                }
+                self.free_frames += 1 << order;
+            }
+        }
+    }
+
+    /// 包含 addr 的空闲块的地址和 order。
+    fn free_block_containing(&self, addr: PhysAddr) -> Option<(PhysAddr, usize)> {
+        (0..ORDER_COUNT)
+            .map(|order| (addr.align_down(block_size(order)), order))
+            .find(|&(block, order)| self.is_free(block, order))
+    }
+
+    /// 为已分配的页帧增加一个所有者，之后每个所有者都需要调用一次 deallocate_frame。不由分配器管理的页帧（例如
+    /// MMIO）没有引用计数，什么也不做。
+    pub fn add_ref(&mut self, frame: PhysFrame) {
+        if let Some(index) = self.frame_index(frame) {
+            let refs = &mut self.refs[index];
+            *refs = refs.checked_add(1).expect("frame reference count overflow");
+        }
+    }
+
+    /// 已分配的页帧的所有者数量，不由分配器管理的页帧返回 0。
+    pub fn ref_count(&self, frame: PhysFrame) -> usize {
+        self.frame_index(frame)
+            .map_or(0, |index| self.refs[index] as usize + 1)
+    }
+
+    /// 页帧在 refs 中的下标，超出分配器管理范围时返回 None。
+    fn frame_index(&self, frame: PhysFrame) -> Option<usize> {
+        let index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        (index < self.frame_count).then_some(index)
+    }
+
+    /// 分配 count 个物理上连续的页帧，起始地址按 2^order 个页帧对齐，适用于 DMA 缓冲区等场景。
+    /// 最多可以分配 2^MAX_ORDER 个页帧；多分配出来的尾部页帧会立即归还。
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrameRange> {
+        if count == 0 {
+            return None;
+        }
+        let order = count.next_power_of_two().trailing_zeros() as usize;
+        if order > MAX_ORDER {
+            return None;
+        }
+        let start = self.alloc_block(order)?;
+        let end = start + count as u64 * FRAME_SIZE;
+        self.free_range(end.as_u64(), (start + block_size(order)).as_u64());
+        Some(PhysFrame::range(
+            PhysFrame::containing_address(start),
+            PhysFrame::containing_address(end),
+        ))
+    }
+
+    /// 释放 allocate_contiguous 分配的页帧。
+    /// # Safety
+    /// 调用者需要保证这些页帧已经不再使用。
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        self.free_range(
+            range.start.start_address().as_u64(),
+            range.end.start_address().as_u64(),
+        );
+    }
+
+    /// 分配一个 2^order 个页帧的块。
+    fn alloc_block(&mut self, order: usize) -> Option<PhysAddr> {
+        let mut current = (order..ORDER_COUNT).find(|&o| self.free_lists[o].is_some())?;
+        let addr = self.free_lists[current]?;
+        self.remove(addr, current);
+        while current > order {
+            // 拆分：低半部分继续使用，高半部分放回低一级的空闲链表。
+            current -= 1;
+            self.push(addr + block_size(current), current);
+        }
+        self.free_frames -= 1 << order;
+        Some(addr)
+    }
+
+    /// 释放一个 2^order 个页帧的块，并尽可能与伙伴块合并。
+    fn free_block(&mut self, mut addr: PhysAddr, mut order: usize) {
+        debug_assert!(!self.is_free(addr, order), "double free of {:?}", addr);
+        self.free_frames += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = PhysAddr::new(addr.as_u64() ^ block_size(order));
+            if !self.is_free(buddy, order) {
+                break;
+            }
+            self.remove(buddy, order);
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push(addr, order);
+    }
+
+    /// 将 [start, end) 按最大的对齐块释放。
+    fn free_range(&mut self, mut start: u64, end: u64) {
+        while start < end {
+            let mut order = MAX_ORDER;
+            while order > 0 && (start % block_size(order) != 0 || start + block_size(order) > end) {
+                order -= 1;
+            }
+            self.free_block(PhysAddr::new(start), order);
+            start += block_size(order);
+        }
+    }
+
+    fn node(&self, addr: PhysAddr) -> *mut FreeBlock {
+        (self.phys_offset + addr.as_u64()) as *mut FreeBlock
+    }
+
+    /// 插入到空闲链表头部。
+    fn push(&mut self, addr: PhysAddr, order: usize) {
+        let head = self.free_lists[order];
+        unsafe {
+            self.node(addr).write(FreeBlock {
+                prev: None,
+                next: head,
+            });
+            if let Some(head) = head {
+                (*self.node(head)).prev = Some(addr);
+            }
+        }
+        self.free_lists[order] = Some(addr);
+        self.set_free(addr, order, true);
+    }
+
+    /// 从空闲链表中摘除。
+    fn remove(&mut self, addr: PhysAddr, order: usize) {
+        let (prev, next) = unsafe {
+            let node = &*self.node(addr);
+            (node.prev, node.next)
+        };
+        match prev {
+            Some(prev) => unsafe { (*self.node(prev)).next = next },
+            None => self.free_lists[order] = next,
+        }
+        if let Some(next) = next {
+            unsafe { (*self.node(next)).prev = prev };
+        }
+        self.set_free(addr, order, false);
+    }
+
+    fn bit_index(&self, addr: PhysAddr, order: usize) -> Option<usize> {
+        let frame = (addr.as_u64() / FRAME_SIZE) as usize;
+        if frame >= self.frame_count {
+            return None;
+        }
+        Some(self.bitmap_offsets[order] + (frame >> order))
+    }
+
+    fn is_free(&self, addr: PhysAddr, order: usize) -> bool {
+        self.bit_index(addr, order)
+            .map_or(false, |i| self.bitmap[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    fn set_free(&mut self, addr: PhysAddr, order: usize, free: bool) {
+        if let Some(i) = self.bit_index(addr, order) {
+            if free {
+                self.bitmap[i / 64] |= 1 << (i % 64);
+            } else {
+                self.bitmap[i / 64] &= !(1 << (i % 64));
+            }
+        }
+    }
+}
+
+/// 2^order 个页帧的字节数。
+const fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.alloc_block(0).map(PhysFrame::containing_address)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    /// 释放一个引用，没有其他所有者时才真正释放页帧。不由分配器管理的页帧被忽略。
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = match self.frame_index(frame) {
+            Some(index) => index,
+            None => return,
+        };
+        let refs = &mut self.refs[index];
+        if *refs > 0 {
+            *refs -= 1;
+            return;
+        }
+        self.free_block(frame.start_address(), 0)
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        self.alloc_block(HUGE_ORDER)
+            .map(PhysFrame::containing_address)
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        self.free_block(frame.start_address(), HUGE_ORDER)
+    }
+}
@@ -0,0 +1,320 @@
+mod address_space;
+mod buddy;
+mod phys_map;
+mod protect;
+mod stack;
+mod uaccess;
+pub mod vma;
+mod vmalloc;
+
+pub use address_space::{
+    flush_all, switch_to_kernel, AddressSpace, COW, USER_SPACE_END, USER_SPACE_START,
+};
+pub use buddy::BuddyFrameAllocator;
+pub use phys_map::{
+    find_reservation, print_memory_map, region_type, reserve as reserve_phys, Reservation,
+    ReserveError,
+};
+pub use protect::KernelSection;
+pub use stack::{is_guard_page, KernelStack, KERNEL_STACKS_END, KERNEL_STACKS_START};
+pub use uaccess::{
+    copy_from_user, copy_to_user, search_exception_table, smap_enabled, smep_enabled,
+    UserAccessError,
+};
+pub use vmalloc::{
+    ioremap, iounmap, vfree, vmalloc, vmap, vunmap, CacheMode, VmallocError, VMALLOC_END,
+    VMALLOC_START,
+};
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, page_table::PageTableEntry, FrameAllocator, FrameDeallocator, Mapper,
+        OffsetPageTable, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+        Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// 通过当前页表的起始偏移，获取当前进程（或内核）的页表指针（虚拟地址）。
+fn active_level_4_table(physical_address_offset: u64) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+    // Cr3 是 x86 存放当前页表地址的寄存器，它的值是当前页表的物理地址
+    let (level_4_table_frame, _) = Cr3::read();
+    // 由于 CPU 都是使用的虚拟地址，而页表又需要物理地址（如果是虚拟地址，则会陷入地址转换的循环）。如果直接采用
+    // 一致映射，则不同进程的页表会冲突。所以页表部分地址映射采用偏移的方法，一方面使CPU能够正确访问页表，另一方面
+    // 防止不同进程的页表冲突。
+    let phys = level_4_table_frame.start_address();
+    // 寄存器内当前页表的物理地址加上固定的偏移，即为虚拟地址。
+    let virt = VirtAddr::new(physical_address_offset + phys.as_u64());
+    // 此时的虚拟地址指向的就是 4级页表的地址。
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+    unsafe { &mut *page_table_ptr }
+}
+
+/// 自己实现的地址转换函数，用于将虚拟地址转换为物理地址。支持 2 MiB 和 1 GiB 大页。
+pub fn translate_addr(addr: VirtAddr, phy_addr_offset: u64) -> Option<PhysAddr> {
+    translate_addr_inner(addr, phy_addr_offset)
+}
+
+fn translate_addr_inner(addr: VirtAddr, phy_addr_offset: u64) -> Option<PhysAddr> {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::page_table::FrameError;
+
+    // 从 Cr3 读取当前页表的物理地址
+    let (level_4_table_frame, _) = Cr3::read();
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut frame = level_4_table_frame;
+    for (level, index) in table_indexes.into_iter().enumerate() {
+        // 此时所有页表的虚拟地址=》物理地址的映射方式都是线性的，所以可以直接通过偏移计算出虚拟地址。
+        let virt = VirtAddr::new(phy_addr_offset + frame.start_address().as_u64());
+        let table_ptr: *const PageTable = virt.as_mut_ptr();
+        let table = unsafe { &*table_ptr };
+        let entry = &table[index];
+        // 获取当前 PTE 指向的页帧
+        // PTE 上 12~51 位是页帧的物理地址，共 40 位，指向的是 4KB 的页帧。 对 x86 来说，页大小 12位 + 40 位 = 52 位 地址空间。
+        // x86_64平台仅支持52位物理地址，所以页帧的物理地址最大为 2^52 = 。
+        // x86_64 仅支持48位虚拟地址，所以虚拟地址最大为 2^48 = 256TB（虚拟地址的 offset 是9位）。
+        frame = match entry.frame() {
+            // 正确映射，返回页帧
+            Ok(frame) => frame,
+            // 未映射，返回 None
+            Err(FrameError::FrameNotPresent) => return None,
+            // 大页帧：P3 项指向 1 GiB 的页，P2 项指向 2 MiB 的页，不再有下一级页表。
+            // 此时虚拟地址剩下的低 30 位（或 21 位）都是页内偏移。
+            Err(FrameError::HugeFrame) => {
+                let page_size = match level {
+                    1 => Size1GiB::SIZE,
+                    2 => Size2MiB::SIZE,
+                    // P1 项的第 7 位是 PAT 位（例如写合并的映射），仍然是普通的 4 KiB 页。
+                    3 => return Some(entry.addr() + u64::from(addr.page_offset())),
+                    // P4 项的 HUGE_PAGE 位是保留位，不应该被设置。
+                    _ => return None,
+                };
+                // 大页项的第 12 位是 PAT 位，entry.addr() 会把它当作地址的一部分，需要按页大小向下对齐。
+                let start = entry.addr().align_down(page_size);
+                return Some(start + (addr.as_u64() & (page_size - 1)));
+            }
+        };
+    }
+    // 目标页帧的物理地址 + 目标页帧内偏移 = 目标物理地址
+    Some(frame.start_address() + u64::from(addr.page_offset()))
+}
+
+/// 物理内存映射的偏移。
+static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// 内核页表（启动时 CR3 指向的页表）的物理地址。
+static KERNEL_LEVEL_4: AtomicU64 = AtomicU64::new(0);
+
+pub fn init(phy_addr_offset: u64) -> OffsetPageTable<'static> {
+    use x86_64::registers::control::Cr3;
+
+    PHYS_OFFSET.store(phy_addr_offset, Ordering::Relaxed);
+    KERNEL_LEVEL_4.store(Cr3::read().0.start_address().as_u64(), Ordering::Relaxed);
+    address_space::init_pcid();
+    vmalloc::init_pat();
+    uaccess::init_smep_smap();
+    let level_4_table = active_level_4_table(phy_addr_offset);
+    let mut mapper = unsafe { OffsetPageTable::new(level_4_table, VirtAddr::new(phy_addr_offset)) };
+    protect::protect_kernel(&mut mapper, phy_addr_offset);
+    mapper
+}
+
+/// 物理内存映射的偏移，init 之后有效。
+pub fn phys_offset() -> u64 {
+    PHYS_OFFSET.load(Ordering::Relaxed)
+}
+
+/// 通过物理内存映射访问物理地址。
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(phys_offset() + addr.as_u64())
+}
+
+/// 内核页表所在的页帧。
+pub fn kernel_level_4_frame() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_LEVEL_4.load(Ordering::Relaxed)))
+}
+
+/// 内核全局使用的页表和物理页帧分配器。
+pub struct KernelMemory {
+    pub mapper: OffsetPageTable<'static>,
+    pub frame_allocator: BuddyFrameAllocator,
+}
+
+static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+/// 将页表和页帧分配器交给全局管理，之后堆扩展等需要在任意位置映射内存的功能才可以使用。
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frame_allocator,
+    });
+    // 地址空间只在创建时复制内核的 P4 项，内核栈、vmalloc 和 slab 区域的 P4 项必须提前建立：切换到这些栈、或者在
+    // 中断中访问 ioremap 的寄存器时不能依赖 page fault 补上。
+    with_kernel_memory(|mem| {
+        reserve_kernel_p4_entries(mem, KERNEL_STACKS_START, KERNEL_STACKS_END)?;
+        reserve_kernel_p4_entries(mem, VMALLOC_START, VMALLOC_END)?;
+        reserve_kernel_p4_entries(
+            mem,
+            crate::allocator::SLAB_AREA_START as u64,
+            crate::allocator::SLAB_AREA_END as u64,
+        )?;
+        protect::protect_kernel_alias(mem)
+    })
+    .unwrap()
+    .expect("failed to allocate kernel page tables");
+    // 页表和页帧分配器可用之后，将 IST 换成带保护页的栈。
+    crate::gdt::init_interrupt_stacks();
+}
+
+/// 为内核区域 [start, end) 预先建立 P4 项（分配空的 3 级页表），之后在这里建立的映射对所有地址空间可见。
+pub fn reserve_kernel_p4_entries(
+    mem: &mut KernelMemory,
+    start: u64,
+    end: u64,
+) -> Result<(), MapToError<Size4KiB>> {
+    let first = VirtAddr::new(start).p4_index();
+    let last = VirtAddr::new(end - 1).p4_index();
+    let level_4 = mem.mapper.level_4_table();
+    for index in u16::from(first)..=u16::from(last) {
+        let entry = &mut level_4[index as usize];
+        if !entry.is_unused() {
+            continue;
+        }
+        let frame = mem
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe {
+            phys_to_virt(frame.start_address())
+                .as_mut_ptr::<PageTable>()
+                .write(PageTable::new());
+        }
+        entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+    Ok(())
+}
+
+/// 在持有全局页表和页帧分配器的情况下执行 f，未调用 init_global 时返回 None。
+/// 注意：f 中不能进行堆分配，因为堆扩展时也会获取这把锁。
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> Option<R> {
+    // 关闭中断，防止中断处理程序中再次获取锁导致死锁。
+    x86_64::instructions::interrupts::without_interrupts(|| KERNEL_MEMORY.lock().as_mut().map(f))
+}
+
+/// 与 with_kernel_memory 相同，但锁已被持有时直接返回 None 而不是等待。用于 page fault 处理程序：
+/// 持有锁时发生的 page fault 如果再等待这把锁就会死锁。
+pub fn try_with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        KERNEL_MEMORY.try_lock()?.as_mut().map(f)
+    })
+}
+
+/// 使用 2 MiB 大页映射 [start, start + size)，物理页帧从 frame_allocator 中分配。
+/// 一个大页只占用一个 TLB 项，适合堆这类大块、长期存在的内核区域。start 和 size 必须按 2 MiB 对齐。
+pub fn map_huge_pages<A>(
+    mapper: &mut impl Mapper<Size2MiB>,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut A,
+) -> Result<(), MapToError<Size2MiB>>
+where
+    A: FrameAllocator<Size4KiB> + FrameAllocator<Size2MiB> + FrameDeallocator<Size2MiB>,
+{
+    assert!(start.is_aligned(Size2MiB::SIZE) && size % Size2MiB::SIZE == 0);
+    let start_page = Page::<Size2MiB>::containing_address(start);
+    for page in Page::range(start_page, start_page + size / Size2MiB::SIZE) {
+        let frame = FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // 中间级页表仍然是 4 KiB 的页帧。
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                // 页帧还没有被映射，还给分配器。
+                unsafe { FrameDeallocator::<Size2MiB>::deallocate_frame(frame_allocator, frame) };
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 使用 2 MiB 大页将物理区域 [phys_start, phys_start + size) 映射到 virt_start，例如物理内存窗口。
+/// # Safety
+/// 调用者需要保证这段物理内存可以被映射，并且不会因为新的映射产生别名问题。
+pub unsafe fn map_physical_huge_pages(
+    mapper: &mut impl Mapper<Size2MiB>,
+    phys_start: PhysAddr,
+    virt_start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size2MiB>> {
+    assert!(phys_start.is_aligned(Size2MiB::SIZE) && virt_start.is_aligned(Size2MiB::SIZE));
+    assert!(size % Size2MiB::SIZE == 0);
+    for i in 0..size / Size2MiB::SIZE {
+        let page = Page::<Size2MiB>::containing_address(virt_start + i * Size2MiB::SIZE);
+        let frame = PhysFrame::<Size2MiB>::containing_address(phys_start + i * Size2MiB::SIZE);
+        mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    }
+    Ok(())
+}
+
+pub struct EmptyFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        None
+    }
+}
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+
+/// 用于分配物理内存的 FrameAllocator。
+/// 只能顺序分配、不能回收，仅适合启动早期使用，正常情况下应使用 [`BuddyFrameAllocator`]。
+pub struct BootInfoFrameAllocator {
+    /// 内存映射表, 用于记录内存的起始地址和大小，以及类型。由bootloader(BIOS/UEFI)提供
+    /// MemoryMap 是一个 MemoryRegion 的数组，每个 MemoryRegion 代表一段内存。
+    memory_map: &'static MemoryMap,
+    /// 下一个可用的内存区域
+    next: usize,
+}
+
+impl BootInfoFrameAllocator {
+    /// 创建一个新的 FrameAllocator.
+    /// unsafe 的意义是：memory_map 由调用者提供，必须保证其有效性。
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        Self {
+            memory_map,
+            next: 0,
+        }
+    }
+
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| r.range.start_addr()..r.range.end_addr()) // 转换为 byte_range
+            .flat_map(|r| r.step_by(4096)) // step_by 将iter按步长跳过，这里是按4KB跳过。flat_map 将多个iter合并为一个iter
+            .filter(|&addr| find_reservation(PhysAddr::new(addr)).is_none()) // 跳过保留的物理内存
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr))) // 每4K是一个frame
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // 此方法每次都会生成新的迭代器，不是很高效。更好的方案应该是在初始化时就生成好迭代器，然后在此方法中使用迭代器的 next 方法。
+        // 但是由于 rust 还不支持 struct 属性类型为 impl Trait，所以只能这样写了，除非 named existential types（https://github.com/rust-lang/rfcs/pull/2071） 被 rust 实现。
+        let frame = self.usable_frames().nth(self.next);
+        self.next += 1;
+        frame
+    }
+}
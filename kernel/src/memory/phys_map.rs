@@ -0,0 +1,198 @@
+//! 物理内存映射：记录 bootloader 报告的所有区域（可用内存、内核镜像、页表、boot info、ACPI、保留区域等），
+//! 以及内核在运行时保留的物理地址范围。被保留的范围不会作为空闲页帧分配出去，适合设备内存、固件表和 DMA 缓冲区。
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::{Mutex, Once};
+use x86_64::{instructions::interrupts::without_interrupts, PhysAddr};
+
+use super::with_kernel_memory;
+use crate::println;
+
+const PAGE_SIZE: u64 = 4096;
+/// 最多可以保留的范围数量。
+const MAX_RESERVATIONS: usize = 32;
+
+/// 运行时保留的物理地址范围，start 和 end 按页对齐。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    pub start: PhysAddr,
+    pub end: PhysAddr,
+    /// 保留者的名字，只用于输出。
+    pub owner: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReserveError {
+    /// 长度为 0。
+    Empty,
+    /// 与已有的保留范围重叠。
+    Overlap,
+    /// 范围中有可用内存已经被分配出去了。
+    InUse,
+    /// 页帧分配器已经初始化，但还没有交给 init_global，此时无法从空闲链表中移除页帧。
+    NotReady,
+    /// 保留表已满。
+    TableFull,
+}
+
+struct Reservations {
+    entries: [Option<Reservation>; MAX_RESERVATIONS],
+    /// 页帧分配器是否已经接管了可用内存。之前的保留只需要记录，初始化时会跳过；之后需要从空闲链表中移除。
+    allocator_ready: bool,
+}
+
+static BOOT_MAP: Once<&'static MemoryMap> = Once::new();
+static RESERVATIONS: Mutex<Reservations> = Mutex::new(Reservations {
+    entries: [None; MAX_RESERVATIONS],
+    allocator_ready: false,
+});
+
+/// 记录 bootloader 提供的内存映射表，由页帧分配器初始化时调用，之后的保留需要从分配器中移除页帧。
+pub(super) fn init(memory_map: &'static MemoryMap) {
+    BOOT_MAP.call_once(|| memory_map);
+    without_interrupts(|| RESERVATIONS.lock().allocator_ready = true);
+}
+
+fn boot_map() -> &'static MemoryMap {
+    BOOT_MAP
+        .get()
+        .expect("physical memory map is not initialized")
+}
+
+/// 对 [start, end) 中没有被保留的每一段调用 f，页帧分配器初始化时用它确定哪些页帧是空闲的。
+pub(super) fn for_each_unreserved(start: u64, end: u64, mut f: impl FnMut(u64, u64)) {
+    let mut sorted = without_interrupts(|| RESERVATIONS.lock().entries);
+    sorted.sort_unstable_by_key(|r| r.map_or(u64::MAX, |r| r.start.as_u64()));
+    let mut cursor = start;
+    for r in sorted.iter().flatten() {
+        let (r_start, r_end) = (r.start.as_u64(), r.end.as_u64());
+        if r_end <= cursor || r_start >= end {
+            continue;
+        }
+        if r_start > cursor {
+            f(cursor, r_start);
+        }
+        cursor = r_end;
+    }
+    if cursor < end {
+        f(cursor, end);
+    }
+}
+
+/// 保留物理地址 [start, start + len)（扩展到整页），之后其中的页帧不会被分配。
+/// 在页帧分配器初始化之前保留的范围会在初始化时跳过；之后保留时，范围内的可用内存必须仍然是空闲的。
+pub fn reserve(start: PhysAddr, len: u64, owner: &'static str) -> Result<(), ReserveError> {
+    if len == 0 {
+        return Err(ReserveError::Empty);
+    }
+    let reservation = Reservation {
+        start: start.align_down(PAGE_SIZE),
+        end: (start + len).align_up(PAGE_SIZE),
+        owner,
+    };
+    without_interrupts(|| {
+        let mut reservations = RESERVATIONS.lock();
+        let overlaps = reservations
+            .entries
+            .iter()
+            .flatten()
+            .any(|r| r.start < reservation.end && reservation.start < r.end);
+        if overlaps {
+            return Err(ReserveError::Overlap);
+        }
+        let slot = reservations
+            .entries
+            .iter()
+            .position(|r| r.is_none())
+            .ok_or(ReserveError::TableFull)?;
+        if reservations.allocator_ready {
+            take_usable_frames(reservation)?;
+        }
+        reservations.entries[slot] = Some(reservation);
+        Ok(())
+    })
+}
+
+/// 将保留范围中的可用内存从页帧分配器中移除。设备内存、固件区域等本来就不会被分配，不需要处理。
+fn take_usable_frames(reservation: Reservation) -> Result<(), ReserveError> {
+    let (start, end) = (reservation.start, reservation.end);
+    let usable = || {
+        boot_map()
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| {
+                let s = PhysAddr::new(r.range.start_addr()).max(start);
+                let e = PhysAddr::new(r.range.end_addr()).min(end);
+                (s, e)
+            })
+            .filter(|(s, e)| s < e)
+    };
+    with_kernel_memory(|mem| {
+        if !usable().all(|(s, e)| mem.frame_allocator.is_range_free(s, e)) {
+            return Err(ReserveError::InUse);
+        }
+        for (s, e) in usable() {
+            mem.frame_allocator.take_range(s, e);
+        }
+        Ok(())
+    })
+    .unwrap_or(Err(ReserveError::NotReady))
+}
+
+/// 包含 addr 的保留范围。
+pub fn find_reservation(addr: PhysAddr) -> Option<Reservation> {
+    without_interrupts(|| {
+        RESERVATIONS
+            .lock()
+            .entries
+            .iter()
+            .flatten()
+            .find(|r| r.start <= addr && addr < r.end)
+            .copied()
+    })
+}
+
+/// bootloader 报告的 addr 所在区域的类型。
+pub fn region_type(addr: PhysAddr) -> Option<MemoryRegionType> {
+    boot_map()
+        .iter()
+        .find(|r| r.range.start_addr() <= addr.as_u64() && addr.as_u64() < r.range.end_addr())
+        .map(|r| r.region_type)
+}
+
+/// 以 e820 的格式打印物理内存映射和保留的范围。
+pub fn print_memory_map() {
+    let mut usable = 0;
+    println!("physical memory map:");
+    for region in boot_map().iter() {
+        let (start, end) = (region.range.start_addr(), region.range.end_addr());
+        if start == end {
+            continue;
+        }
+        if region.region_type == MemoryRegionType::Usable {
+            usable += end - start;
+        }
+        println!(
+            "  [mem {:#018x}-{:#018x}] {:?}",
+            start,
+            end - 1,
+            region.region_type
+        );
+    }
+    let reservations = without_interrupts(|| RESERVATIONS.lock().entries);
+    let mut reserved = 0;
+    for r in reservations.iter().flatten() {
+        reserved += r.end - r.start;
+        println!(
+            "  [mem {:#018x}-{:#018x}] reserved by {}",
+            r.start.as_u64(),
+            r.end.as_u64() - 1,
+            r.owner
+        );
+    }
+    println!(
+        "usable: {} KiB, reserved: {} KiB",
+        usable / 1024,
+        reserved / 1024
+    );
+}
@@ -0,0 +1,226 @@
+//! 内核镜像的页表权限（W^X）：代码只读可执行，只读数据不可写不可执行，可写数据不可执行。
+//! 段的边界由链接脚本（arch/x86/linker.ld）导出。同时启用 EFER.NXE（NO_EXECUTE 位生效）和 CR0.WP（内核态写只读页
+//! 也会触发 page fault），之后写代码或者执行数据都会触发 page fault。
+//! bootloader 映射的启动栈也设为不可执行。物理内存映射中内核镜像的别名在 init_global 之后才设为只读，因为需要
+//! 分配页表把覆盖它的大页拆开。
+
+use core::ptr::addr_of;
+
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        page_table::PageTableEntry,
+        FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTable, PageTableFlags,
+        Size2MiB, Size4KiB, Translate,
+    },
+    VirtAddr,
+};
+
+use super::{address_space::flush_all, phys_to_virt, KernelMemory};
+
+extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+}
+
+/// 内核镜像中的段。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelSection {
+    /// .text：代码。
+    Text,
+    /// .rodata 等只读数据。
+    Rodata,
+    /// .data 和 .bss。
+    Data,
+}
+
+impl KernelSection {
+    const ALL: [KernelSection; 3] = [
+        KernelSection::Text,
+        KernelSection::Rodata,
+        KernelSection::Data,
+    ];
+
+    /// 段的地址范围 [start, end)，按页对齐。
+    pub fn range(self) -> (VirtAddr, VirtAddr) {
+        #[allow(unused_unsafe)]
+        let (start, end) = unsafe {
+            match self {
+                KernelSection::Text => (addr_of!(__text_start), addr_of!(__text_end)),
+                KernelSection::Rodata => (addr_of!(__rodata_start), addr_of!(__rodata_end)),
+                KernelSection::Data => (addr_of!(__data_start), addr_of!(__data_end)),
+            }
+        };
+        (VirtAddr::from_ptr(start), VirtAddr::from_ptr(end))
+    }
+
+    /// 段的页表权限。
+    pub fn flags(self) -> PageTableFlags {
+        match self {
+            KernelSection::Text => PageTableFlags::PRESENT,
+            KernelSection::Rodata => PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE,
+            KernelSection::Data => {
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+            }
+        }
+    }
+
+    /// addr 所在的段。
+    pub fn containing(addr: VirtAddr) -> Option<KernelSection> {
+        Self::ALL.into_iter().find(|section| {
+            let (start, end) = section.range();
+            start <= addr && addr < end
+        })
+    }
+}
+
+/// 按段重新设置内核镜像的页表权限，并启用 NXE 和 WP。由 memory::init 调用。
+/// bootloader 用 4 KiB 的页映射内核镜像，所以可以逐页修改。
+pub(super) fn protect_kernel(mapper: &mut OffsetPageTable, phys_offset: u64) {
+    unsafe {
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+    }
+    for section in KernelSection::ALL {
+        let (start, end) = section.range();
+        if start == end {
+            continue;
+        }
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(start),
+            Page::containing_address(end - 1u64) + 1,
+        );
+        for page in pages {
+            // 只修改最后一级页表项，上级页表项保持 bootloader 设置的宽松权限。
+            unsafe { mapper.update_flags(page, section.flags()) }
+                .expect("kernel section is not mapped with 4 KiB pages")
+                .ignore();
+        }
+    }
+    protect_boot_stack(mapper);
+    // 物理内存映射只用于访问数据（页表等），整个 P4 项设为不可执行，下级的大页都会继承。
+    // 物理内存不超过 512 GiB 时只占用一个 P4 项。
+    let (text_start, _) = KernelSection::Text.range();
+    let index = VirtAddr::new(phys_offset).p4_index();
+    if index != text_start.p4_index() {
+        let entry = &mut mapper.level_4_table()[index];
+        if !entry.is_unused() {
+            entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+        }
+    }
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// 给 page 加上 NO_EXECUTE，page 没有用 4 KiB 页映射时返回 false。
+fn set_no_execute(mapper: &mut OffsetPageTable, page: Page) -> bool {
+    let flags = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(_),
+            flags,
+            ..
+        } => flags,
+        _ => return false,
+    };
+    unsafe { mapper.update_flags(page, flags | PageTableFlags::NO_EXECUTE) }
+        .map(|flush| flush.ignore())
+        .is_ok()
+}
+
+/// bootloader 映射的启动栈（也就是当前正在使用的栈）只设置了 PRESENT 和 WRITABLE。栈的下方是不映射的保护页，
+/// 上方也没有映射，所以从当前的栈指针向两边逐页设置，直到遇到没有映射的页。
+fn protect_boot_stack(mapper: &mut OffsetPageTable) {
+    let marker = 0u8;
+    let current = Page::<Size4KiB>::containing_address(VirtAddr::from_ptr(&marker));
+    let mut page = current;
+    while set_no_execute(mapper, page) {
+        page -= 1;
+    }
+    let mut page = current + 1;
+    while set_no_execute(mapper, page) {
+        page += 1;
+    }
+}
+
+/// 物理内存映射中也有内核镜像的一份别名，通过它仍然可以改写代码和只读数据，所以把 .text 和 .rodata 的别名设为
+/// 只读。bootloader 用 2 MiB 的大页映射物理内存，先把覆盖内核镜像的大页拆成 4 KiB 的页。由 init_global 调用。
+pub(super) fn protect_kernel_alias(mem: &mut KernelMemory) -> Result<(), MapToError<Size4KiB>> {
+    for section in [KernelSection::Text, KernelSection::Rodata] {
+        let (start, end) = section.range();
+        if start == end {
+            continue;
+        }
+        let pages = Page::<Size4KiB>::range(
+            Page::containing_address(start),
+            Page::containing_address(end - 1u64) + 1,
+        );
+        for page in pages {
+            let phys = match mem.mapper.translate_addr(page.start_address()) {
+                Some(phys) => phys,
+                None => continue,
+            };
+            let alias = Page::<Size4KiB>::containing_address(phys_to_virt(phys));
+            split_huge_page(mem, alias)?;
+            if let TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(_),
+                flags,
+                ..
+            } = mem.mapper.translate(alias.start_address())
+            {
+                let flags = flags - PageTableFlags::WRITABLE;
+                if let Ok(flush) = unsafe { mem.mapper.update_flags(alias, flags) } {
+                    flush.ignore();
+                }
+            }
+        }
+    }
+    flush_all();
+    Ok(())
+}
+
+/// page 位于 2 MiB 的大页中时，把大页拆成 512 个权限相同的 4 KiB 页。1 GiB 的大页保持不变。
+fn split_huge_page(mem: &mut KernelMemory, page: Page) -> Result<(), MapToError<Size4KiB>> {
+    /// 下一级页表。项没有使用或者是大页时返回 None。
+    fn next_table(entry: &mut PageTableEntry) -> Option<&'static mut PageTable> {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        Some(unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr() })
+    }
+
+    let addr = page.start_address();
+    let p4 = mem.mapper.level_4_table();
+    let p3 = match next_table(&mut p4[addr.p4_index()]) {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+    let p2 = match next_table(&mut p3[addr.p3_index()]) {
+        Some(table) => table,
+        None => return Ok(()),
+    };
+    let entry = &mut p2[addr.p2_index()];
+    if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return Ok(());
+    }
+    let frame = mem
+        .frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let table = unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr::<PageTable>() };
+    // 2 MiB 页表项的第 12 位是 PAT 位，不属于地址。
+    let start = entry.addr().align_down(Size2MiB::SIZE);
+    let flags = entry.flags() - PageTableFlags::HUGE_PAGE;
+    for (i, pte) in table.iter_mut().enumerate() {
+        pte.set_addr(start + i as u64 * Size4KiB::SIZE, flags);
+    }
+    entry.set_frame(frame, flags);
+    Ok(())
+}
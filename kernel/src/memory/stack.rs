@@ -0,0 +1,154 @@
+//! 内核栈分配器：栈映射在专用的虚拟区域中，区域被划分为固定大小的槽，每个栈占据槽的高端，槽中栈以下的部分
+//! 不映射，作为保护页。栈溢出时访问保护页会触发 page fault（通常进一步升级为 double fault，在 IST 栈上处理），
+//! 而不是悄悄地覆盖相邻的内存。
+//!
+//! 栈的页帧在分配时就全部映射，不使用按需分页：中断和异常切换到这些栈时不能再发生 page fault。
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+        Size4KiB,
+    },
+    VirtAddr,
+};
+
+use super::{address_space::flush_all, phys_offset, translate_addr, with_kernel_memory};
+
+/// 内核栈区域的起始地址，对应 P4 的第 192 项。
+pub const KERNEL_STACKS_START: u64 = 0x0000_6000_0000_0000;
+/// 每个槽的页数，包括至少一个保护页。
+const SLOT_PAGES: usize = 64;
+const SLOT_SIZE: u64 = SLOT_PAGES as u64 * 4096;
+/// 槽的数量，整个区域为 1 GiB。
+const SLOT_COUNT: usize = 4096;
+/// 内核栈区域的结束地址（不含）。
+pub const KERNEL_STACKS_END: u64 = KERNEL_STACKS_START + SLOT_COUNT as u64 * SLOT_SIZE;
+
+/// 槽的分配位图，置 1 表示已使用。
+static SLOTS: Mutex<[u64; SLOT_COUNT / 64]> = Mutex::new([0; SLOT_COUNT / 64]);
+
+fn alloc_slot() -> Option<usize> {
+    without_interrupts(|| {
+        let mut slots = SLOTS.lock();
+        let (word, bits) = slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, w)| **w != u64::MAX)?;
+        let bit = bits.trailing_ones() as usize;
+        *bits |= 1 << bit;
+        Some(word * 64 + bit)
+    })
+}
+
+fn free_slot(slot: usize) {
+    without_interrupts(|| SLOTS.lock()[slot / 64] &= !(1 << (slot % 64)));
+}
+
+/// 一个带保护页的内核栈，销毁时取消映射并释放页帧。
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: usize,
+    pages: usize,
+}
+
+impl KernelStack {
+    /// 一个栈最多的页数，槽中至少要留一个保护页。
+    pub const MAX_PAGES: usize = SLOT_PAGES - 1;
+
+    /// 分配一个 pages 页大小的栈。
+    pub fn new(pages: usize) -> Result<Self, MapToError<Size4KiB>> {
+        assert!(
+            (1..=Self::MAX_PAGES).contains(&pages),
+            "invalid kernel stack size: {} pages",
+            pages
+        );
+        let slot = alloc_slot().ok_or(MapToError::FrameAllocationFailed)?;
+        let mut stack = KernelStack { slot, pages: 0 };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let result = with_kernel_memory(|mem| {
+            while stack.pages < pages {
+                let page = stack.bottom_page() - 1;
+                let frame = mem
+                    .frame_allocator
+                    .allocate_frame()
+                    .ok_or(MapToError::FrameAllocationFailed)?;
+                match unsafe {
+                    mem.mapper
+                        .map_to(page, frame, flags, &mut mem.frame_allocator)
+                } {
+                    Ok(flush) => flush.flush(),
+                    Err(err) => {
+                        unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                        return Err(err);
+                    }
+                }
+                stack.pages += 1;
+            }
+            Ok(())
+        })
+        .unwrap_or(Err(MapToError::FrameAllocationFailed));
+        // 失败时 stack 被销毁，已经映射的页会被释放。
+        result.map(|()| stack)
+    }
+
+    fn top_page(&self) -> Page {
+        Page::containing_address(VirtAddr::new(
+            KERNEL_STACKS_START + (self.slot as u64 + 1) * SLOT_SIZE,
+        ))
+    }
+
+    fn bottom_page(&self) -> Page {
+        self.top_page() - self.pages as u64
+    }
+
+    /// 栈顶（最高地址，不含），栈从这里向下增长。
+    pub fn top(&self) -> VirtAddr {
+        self.top_page().start_address()
+    }
+
+    /// 栈底（最低的可用地址）。
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom_page().start_address()
+    }
+
+    /// 紧挨着栈底的保护页。
+    pub fn guard_page(&self) -> Page {
+        self.bottom_page() - 1
+    }
+
+    /// 栈的大小（字节）。
+    pub fn size(&self) -> u64 {
+        self.pages as u64 * 4096
+    }
+
+    /// 放弃所有权，栈永远不会被释放，返回栈顶。用于 IST 等整个运行期间都存在的栈。
+    pub fn leak(self) -> VirtAddr {
+        let top = self.top();
+        core::mem::forget(self);
+        top
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        with_kernel_memory(|mem| {
+            for page in Page::<Size4KiB>::range(self.bottom_page(), self.top_page()) {
+                if let Ok((frame, flush)) = mem.mapper.unmap(page) {
+                    flush.ignore();
+                    unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                }
+            }
+        });
+        // 其他地址空间（PCID）的 TLB 中可能还缓存着这个栈的映射。
+        flush_all();
+        free_slot(self.slot);
+    }
+}
+
+/// 判断地址是否落在内核栈的保护页（槽中未映射的部分）中，用于诊断栈溢出。
+pub fn is_guard_page(addr: VirtAddr) -> bool {
+    (KERNEL_STACKS_START..KERNEL_STACKS_END).contains(&addr.as_u64())
+        && translate_addr(addr, phys_offset()).is_none()
+}
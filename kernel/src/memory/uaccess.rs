@@ -0,0 +1,159 @@
+//! 内核访问用户内存：SMEP 禁止内核执行用户页中的代码，SMAP 禁止内核直接读写用户页，两者在 CPU 支持时启用。
+//! 内核需要读写用户内存时使用 copy_from_user/copy_to_user：它们在复制期间用 stac/clac 临时允许访问用户页，
+//! 并且可以从 page fault 中恢复。
+//!
+//! 可能出错的指令登记在异常修复表（链接到 `__ex_table` 段）中，每一项记录出错指令和修复代码的地址。page fault
+//! 无法处理时，page_fault_handler 用 search_exception_table 查找出错的指令，找到后跳到修复代码继续执行，
+//! 复制函数因此返回错误而不是让内核 panic。
+
+use core::{
+    arch::asm,
+    mem::size_of,
+    ptr::addr_of,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::VirtAddr;
+
+use super::{USER_SPACE_END, USER_SPACE_START};
+
+/// 是否已经启用 CR4.SMAP。没有启用时 stac/clac 是非法指令，不能执行。
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// CPU 支持时启用 SMEP 和 SMAP。由 memory::init 调用。
+pub(super) fn init_smep_smap() {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    // CPUID.07H:EBX 的第 7 位表示支持 SMEP，第 20 位表示支持 SMAP。较新的编译器中 __cpuid 不再是 unsafe 的。
+    #[allow(unused_unsafe)]
+    let features = unsafe {
+        if __cpuid(0).eax < 7 {
+            return;
+        }
+        __cpuid_count(7, 0).ebx
+    };
+    if features & (1 << 7) != 0 {
+        unsafe {
+            Cr4::update(|flags| flags.insert(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION))
+        };
+    }
+    if features & (1 << 20) != 0 {
+        unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION)) };
+        SMAP_ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// 是否启用了 SMEP。
+pub fn smep_enabled() -> bool {
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+
+    Cr4::read().contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION)
+}
+
+/// 是否启用了 SMAP。
+pub fn smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserAccessError {
+    /// 地址范围不完全在用户区域内。
+    OutOfRange,
+    /// 复制了 copied 字节之后访问用户内存出错（没有映射或者权限不足）。
+    Fault { copied: usize },
+}
+
+/// 异常修复表的一项。
+#[repr(C)]
+struct ExceptionTableEntry {
+    /// 可能出错的指令的地址。
+    insn: u64,
+    /// 出错时跳转到的地址。
+    fixup: u64,
+}
+
+extern "C" {
+    static __ex_table_start: ExceptionTableEntry;
+    static __ex_table_end: ExceptionTableEntry;
+}
+
+fn exception_table() -> &'static [ExceptionTableEntry] {
+    #[allow(unused_unsafe)]
+    let (start, end) = unsafe { (addr_of!(__ex_table_start), addr_of!(__ex_table_end)) };
+    let len = (end as usize - start as usize) / size_of::<ExceptionTableEntry>();
+    unsafe { core::slice::from_raw_parts(start, len) }
+}
+
+/// 查找出错指令 rip 的修复代码地址。
+pub fn search_exception_table(rip: VirtAddr) -> Option<VirtAddr> {
+    exception_table()
+        .iter()
+        .find(|entry| entry.insn == rip.as_u64())
+        .map(|entry| VirtAddr::new(entry.fixup))
+}
+
+/// [addr, addr + len) 是否完全在用户区域内。
+fn is_user_range(addr: VirtAddr, len: usize) -> bool {
+    let start = addr.as_u64();
+    match start.checked_add(len as u64) {
+        Some(end) => USER_SPACE_START <= start && end <= USER_SPACE_END,
+        None => false,
+    }
+}
+
+/// 从 src 复制 len 字节到 dst，其中一方是用户内存。返回没有复制的字节数，访问出错时不为 0。
+///
+/// rep movsb 出错时 rcx 是剩余的字节数，修复代码就是它的下一条指令，直接返回 rcx 即可。
+unsafe fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    let smap = smap_enabled();
+    let remaining: usize;
+    if smap {
+        asm!("stac", options(nostack));
+    }
+    asm!(
+        "2:",
+        "rep movsb",
+        "3:",
+        ".pushsection __ex_table, \"a\"",
+        ".balign 8",
+        ".quad 2b, 3b",
+        ".popsection",
+        inout("rcx") len => remaining,
+        inout("rdi") dst => _,
+        inout("rsi") src => _,
+        options(nostack, preserves_flags),
+    );
+    if smap {
+        asm!("clac", options(nostack));
+    }
+    remaining
+}
+
+fn copy_result(len: usize, remaining: usize) -> Result<(), UserAccessError> {
+    match remaining {
+        0 => Ok(()),
+        _ => Err(UserAccessError::Fault {
+            copied: len - remaining,
+        }),
+    }
+}
+
+/// 从当前地址空间的用户地址 src 复制 dst.len() 字节到 dst。
+/// 用户内存没有映射时会像普通访问一样经过按需分页和写时复制，仍然失败时返回已经复制的字节数。
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), UserAccessError> {
+    if !is_user_range(src, dst.len()) {
+        return Err(UserAccessError::OutOfRange);
+    }
+    let remaining = unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), dst.len()) };
+    copy_result(dst.len(), remaining)
+}
+
+/// 将 src 复制到当前地址空间的用户地址 dst。
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), UserAccessError> {
+    if !is_user_range(dst, src.len()) {
+        return Err(UserAccessError::OutOfRange);
+    }
+    let remaining = unsafe { copy_user(dst.as_mut_ptr(), src.as_ptr(), src.len()) };
+    copy_result(src.len(), remaining)
+}
@@ -0,0 +1,325 @@
+//! 虚拟内存区域（VMA）：登记已经保留、但还没有分配物理页帧的虚拟地址范围。访问这些地址时发生 page fault，
+//! 由 handle_page_fault 分配一个清零的页帧并映射，然后返回到出错的指令继续执行（按需分页）。
+//!
+//! 内核 VMA 对所有地址空间可见；用户 VMA 属于某个地址空间（以它的 4 级页表区分）。
+//! page fault 处理程序中不能进行堆分配，所以 VMA 表是固定大小的数组。
+//!
+//! 对写时复制页（见 [`super::COW`]）的写入也在这里处理。
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    registers::control::Cr3,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable,
+            PageTableFlags, PhysFrame, Size4KiB,
+        },
+    },
+    VirtAddr,
+};
+
+use super::{
+    address_space::{flush_all, resolve_cow, OWNED},
+    kernel_level_4_frame, phys_offset, phys_to_virt, try_with_kernel_memory, with_kernel_memory,
+    USER_SPACE_END, USER_SPACE_START,
+};
+
+/// 最多可以登记的 VMA 数量。
+const MAX_VMAS: usize = 64;
+
+/// VMA 的用途，只用于调试输出。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    /// 匿名内存，例如 mmap 的区域。
+    Anonymous,
+    Heap,
+    Stack,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    /// 映射页时使用的标志。
+    pub flags: PageTableFlags,
+    pub kind: VmaKind,
+    /// 所属地址空间的 4 级页表，内核 VMA 为 None。
+    owner: Option<PhysFrame>,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    fn overlaps(&self, other: &Vma) -> bool {
+        self.start < other.end && other.start < self.end && self.owner == other.owner
+    }
+
+    /// 区域中的所有页。
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// 起始地址或大小没有按页对齐，或者大小为 0。
+    Unaligned,
+    /// 内核 VMA 位于用户区域，或者用户 VMA 不在用户区域内。
+    OutOfRange,
+    /// 与已有的 VMA 重叠。
+    Overlap,
+    /// VMA 表已满。
+    TableFull,
+}
+
+/// page fault 无法按需分页解决的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// 地址不属于任何 VMA。
+    NoVma,
+    /// 访问方式不被 VMA 允许，例如写只读区域、用户态访问内核区域或者执行不可执行的区域。
+    AccessViolation(VmaKind),
+    /// 页已经存在，是权限错误而不是缺页。
+    ProtectionViolation,
+    /// 没有可用的物理页帧。
+    OutOfMemory,
+    /// 全局页表正在被使用（在持有锁时发生了 page fault），无法处理。
+    Busy,
+}
+
+struct VmaTable {
+    vmas: [Option<Vma>; MAX_VMAS],
+}
+
+impl VmaTable {
+    fn insert(&mut self, vma: Vma) -> Result<(), VmaError> {
+        if self.vmas.iter().flatten().any(|v| v.overlaps(&vma)) {
+            return Err(VmaError::Overlap);
+        }
+        let slot = self
+            .vmas
+            .iter_mut()
+            .find(|v| v.is_none())
+            .ok_or(VmaError::TableFull)?;
+        *slot = Some(vma);
+        Ok(())
+    }
+
+    fn remove(&mut self, start: VirtAddr, owner: Option<PhysFrame>) -> Option<Vma> {
+        self.vmas
+            .iter_mut()
+            .find(|v| matches!(v, Some(v) if v.start == start && v.owner == owner))?
+            .take()
+    }
+
+    /// 查找当前地址空间中包含 addr 的 VMA。
+    fn find(&self, addr: VirtAddr, current: PhysFrame) -> Option<Vma> {
+        self.vmas
+            .iter()
+            .flatten()
+            .find(|v| v.contains(addr) && v.owner.map_or(true, |owner| owner == current))
+            .copied()
+    }
+}
+
+static VMAS: Mutex<VmaTable> = Mutex::new(VmaTable {
+    vmas: [None; MAX_VMAS],
+});
+
+fn is_user_addr(addr: VirtAddr) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&addr.as_u64())
+}
+
+fn new_vma(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    kind: VmaKind,
+    owner: Option<PhysFrame>,
+) -> Result<Vma, VmaError> {
+    if size == 0 || !start.is_aligned(4096u64) || size % 4096 != 0 {
+        return Err(VmaError::Unaligned);
+    }
+    let end = start
+        .as_u64()
+        .checked_add(size)
+        .ok_or(VmaError::OutOfRange)?;
+    let user = owner.is_some();
+    let in_user_space = start.as_u64() >= USER_SPACE_START && end <= USER_SPACE_END;
+    let touches_user_space = start.as_u64() < USER_SPACE_END && end > USER_SPACE_START;
+    if user != in_user_space || (!user && touches_user_space) {
+        return Err(VmaError::OutOfRange);
+    }
+    Ok(Vma {
+        start,
+        end: VirtAddr::new(end),
+        flags: flags | PageTableFlags::PRESENT,
+        kind,
+        owner,
+    })
+}
+
+/// 登记一个内核 VMA，[start, start + size) 中的页在第一次访问时才分配。
+pub fn register_vma(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    kind: VmaKind,
+) -> Result<(), VmaError> {
+    let vma = new_vma(start, size, flags, kind, None)?;
+    without_interrupts(|| VMAS.lock().insert(vma))
+}
+
+/// 取消登记内核 VMA，并释放已经分配的页帧。
+pub fn unregister_vma(start: VirtAddr) -> Option<Vma> {
+    let vma = without_interrupts(|| VMAS.lock().remove(start, None))?;
+    with_kernel_memory(|mem| {
+        for page in vma.pages() {
+            if let Ok((frame, flush)) = mem.mapper.unmap(page) {
+                flush.ignore();
+                unsafe { mem.frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    });
+    // 其他地址空间（PCID）的 TLB 中可能还缓存着内核映射。
+    flush_all();
+    Some(vma)
+}
+
+/// 登记属于 owner 地址空间的用户 VMA，由 AddressSpace 调用。
+pub(super) fn register_user_vma(
+    owner: PhysFrame,
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    kind: VmaKind,
+) -> Result<(), VmaError> {
+    let vma = new_vma(start, size, flags, kind, Some(owner))?;
+    without_interrupts(|| VMAS.lock().insert(vma))
+}
+
+pub(super) fn unregister_user_vma(owner: PhysFrame, start: VirtAddr) -> Option<Vma> {
+    without_interrupts(|| VMAS.lock().remove(start, Some(owner)))
+}
+
+/// 移除地址空间的所有 VMA，地址空间销毁时调用。
+pub(super) fn unregister_user_vmas(owner: PhysFrame) {
+    without_interrupts(|| {
+        for slot in VMAS.lock().vmas.iter_mut() {
+            if matches!(slot, Some(vma) if vma.owner == Some(owner)) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+/// 将 from 地址空间的 VMA 复制给 to，fork 时调用。
+pub(super) fn copy_user_vmas(from: PhysFrame, to: PhysFrame) -> Result<(), VmaError> {
+    without_interrupts(|| {
+        let mut table = VMAS.lock();
+        for i in 0..MAX_VMAS {
+            if let Some(vma) = table.vmas[i].filter(|vma| vma.owner == Some(from)) {
+                table.insert(Vma {
+                    owner: Some(to),
+                    ..vma
+                })?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// 查找当前地址空间中包含 addr 的 VMA。
+pub fn find_vma(addr: VirtAddr) -> Option<Vma> {
+    without_interrupts(|| VMAS.lock().find(addr, Cr3::read().0))
+}
+
+fn table(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() }
+}
+
+/// 尝试解决 page fault：成功时出错的指令可以重新执行。由 page fault 处理程序调用。
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> Result<(), FaultError> {
+    let current = Cr3::read().0;
+    if !is_user_addr(addr) && current != kernel_level_4_frame() {
+        // 地址空间创建之后内核才使用的 P4 项不会出现在它的 4 级页表中，从内核页表同步过来即可。
+        let index = addr.p4_index();
+        let kernel_entry = &table(kernel_level_4_frame())[index];
+        let entry = &mut table(current)[index];
+        if entry.is_unused() && !kernel_entry.is_unused() {
+            *entry = kernel_entry.clone();
+            return Ok(());
+        }
+    }
+    let page = Page::<Size4KiB>::containing_address(addr);
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        // 写入写时复制的页。
+        if is_user_addr(addr) && error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            let user_mode = error_code.contains(PageFaultErrorCode::USER_MODE);
+            if let Some(result) = resolve_cow(current, page, user_mode) {
+                return result;
+            }
+        }
+        return Err(FaultError::ProtectionViolation);
+    }
+    // 在持有 VMA 表的锁时发生 page fault（只可能是内核的 bug），不能再次加锁。
+    let vma = VMAS
+        .try_lock()
+        .ok_or(FaultError::Busy)?
+        .find(addr, current)
+        .ok_or(FaultError::NoVma)?;
+    let denied = (error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !vma.flags.contains(PageTableFlags::WRITABLE))
+        || (error_code.contains(PageFaultErrorCode::USER_MODE)
+            && !vma.flags.contains(PageTableFlags::USER_ACCESSIBLE))
+        || (error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+            && vma.flags.contains(PageTableFlags::NO_EXECUTE));
+    if denied {
+        return Err(FaultError::AccessViolation(vma.kind));
+    }
+    try_with_kernel_memory(|mem| {
+        let frame = mem
+            .frame_allocator
+            .allocate_frame()
+            .ok_or(FaultError::OutOfMemory)?;
+        unsafe {
+            phys_to_virt(frame.start_address())
+                .as_mut_ptr::<u8>()
+                .write_bytes(0, 4096);
+        }
+        let result = match vma.owner {
+            // 内核 VMA 映射到内核页表，下级页表是共享的，所有地址空间都能看到。
+            None => unsafe {
+                mem.mapper
+                    .map_to(page, frame, vma.flags, &mut mem.frame_allocator)
+            },
+            // 用户 VMA 映射到当前地址空间，页帧归它所有，销毁时释放。
+            Some(owner) => unsafe {
+                OffsetPageTable::new(table(owner), VirtAddr::new(phys_offset())).map_to(
+                    page,
+                    frame,
+                    vma.flags | OWNED,
+                    &mut mem.frame_allocator,
+                )
+            },
+        };
+        match result {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(_) => {
+                unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                Err(FaultError::OutOfMemory)
+            }
+        }
+    })
+    .ok_or(FaultError::Busy)?
+}
@@ -0,0 +1,353 @@
+//! 内核虚拟地址区域的分配和映射：
+//! - vmap：将一组（可以不连续的）页帧映射到连续的虚拟地址；
+//! - vmalloc：分配虚拟地址连续、物理上分散的内存，适合大缓冲区；
+//! - ioremap：以指定的缓存模式映射设备内存（帧缓冲区、PCI BAR、APIC 寄存器等）。
+//!
+//! 虚拟地址从 [VMALLOC_START, VMALLOC_END) 中按首次适应分配，每个区域之后留一个不映射的保护页。区域记录在固定
+//! 大小的表中，不依赖堆。
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use spin::Mutex;
+use x86_64::{
+    instructions::interrupts::without_interrupts,
+    registers::{
+        control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+        model_specific::Msr,
+    },
+    structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{address_space::flush_all, phys_to_virt, with_kernel_memory};
+
+/// vmalloc 区域的起始地址，对应 P4 的第 224 项。
+pub const VMALLOC_START: u64 = 0x0000_7000_0000_0000;
+/// vmalloc 区域的结束地址（不含），共 64 GiB。
+pub const VMALLOC_END: u64 = VMALLOC_START + (64 << 30);
+/// 最多同时存在的区域数量。
+const MAX_AREAS: usize = 256;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// 映射设备内存时使用的缓存模式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// 回写，普通内存的默认模式。
+    WriteBack,
+    /// 写穿透：读可以被缓存，写直接到达设备。
+    WriteThrough,
+    /// 写合并：不缓存，但多次写可以合并成一次突发传输，适合帧缓冲区。
+    WriteCombining,
+    /// 不缓存，适合设备寄存器。
+    Uncached,
+}
+
+/// IA32_PAT 寄存器。
+const IA32_PAT: u32 = 0x277;
+/// 4 KiB 页表项的第 7 位是 PAT 位（在高级页表项中同一位表示大页）。
+const PTE_PAT: PageTableFlags = PageTableFlags::HUGE_PAGE;
+/// PAT 中各项的内存类型，页表项中 PAT、PCD、PWT 三位组成下标。除了第 5 项改为写合并外，与上电时的默认值相同。
+const PAT_VALUE: u64 = 0x06 // 0: WB
+    | 0x04 << 8 // 1: WT
+    | 0x07 << 16 // 2: UC-
+    | 0x00 << 24 // 3: UC
+    | 0x06 << 32 // 4: WB
+    | 0x01 << 40 // 5: WC
+    | 0x07 << 48 // 6: UC-
+    | 0x00 << 56; // 7: UC
+/// CPU 是否支持 PAT 并且已经写入了 PAT_VALUE。
+static PAT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+impl CacheMode {
+    fn flags(self) -> PageTableFlags {
+        match self {
+            CacheMode::WriteBack => PageTableFlags::empty(),
+            CacheMode::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            // 不支持 PAT 时第 7 位是保留位，退回不缓存。
+            CacheMode::WriteCombining if PAT_ENABLED.load(Ordering::Relaxed) => {
+                PTE_PAT | PageTableFlags::WRITE_THROUGH
+            }
+            CacheMode::WriteCombining => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            CacheMode::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+        }
+    }
+}
+
+/// 设置 PAT，使 CacheMode::WriteCombining 可用。在映射任何使用 PAT 位的页之前调用。
+/// 按照 Intel SDM 11.12.4 的要求，修改 PAT 前后需要关闭缓存并清空缓存和 TLB，避免同一内存以不同类型被缓存。
+pub(super) fn init_pat() {
+    // CPUID.01H:EDX 的第 16 位表示支持 PAT。较新的编译器中 __cpuid 不再是 unsafe 的。
+    #[allow(unused_unsafe)]
+    let supported = unsafe { core::arch::x86_64::__cpuid(1) }.edx & (1 << 16) != 0;
+    if !supported {
+        return;
+    }
+    without_interrupts(|| unsafe {
+        let cr0 = Cr0::read();
+        Cr0::write((cr0 | Cr0Flags::CACHE_DISABLE) - Cr0Flags::NOT_WRITE_THROUGH);
+        asm!("wbinvd", options(nostack, preserves_flags));
+        flush_tlb();
+        Msr::new(IA32_PAT).write(PAT_VALUE);
+        asm!("wbinvd", options(nostack, preserves_flags));
+        flush_tlb();
+        Cr0::write(cr0);
+    });
+    PAT_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// 清空包括全局页在内的所有 TLB 项。
+unsafe fn flush_tlb() {
+    let cr4 = Cr4::read();
+    if cr4.contains(Cr4Flags::PAGE_GLOBAL) {
+        Cr4::write(cr4 - Cr4Flags::PAGE_GLOBAL);
+        Cr4::write(cr4);
+    } else {
+        x86_64::instructions::tlb::flush_all();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmallocError {
+    /// vmalloc 区域中没有足够大的空闲虚拟地址。
+    OutOfVirtualSpace,
+    /// 区域表已满。
+    TooManyAreas,
+    /// 没有可用的物理页帧（或者无法分配页表）。
+    OutOfMemory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AreaKind {
+    Vmap,
+    Vmalloc,
+    Ioremap,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    start: u64,
+    pages: u64,
+    kind: AreaKind,
+    flags: PageTableFlags,
+}
+
+impl Area {
+    const EMPTY: Area = Area {
+        start: 0,
+        pages: 0,
+        kind: AreaKind::Vmap,
+        flags: PageTableFlags::empty(),
+    };
+
+    /// 区域占用的虚拟地址的结束位置，包括之后的保护页。
+    fn reserved_end(&self) -> u64 {
+        self.start + (self.pages + 1) * PAGE_SIZE
+    }
+
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(VirtAddr::new(self.start));
+        Page::range(start, start + self.pages)
+    }
+}
+
+/// 按起始地址排序的区域表。
+struct Areas {
+    areas: [Area; MAX_AREAS],
+    len: usize,
+}
+
+impl Areas {
+    /// 首次适应：找到第一个能放下 pages 页（加一个保护页）的空隙。
+    fn reserve(
+        &mut self,
+        pages: u64,
+        kind: AreaKind,
+        flags: PageTableFlags,
+    ) -> Result<Area, VmallocError> {
+        if self.len == MAX_AREAS {
+            return Err(VmallocError::TooManyAreas);
+        }
+        let size = (pages + 1) * PAGE_SIZE;
+        let mut start = VMALLOC_START;
+        let mut index = 0;
+        while index < self.len && self.areas[index].start - start < size {
+            start = self.areas[index].reserved_end();
+            index += 1;
+        }
+        if VMALLOC_END - start < size {
+            return Err(VmallocError::OutOfVirtualSpace);
+        }
+        let area = Area {
+            start,
+            pages,
+            kind,
+            flags,
+        };
+        self.areas.copy_within(index..self.len, index + 1);
+        self.areas[index] = area;
+        self.len += 1;
+        Ok(area)
+    }
+
+    fn remove(&mut self, start: u64) -> Option<Area> {
+        let index = self.areas[..self.len]
+            .binary_search_by_key(&start, |area| area.start)
+            .ok()?;
+        let area = self.areas[index];
+        self.areas.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        Some(area)
+    }
+}
+
+static AREAS: Mutex<Areas> = Mutex::new(Areas {
+    areas: [Area::EMPTY; MAX_AREAS],
+    len: 0,
+});
+
+fn reserve(pages: u64, kind: AreaKind, flags: PageTableFlags) -> Result<Area, VmallocError> {
+    without_interrupts(|| AREAS.lock().reserve(pages, kind, flags))
+}
+
+/// 取消映射并释放区域。vmalloc 分配的页帧会被释放，其他区域的页帧属于调用者。
+fn release(area: Area) {
+    with_kernel_memory(|mem| {
+        for page in area.pages() {
+            if area.flags.contains(PTE_PAT) {
+                // 4 KiB 页表项的 PAT 位与大页位相同，x86_64 crate 会把它当成大页而拒绝取消映射，先清除这一位。
+                if let Ok(flush) = unsafe { mem.mapper.update_flags(page, area.flags - PTE_PAT) } {
+                    flush.ignore();
+                }
+            }
+            if let Ok((frame, flush)) = mem.mapper.unmap(page) {
+                flush.ignore();
+                if area.kind == AreaKind::Vmalloc {
+                    unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                }
+            }
+        }
+    });
+    // 其他地址空间（PCID）的 TLB 中可能还缓存着这些映射。
+    flush_all();
+    without_interrupts(|| AREAS.lock().remove(area.start));
+}
+
+/// 将 frame(i) 映射到区域的第 i 页，失败时撤销整个区域。
+fn map_area(
+    area: Area,
+    mut frame: impl FnMut(&mut dyn FrameAllocator<Size4KiB>, u64) -> Option<PhysFrame>,
+) -> Result<VirtAddr, VmallocError> {
+    let result = with_kernel_memory(|mem| {
+        for (i, page) in area.pages().enumerate() {
+            let frame =
+                frame(&mut mem.frame_allocator, i as u64).ok_or(VmallocError::OutOfMemory)?;
+            let result = unsafe {
+                mem.mapper
+                    .map_to(page, frame, area.flags, &mut mem.frame_allocator)
+            };
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    if area.kind == AreaKind::Vmalloc {
+                        unsafe { mem.frame_allocator.deallocate_frame(frame) };
+                    }
+                    return Err(VmallocError::OutOfMemory);
+                }
+            }
+        }
+        Ok(())
+    })
+    .unwrap_or(Err(VmallocError::OutOfMemory));
+    match result {
+        Ok(()) => Ok(VirtAddr::new(area.start)),
+        Err(err) => {
+            release(area);
+            Err(err)
+        }
+    }
+}
+
+fn remove_area(addr: VirtAddr, kind: AreaKind) {
+    let start = addr.align_down(PAGE_SIZE).as_u64();
+    let area = without_interrupts(|| {
+        let areas = AREAS.lock();
+        areas.areas[..areas.len]
+            .iter()
+            .find(|area| area.start == start)
+            .copied()
+    });
+    match area {
+        Some(area) if area.kind == kind => release(area),
+        Some(area) => panic!(
+            "{:?} was mapped by {:?}, released as {:?}",
+            addr, area.kind, kind
+        ),
+        None => panic!("{:?} is not a mapped area", addr),
+    }
+}
+
+/// 将 frames 依次映射到一段连续的虚拟地址，返回起始地址。用 vunmap 取消映射，页帧仍然属于调用者。
+/// # Safety
+/// 调用者需要保证页帧在映射期间有效，并且不会因为新的映射产生别名问题。
+pub unsafe fn vmap(frames: &[PhysFrame], flags: PageTableFlags) -> Result<VirtAddr, VmallocError> {
+    let flags = flags | PageTableFlags::PRESENT;
+    let area = reserve(frames.len() as u64, AreaKind::Vmap, flags)?;
+    map_area(area, |_, i| Some(frames[i as usize]))
+}
+
+/// 取消 vmap 建立的映射。
+pub fn vunmap(addr: VirtAddr) {
+    remove_area(addr, AreaKind::Vmap);
+}
+
+/// 分配 len 字节（按页向上取整）虚拟地址连续的内存，内容清零。页帧逐个分配，物理上不需要连续。
+pub fn vmalloc(len: usize) -> Result<VirtAddr, VmallocError> {
+    let pages = (len as u64 + PAGE_SIZE - 1) / PAGE_SIZE;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let area = reserve(pages.max(1), AreaKind::Vmalloc, flags)?;
+    map_area(area, |allocator, _| {
+        let frame = allocator.allocate_frame()?;
+        unsafe {
+            phys_to_virt(frame.start_address())
+                .as_mut_ptr::<u8>()
+                .write_bytes(0, PAGE_SIZE as usize);
+        }
+        Some(frame)
+    })
+}
+
+/// 释放 vmalloc 分配的内存。
+pub fn vfree(addr: VirtAddr) {
+    remove_area(addr, AreaKind::Vmalloc);
+}
+
+/// 以 mode 映射物理地址 [phys, phys + len) 的设备内存，返回 phys 对应的虚拟地址，phys 不需要按页对齐。
+/// # Safety
+/// 调用者需要保证这段物理地址可以被映射，并且使用的缓存模式与其他映射（例如物理内存映射）不冲突。
+pub unsafe fn ioremap(
+    phys: PhysAddr,
+    len: usize,
+    mode: CacheMode,
+) -> Result<VirtAddr, VmallocError> {
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last = PhysFrame::<Size4KiB>::containing_address(phys + len.max(1) as u64 - 1u64);
+    let pages = (last - first) + 1;
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | mode.flags();
+    let area = reserve(pages, AreaKind::Ioremap, flags)?;
+    let start = map_area(area, |_, i| Some(first + i))?;
+    Ok(start + (phys.as_u64() - first.start_address().as_u64()))
+}
+
+/// 取消 ioremap 建立的映射，addr 是 ioremap 的返回值。
+pub fn iounmap(addr: VirtAddr) {
+    remove_area(addr, AreaKind::Ioremap);
+}
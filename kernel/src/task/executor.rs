@@ -1,31 +1,53 @@
 use core::{
+    future::Future,
     panic,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{Context, Waker},
 };
 
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, task::Wake};
 use crossbeam_queue::ArrayQueue;
 
-use super::{Task, TaskId};
+use super::{
+    join_handle::{Join, JoinState},
+    set_current_task, JoinHandle, Task, TaskFuture, TaskId,
+};
+
+/// 就绪任务的队列，由 Executor 和所有 TaskWaker 共享。
+struct ReadyQueue {
+    /// ArrayQueue 是无锁、安全的队列，所以不需要 Mutex。使用固定大小的队列，避免了内存分配。
+    ids: ArrayQueue<TaskId>,
+    /// 队列满时有唤醒没能放进队列，执行器需要检查所有任务的 queued 标记。
+    overflowed: AtomicBool,
+}
 
 struct TaskWaker {
     task_id: TaskId,
+    /// 任务已经在就绪队列中，重复的唤醒直接忽略，所以每个任务在队列中最多出现一次。执行器 poll 之前清除。
+    queued: AtomicBool,
     /// 与 Executor 共享的队列。
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    ready: Arc<ReadyQueue>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        // from 函数负责为我们的TaskWaker类型构造一个RawWakerVTable和一个RawWaker实例
-        Waker::from(Arc::new(Self {
+    fn new(task_id: TaskId, ready: Arc<ReadyQueue>) -> Arc<Self> {
+        Arc::new(Self {
             task_id,
-            task_queue,
-        }))
+            queued: AtomicBool::new(false),
+            ready,
+        })
     }
 
+    /// wake 只是简单将它放回 ready 队列即可。定时器等中断处理函数中也会调用 wake，所以这里不能 panic。
     fn wake_task(&self) {
-        // wake 只是简单将它放回 ready 队列即可。
-        self.task_queue.push(self.task_id).expect("queue is full")
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if self.ready.ids.push(self.task_id).is_err() {
+            // 队列已满：queued 保持为 true，执行器清空队列之后会把这个任务重新放回队列。
+            self.ready.overflowed.store(true, Ordering::Release);
+        }
     }
 }
 
@@ -41,20 +63,95 @@ impl Wake for TaskWaker {
     }
 }
 
+/// 通过 Spawner 创建、还没有被执行器取走的任务。
+struct Injected {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// Executor 和 Spawner 共享的注入队列。
+struct Injector {
+    queue: ArrayQueue<Injected>,
+    /// 执行器已经被丢弃，不再接受新任务。
+    closed: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// 注入队列已满，执行器还没有取走之前的任务。
+    QueueFull,
+    /// 执行器已经被丢弃。
+    Closed,
+}
+
+/// 向执行器添加任务的句柄，由 Executor::spawner 创建。
+/// 可以克隆并交给正在运行的任务，也可以保存在 static 中，在中断处理函数里把工作推迟到任务中完成。
+#[derive(Clone)]
+pub struct Spawner {
+    injector: Arc<Injector>,
+    ready: Arc<ReadyQueue>,
+}
+
+impl Spawner {
+    /// 创建一个任务，执行器下一次运行时开始执行它。
+    /// 任务可能来自中断处理函数，所以 future 和它的输出都必须是 Send 的。
+    pub fn spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        if self.injector.closed.load(Ordering::Acquire) {
+            return Err(SpawnError::Closed);
+        }
+        let id = TaskId::new();
+        let state = Arc::new(JoinState::new());
+        let waker = TaskWaker::new(id, self.ready.clone());
+        let injected = Injected {
+            id,
+            future: Box::pin(Join::new(future, state.clone())),
+            waker: waker.clone(),
+        };
+        // 注入队列是无锁的，中断处理函数中也可以安全地 push。
+        self.injector
+            .queue
+            .push(injected)
+            .map_err(|_| SpawnError::QueueFull)?;
+        Ok(JoinHandle::new(id, state, Waker::from(waker)))
+    }
+}
+
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    /// 用于存放 task id 的队列。由于队列会在 多个 Waker 和 Executor 之间共享，所以这里使用 Arc。ArrayQueue 是无锁、安全的队列，所以不需要 Mutex。
-    /// 使用固定大小的队列，避免了内存分配。
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    /// 任务的 future，输出已经由 Join 交给了 JoinHandle。
+    tasks: BTreeMap<TaskId, TaskFuture>,
+    /// 用于存放 task id 的队列。由于队列会在 多个 Waker 和 Executor 之间共享，所以这里使用 Arc。
+    ready: Arc<ReadyQueue>,
+    waker_cache: BTreeMap<TaskId, Arc<TaskWaker>>,
+    /// Spawner 创建的新任务，执行器每次运行任务之前把它们取出来。
+    injector: Arc<Injector>,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Self {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            ready: Arc::new(ReadyQueue {
+                ids: ArrayQueue::new(100),
+                overflowed: AtomicBool::new(false),
+            }),
             waker_cache: BTreeMap::new(),
+            injector: Arc::new(Injector {
+                queue: ArrayQueue::new(100),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// 创建一个 Spawner，执行器运行时也可以通过它添加任务。
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            injector: self.injector.clone(),
+            ready: self.ready.clone(),
         }
     }
 
@@ -66,35 +163,69 @@ impl Executor {
         }
     }
 
+    /// 运行直到所有任务都完成。
+    pub fn run_until_complete(&mut self) {
+        while !self.tasks.is_empty() || !self.injector.queue.is_empty() {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
     fn sleep_if_idle(&self) {
         use x86_64::instructions::interrupts::{disable, enable, enable_and_hlt};
         // 关闭中断
         disable();
-        if self.task_queue.is_empty() {
+        if self.ready.ids.is_empty()
+            && !self.ready.overflowed.load(Ordering::Acquire)
+            && self.injector.queue.is_empty()
+        {
             enable_and_hlt(); // 开启中断并hlt
         } else {
             enable();
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task_id, task).is_some() {
+    /// 返回的 JoinHandle 可以等待任务的输出或者取消任务。
+    pub fn spawn<T: 'static>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let Task { id, future, state } = task;
+        if self.tasks.insert(id, future).is_some() {
             // 由于taskId 是唯一的，这里的 panic 不应该发生。
             panic!("task with same ID already in tasks");
         }
+        // JoinHandle 用任务的 Waker 通知执行器移除被 abort 的任务。
+        let waker = TaskWaker::new(id, self.ready.clone());
+        self.waker_cache.insert(id, waker.clone());
         // 这里会使 task 尽快开始执行。
-        self.task_queue.push(task_id).expect("queue full");
+        waker.wake_task();
+        JoinHandle::new(id, state, Waker::from(waker))
     }
 
     fn run_ready_tasks(&mut self) {
         // 避免借用检查器报错。
         let Self {
             tasks,
-            task_queue,
+            ready,
             waker_cache,
+            injector,
         } = self;
-        while let Ok(task_id) = task_queue.pop() {
+        // 先取出 Spawner 创建的任务，它们的 Waker 已经创建好了。只取就绪队列还放得下的数量，其余的留在注入队列中，
+        // 等下一轮再取。
+        let room = ready.ids.capacity() - ready.ids.len();
+        for _ in 0..room {
+            let Injected { id, future, waker } = match injector.queue.pop() {
+                Ok(injected) => injected,
+                Err(_) => break,
+            };
+            tasks.insert(id, future);
+            // 任务在注入队列中时可能已经被唤醒（比如被 abort）：那次唤醒的 id 要么因为队列满没能入队，要么在任务进入
+            // tasks 之前就被取出丢弃了，而 queued 标记还在，wake_task 不会再入队。所以这里不经过 wake_task，直接入队。
+            waker.queued.store(true, Ordering::Release);
+            if ready.ids.push(id).is_err() {
+                ready.overflowed.store(true, Ordering::Release);
+            }
+            waker_cache.insert(id, waker);
+        }
+        while let Ok(task_id) = ready.ids.pop() {
             let task = match tasks.get_mut(&task_id) {
                 Some(t) => t,
                 // task 不存在了。
@@ -102,9 +233,16 @@ impl Executor {
             };
             let waker = waker_cache
                 .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+                .or_insert_with(|| TaskWaker::new(task_id, ready.clone()));
+            // 在 poll 之前清除标记，poll 期间的唤醒会把任务重新放回队列。
+            waker.queued.store(false, Ordering::Release);
+            let waker = Waker::from(waker.clone());
+            let mut context = Context::from_waker(&waker);
+            set_current_task(Some(task_id));
+            let poll = task.as_mut().poll(&mut context);
+            set_current_task(None);
+            match poll {
+                // 完成或者被 abort。
                 core::task::Poll::Ready(_) => {
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
@@ -113,5 +251,22 @@ impl Executor {
                 core::task::Poll::Pending => {}
             }
         }
+        // 有唤醒因为队列满没能入队，现在队列空了，把这些任务放回去。刚被唤醒的任务可能因此入队两次，只是多 poll 一次。
+        if ready.overflowed.swap(false, Ordering::AcqRel) {
+            for waker in waker_cache.values() {
+                if waker.queued.load(Ordering::Acquire) && ready.ids.push(waker.task_id).is_err() {
+                    ready.overflowed.store(true, Ordering::Release);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        // 之后 Spawner 不能再添加任务；已经注入的任务和 tasks 一样随执行器丢弃，JoinHandle 得到 Cancelled。
+        self.injector.closed.store(true, Ordering::Release);
+        while self.injector.queue.pop().is_ok() {}
     }
 }
@@ -0,0 +1,181 @@
+//! 任务的结果和取消。
+//!
+//! Task::new 和 Spawner::spawn 把 future 包装成 Join，Join 完成时把输出放进与 JoinHandle 共享的 JoinState，
+//! 并唤醒等待的任务。
+//! abort 只设置标志并唤醒任务，执行器下一次 poll 时 Join 直接完成，任务随之从执行器中移除，future 被丢弃。
+//! future 没有完成就被丢弃时（取消，或者执行器本身被丢弃），JoinHandle 得到 JoinError::Cancelled。
+//!
+//! 内核以 panic = "abort" 编译，任务中的 panic 无法展开、也无法交给 JoinHandle，
+//! panic 处理函数会打印 panic 时正在运行的任务（见 current_task）。
+
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::TaskId;
+
+/// JoinHandle 没有得到任务输出的原因。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// 任务在完成之前被 abort，或者被执行器丢弃。
+    Cancelled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("task was cancelled"),
+        }
+    }
+}
+
+/// 任务与 JoinHandle 共享的状态。JoinHandle 可能在中断处理函数中使用，只在关闭中断时加锁。
+pub(super) struct JoinState<T> {
+    aborted: AtomicBool,
+    inner: Mutex<JoinInner<T>>,
+}
+
+struct JoinInner<T> {
+    /// 任务的结果，被 JoinHandle 取走后为 None。
+    result: Option<Result<T, JoinError>>,
+    finished: bool,
+    /// 等待结果的任务。
+    waker: Option<Waker>,
+}
+
+impl<T> JoinState<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            aborted: AtomicBool::new(false),
+            inner: Mutex::new(JoinInner {
+                result: None,
+                finished: false,
+                waker: None,
+            }),
+        }
+    }
+
+    fn complete(&self, result: Result<T, JoinError>) {
+        let waker = without_interrupts(|| {
+            let mut inner = self.inner.lock();
+            inner.result = Some(result);
+            inner.finished = true;
+            inner.waker.take()
+        });
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// 执行器实际运行的 future：运行 future，并把输出交给 JoinState。
+pub(super) struct Join<F: Future> {
+    future: F,
+    state: Arc<JoinState<F::Output>>,
+    completed: bool,
+}
+
+impl<F: Future> Join<F> {
+    pub(super) fn new(future: F, state: Arc<JoinState<F::Output>>) -> Self {
+        Self {
+            future,
+            state,
+            completed: false,
+        }
+    }
+}
+
+impl<F: Future> Future for Join<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // future 可能不是 Unpin 的，只通过 Pin 访问。
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.completed || this.state.aborted.load(Ordering::Relaxed) {
+            return Poll::Ready(());
+        }
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let output = match future.poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.completed = true;
+        this.state.complete(Ok(output));
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future> Drop for Join<F> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.state.complete(Err(JoinError::Cancelled));
+        }
+    }
+}
+
+/// Executor::spawn 和 Spawner::spawn 返回的句柄，await 它得到任务的输出。丢弃句柄不会取消任务。
+pub struct JoinHandle<T> {
+    id: TaskId,
+    state: Arc<JoinState<T>>,
+    /// 任务的 Waker，abort 时用来让执行器移除任务。
+    task_waker: Waker,
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn new(id: TaskId, state: Arc<JoinState<T>>, task_waker: Waker) -> Self {
+        Self {
+            id,
+            state,
+            task_waker,
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 取消任务。任务会在执行器下一次运行时被移除，future 随之被丢弃，已经完成的任务不受影响。
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::Relaxed);
+        self.task_waker.wake_by_ref();
+    }
+
+    /// 任务是否已经结束（完成或者被取消）。
+    pub fn is_finished(&self) -> bool {
+        without_interrupts(|| self.state.inner.lock().finished)
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        without_interrupts(|| {
+            let mut inner = self.state.inner.lock();
+            match inner.result.take() {
+                Some(result) => Poll::Ready(result),
+                None => {
+                    match &inner.waker {
+                        Some(waker) if waker.will_wake(cx.waker()) => {}
+                        _ => inner.waker = Some(cx.waker().clone()),
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JoinHandle").field("id", &self.id).finish()
+    }
+}
@@ -1,18 +1,26 @@
 pub mod executor;
+mod join_handle;
 pub mod keyboard;
 pub mod simple_executor;
 
+pub use join_handle::{JoinError, JoinHandle};
+
 use core::{
+    fmt,
     future::Future,
     pin::Pin,
     sync::atomic,
     task::{Context, Poll},
 };
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, sync::Arc};
+
+use join_handle::{Join, JoinState};
+
+type TaskFuture = Pin<Box<dyn Future<Output = ()>>>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct TaskId(u64);
+pub struct TaskId(u64);
 
 impl TaskId {
     fn new() -> Self {
@@ -22,23 +30,51 @@ impl TaskId {
     }
 }
 
-pub struct Task {
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// 没有任务在运行。
+const NO_TASK: u64 = u64::MAX;
+/// 执行器正在 poll 的任务。
+static CURRENT_TASK: atomic::AtomicU64 = atomic::AtomicU64::new(NO_TASK);
+
+/// 正在运行的任务，用于在 panic 时报告是哪个任务出了问题。
+pub fn current_task() -> Option<TaskId> {
+    match CURRENT_TASK.load(atomic::Ordering::Relaxed) {
+        NO_TASK => None,
+        id => Some(TaskId(id)),
+    }
+}
+
+fn set_current_task(task: Option<TaskId>) {
+    CURRENT_TASK.store(task.map_or(NO_TASK, |id| id.0), atomic::Ordering::Relaxed);
+}
+
+/// T 是 future 的输出，由 Executor::spawn 返回的 JoinHandle 取得。
+pub struct Task<T = ()> {
     id: TaskId,
     ///
     /// Pin: 不被 move，不允许获取 &mut 引用。
     /// Box: 分配在堆上
     /// dyn Future: 动态分发，且为 Future。
     /// Pin<Box>: 保证自引用的安全性。
-    future: Pin<Box<dyn Future<Output = ()>>>,
+    /// 输出被 Join 交给了 state，所以这里的 Output 总是 ()。
+    future: TaskFuture,
+    state: Arc<JoinState<T>>,
 }
 
-impl Task {
+impl<T: 'static> Task<T> {
     // 'static: Task 可能存在任意时间（直到被 poll 并且完成）
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+    pub fn new(future: impl Future<Output = T> + 'static) -> Self {
+        let state = Arc::new(JoinState::new());
         Self {
             id: TaskId::new(),
             // 注意：这里实际上发生了一次move，Box::new 会将 future move 到堆上。由于future 在被 poll 前是没有自引用的，所以是可以 move 的。
-            future: Box::pin(future),
+            future: Box::pin(Join::new(future, state.clone())),
+            state,
         }
     }
 
@@ -0,0 +1,77 @@
+//! 集成测试共用的工具。
+//!
+//! 期望 panic 的测试（异常报告、堆破坏检测等）要在自己的 panic_handler 中检查 panic 信息。panic 时堆可能已经损坏，
+//! 所以信息格式化到固定大小的缓冲区中，不分配内存。
+
+use core::{
+    fmt::{self, Write},
+    panic::PanicInfo,
+};
+
+use crate::{
+    hlt_loop,
+    qemu::{exit_qemu, QemuExitCode},
+    serial_println,
+};
+
+/// 固定大小的缓冲区，写满后丢弃多余的内容。
+pub struct Buffer {
+    bytes: [u8; 2048],
+    len: usize,
+}
+
+impl Buffer {
+    pub const fn new() -> Self {
+        Buffer {
+            bytes: [0; 2048],
+            len: 0,
+        }
+    }
+
+    /// 格式化 args 到一个新的缓冲区中。
+    pub fn format(args: fmt::Arguments) -> Self {
+        let mut buffer = Buffer::new();
+        let _ = buffer.write_fmt(args);
+        buffer
+    }
+
+    pub fn as_str(&self) -> &str {
+        // 截断可能落在多字节字符中间，只取合法的前缀。
+        match core::str::from_utf8(&self.bytes[..self.len]) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&self.bytes[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::new()
+    }
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = (self.len + s.len()).min(self.bytes.len());
+        self.bytes[self.len..end].copy_from_slice(&s.as_bytes()[..end - self.len]);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// 检查 panic 信息包含 expected 中的每一项、并且不包含 unexpected 中的任何一项，输出 [ok] 或 [failed] 后退出 QEMU。
+pub fn expect_panic_message(info: &PanicInfo, expected: &[&str], unexpected: &[&str]) -> ! {
+    let message = Buffer::format(format_args!("{}", info));
+    let message = message.as_str();
+    if expected.iter().all(|line| message.contains(line))
+        && !unexpected.iter().any(|line| message.contains(line))
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: unexpected panic:\n{}", message);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop()
+}
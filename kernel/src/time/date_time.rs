@@ -0,0 +1,82 @@
+use core::fmt;
+
+/// 公历日期和时间（UTC），精确到秒。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12。
+    pub month: u8,
+    /// 1-31。
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// 从 1970-01-01 到 year-month-day 的天数（year 不早于 1970）。
+/// 把 3 月作为一年的第一个月，2 月的闰日就落在年末，每个月的天数可以用一个线性公式计算。
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719468 是 0000-03-01 到 1970-01-01 的天数。
+    era * 146097 + day_of_era - 719468
+}
+
+/// days_from_civil 的逆运算。
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl DateTime {
+    /// Unix 时间戳（1970-01-01 00:00:00 UTC 以来的秒数）对应的时间。
+    pub fn from_unix_timestamp(secs: u64) -> DateTime {
+        let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+        let secs_of_day = secs % SECS_PER_DAY;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+        }
+    }
+
+    /// 对应的 Unix 时间戳，早于 1970 年时返回 0。
+    pub fn unix_timestamp(&self) -> u64 {
+        if self.year < 1970 {
+            return 0;
+        }
+        days_from_civil(self.year as u64, self.month as u64, self.day as u64) * SECS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+/// ISO 8601 格式，例如 `2024-01-02 03:04:05`。
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
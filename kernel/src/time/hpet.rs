@@ -0,0 +1,94 @@
+//! 高精度事件定时器（HPET）。
+//!
+//! HPET 的位置由 ACPI 的 HPET 表给出，寄存器是内存映射的。这里只使用它的主计数器：频率固定（至少 10 MHz），
+//! 启用后单调递增，作为校准其他时钟的参考，在 TSC 不可靠时也作为时钟源。
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::PhysAddr;
+
+use crate::{
+    acpi,
+    memory::{self, CacheMode, VmallocError},
+};
+
+/// 寄存器的偏移。
+const CAPABILITIES: u64 = 0x000;
+const CONFIG: u64 = 0x010;
+const MAIN_COUNTER: u64 = 0x0f0;
+/// 能力寄存器的第 13 位：主计数器是 64 位的。
+const COUNTER_64BIT: u64 = 1 << 13;
+/// 配置寄存器的第 0 位：启动主计数器。
+const ENABLE: u64 = 1 << 0;
+/// HPET 表中基地址（通用地址结构中的地址字段）的偏移。
+const TABLE_BASE_ADDRESS: u64 = 44;
+
+/// 寄存器映射后的虚拟地址，0 表示没有 HPET。
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// 主计数器的频率（Hz）。
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    /// ACPI 中没有 HPET 表。
+    NotPresent,
+    /// 计数器周期不合法。
+    InvalidPeriod,
+    Map(VmallocError),
+}
+
+unsafe fn read(base: u64, offset: u64) -> u64 {
+    ((base + offset) as *const u64).read_volatile()
+}
+
+unsafe fn write(base: u64, offset: u64, value: u64) {
+    ((base + offset) as *mut u64).write_volatile(value)
+}
+
+/// 映射并启动 HPET 的主计数器。需要 ioremap，必须在 memory::init_global 之后调用。
+pub(super) fn init() -> Result<(), HpetError> {
+    let (table, _) = acpi::find_table(b"HPET").ok_or(HpetError::NotPresent)?;
+    let address: u64 = unsafe {
+        core::ptr::read_unaligned(memory::phys_to_virt(table + TABLE_BASE_ADDRESS).as_ptr())
+    };
+    let base = unsafe { memory::ioremap(PhysAddr::new(address), 0x400, CacheMode::Uncached) }
+        .map_err(HpetError::Map)?
+        .as_u64();
+    let capabilities = unsafe { read(base, CAPABILITIES) };
+    // 高 32 位是计数器的周期，单位是飞秒（10^-15 秒），规范要求不超过 100 纳秒。
+    let period = capabilities >> 32;
+    if period == 0 || period > 100_000_000 {
+        return Err(HpetError::InvalidPeriod);
+    }
+    unsafe {
+        let config = read(base, CONFIG);
+        write(base, CONFIG, config | ENABLE);
+    }
+    FREQUENCY.store(1_000_000_000_000_000 / period, Ordering::Relaxed);
+    BASE.store(base, Ordering::Relaxed);
+    Ok(())
+}
+
+/// 主计数器的频率，没有 HPET 时返回 None。
+pub fn frequency() -> Option<u64> {
+    match BASE.load(Ordering::Relaxed) {
+        0 => None,
+        _ => Some(FREQUENCY.load(Ordering::Relaxed)),
+    }
+}
+
+/// 主计数器是否是 64 位的。32 位的计数器在 10 MHz 时几分钟就会回绕，只能用作校准的参考，不能作为时钟源。
+pub fn is_64bit() -> bool {
+    match BASE.load(Ordering::Relaxed) {
+        0 => false,
+        base => unsafe { read(base, CAPABILITIES) & COUNTER_64BIT != 0 },
+    }
+}
+
+/// 主计数器的当前值，没有 HPET 时为 0。
+pub fn counter() -> u64 {
+    match BASE.load(Ordering::Relaxed) {
+        0 => 0,
+        base => unsafe { read(base, MAIN_COUNTER) },
+    }
+}
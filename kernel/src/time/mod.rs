@@ -0,0 +1,258 @@
+//! 时间子系统。
+//!
+//! - 时钟中断（tick）：启动时由 PIT 通道 0 以 TICK_HZ 的频率产生；切换到 APIC 之后改用 Local APIC 定时器，
+//!   两者都投递到 IRQ 0 的向量，由 interrupts 中的时钟中断处理函数调用 handle_tick。
+//! - 单调时钟（now）：从启动开始的纳秒数，读取当前时钟源的计数器换算得到。启动时时钟源是 tick 计数，
+//!   init_clocksource 之后换成用 HPET 或 PIT 校准过的 TSC，或者 HPET 的主计数器，精度更高。
+//! - 定时器：sleep、interval、timeout 等 Future 把 Waker 登记在时间轮中，时钟中断推进时间轮并唤醒到期的任务。
+//! - 墙上时间（wall_clock）：init_wall_clock 读取一次 CMOS RTC 的日期和时间，之后加上单调时钟经过的时间，
+//!   用于日志时间戳和文件时间。
+//!
+//! 切换时钟源时以切换时刻的时间为起点，保证时间连续、不会倒退。
+
+mod date_time;
+mod hpet;
+mod instant;
+mod pit;
+pub mod rtc;
+mod timer;
+mod tsc;
+mod wheel;
+
+pub use core::time::Duration;
+pub use date_time::DateTime;
+pub use hpet::HpetError;
+pub use instant::Instant;
+pub use rtc::RtcError;
+pub use timer::{
+    interval, sleep, sleep_until, timeout, timeout_at, Elapsed, Interval, Sleep, Timeout,
+};
+
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{
+    interrupts::{self, ControllerKind},
+    println,
+};
+
+/// 时钟中断的频率。
+pub const TICK_HZ: u64 = 1000;
+/// 校准时测量的时间长度（毫秒）。PIT 通道 2 的一次倒计时最多约 54 毫秒。
+const CALIBRATION_MS: u64 = 10;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// 单调时钟使用的计数器。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    /// 时钟中断的次数，精度为一个 tick。
+    Ticks,
+    /// HPET 的主计数器。
+    Hpet,
+    /// TSC。
+    Tsc,
+}
+
+/// 产生时钟中断的定时器。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TickSource {
+    Pit,
+    LocalApic,
+}
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+static SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Ticks as u8);
+/// 时钟源计数器的频率（Hz）。
+static FREQUENCY: AtomicU64 = AtomicU64::new(TICK_HZ);
+/// 切换到当前时钟源时的时间（纳秒）和计数器的值。
+static BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+static BASE_COUNT: AtomicU64 = AtomicU64::new(0);
+/// now 返回过的最大值，用来保证单调。
+static LAST_NANOS: AtomicU64 = AtomicU64::new(0);
+
+static TICK_SOURCE: AtomicU8 = AtomicU8::new(TickSource::Pit as u8);
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// init_wall_clock 读取 RTC 时的 Unix 时间戳（秒）和单调时钟的时刻（纳秒）。
+static WALL_CLOCK_SECS: AtomicU64 = AtomicU64::new(0);
+static WALL_CLOCK_BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// 设置 PIT 的频率。由 kernel::init 在开中断之前调用。
+pub fn init() {
+    pit::set_periodic(TICK_HZ);
+}
+
+/// 时钟中断处理函数调用。
+pub(crate) fn handle_tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    wheel::advance(ticks);
+}
+
+/// 启动以来时钟中断的次数。
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn clock_source() -> ClockSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        0 => ClockSource::Ticks,
+        1 => ClockSource::Hpet,
+        _ => ClockSource::Tsc,
+    }
+}
+
+pub fn tick_source() -> TickSource {
+    match TICK_SOURCE.load(Ordering::Relaxed) {
+        0 => TickSource::Pit,
+        _ => TickSource::LocalApic,
+    }
+}
+
+/// 校准后的 TSC 频率（Hz），没有校准时返回 None。
+pub fn tsc_frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+fn read_count(source: ClockSource) -> u64 {
+    match source {
+        ClockSource::Ticks => ticks(),
+        ClockSource::Hpet => hpet::counter(),
+        ClockSource::Tsc => tsc::read(),
+    }
+}
+
+fn now_nanos() -> u64 {
+    let source = clock_source();
+    let elapsed = read_count(source).saturating_sub(BASE_COUNT.load(Ordering::Relaxed));
+    let nanos = BASE_NANOS.load(Ordering::Relaxed)
+        + (elapsed as u128 * NANOS_PER_SEC as u128 / FREQUENCY.load(Ordering::Relaxed) as u128)
+            as u64;
+    LAST_NANOS.fetch_max(nanos, Ordering::Relaxed).max(nanos)
+}
+
+/// 单调时钟的当前时刻。
+pub fn now() -> Instant {
+    Instant::from_nanos(now_nanos())
+}
+
+/// 启动以来经过的时间。
+pub fn uptime() -> Duration {
+    now().duration_since(Instant::ZERO)
+}
+
+/// 切换时钟源，新的时钟源从当前时间开始计时。只在启动时由 init_clocksource 调用。
+fn set_clock_source(source: ClockSource, frequency: u64) {
+    without_interrupts(|| {
+        let nanos = now_nanos();
+        SOURCE.store(source as u8, Ordering::Relaxed);
+        FREQUENCY.store(frequency, Ordering::Relaxed);
+        BASE_NANOS.store(nanos, Ordering::Relaxed);
+        BASE_COUNT.store(read_count(source), Ordering::Relaxed);
+    });
+}
+
+/// 用 HPET（没有时用 PIT 通道 2）测量 read 读取的计数器的频率（Hz）。
+fn calibrate(mut read: impl FnMut() -> u64) -> u64 {
+    without_interrupts(|| {
+        let start = match hpet::frequency() {
+            Some(hz) => {
+                let mask = if hpet::is_64bit() {
+                    u64::MAX
+                } else {
+                    u32::MAX as u64
+                };
+                let target = hz * CALIBRATION_MS / 1000;
+                let reference = hpet::counter();
+                let start = read();
+                while hpet::counter().wrapping_sub(reference) & mask < target {
+                    spin_loop();
+                }
+                start
+            }
+            None => {
+                pit::start_one_shot(CALIBRATION_MS);
+                let start = read();
+                while !pit::one_shot_expired() {
+                    spin_loop();
+                }
+                start
+            }
+        };
+        read().wrapping_sub(start) * 1000 / CALIBRATION_MS
+    })
+}
+
+/// 初始化高精度的时钟：启用 HPET，校准 TSC 并选择时钟源；使用 APIC 时把时钟中断换成 Local APIC 定时器。
+/// 需要 ioremap 和已经确定的中断控制器，必须在 interrupts::init_controller 之后调用。返回选择的时钟源。
+pub fn init_clocksource() -> ClockSource {
+    if let Err(err) = hpet::init() {
+        if err != HpetError::NotPresent {
+            println!("failed to initialize the HPET: {:?}", err);
+        }
+    }
+    if tsc::is_supported() {
+        TSC_FREQUENCY.store(calibrate(tsc::read), Ordering::Relaxed);
+    }
+    // 恒定的 TSC 最好；否则 TSC 的频率可能随电源状态变化，优先使用 HPET。
+    let hpet = hpet::frequency().filter(|_| hpet::is_64bit());
+    match (tsc_frequency(), hpet) {
+        (Some(hz), _) if tsc::is_invariant() => set_clock_source(ClockSource::Tsc, hz),
+        (_, Some(hz)) => set_clock_source(ClockSource::Hpet, hz),
+        (Some(hz), None) => set_clock_source(ClockSource::Tsc, hz),
+        (None, None) => {}
+    }
+    if interrupts::controller() == ControllerKind::Apic {
+        init_local_apic_timer();
+    }
+    clock_source()
+}
+
+/// 校准 Local APIC 定时器，让它以 TICK_HZ 的频率产生时钟中断，然后停止 PIT。
+fn init_local_apic_timer() {
+    interrupts::start_timer_calibration();
+    let hz = calibrate(|| (u32::MAX - interrupts::timer_count()) as u64);
+    let initial_count = (hz / TICK_HZ).clamp(1, u32::MAX as u64) as u32;
+    without_interrupts(|| {
+        pit::stop();
+        interrupts::start_timer_periodic(interrupts::irq_vector(0), initial_count);
+        TICK_SOURCE.store(TickSource::LocalApic as u8, Ordering::Relaxed);
+    });
+}
+
+/// 从 RTC 读取当前日期和时间作为墙上时间的起点，返回读到的时间。
+pub fn init_wall_clock() -> DateTime {
+    let date_time = rtc::read();
+    let nanos = now_nanos();
+    without_interrupts(|| {
+        WALL_CLOCK_SECS.store(date_time.unix_timestamp(), Ordering::Relaxed);
+        WALL_CLOCK_BASE_NANOS.store(nanos, Ordering::Relaxed);
+    });
+    date_time
+}
+
+/// 墙上时间：1970-01-01 00:00:00 UTC 以来经过的时间。init_wall_clock 之前从 Unix 纪元开始计时。
+/// 墙上时间由单调时钟推进，不会倒退，但也不会跟随 RTC 的调整。
+pub fn wall_clock() -> Duration {
+    let (secs, base_nanos) = without_interrupts(|| {
+        (
+            WALL_CLOCK_SECS.load(Ordering::Relaxed),
+            WALL_CLOCK_BASE_NANOS.load(Ordering::Relaxed),
+        )
+    });
+    Duration::from_secs(secs) + Duration::from_nanos(now_nanos().saturating_sub(base_nanos))
+}
+
+/// 当前的日期和时间（UTC）。
+pub fn date_time() -> DateTime {
+    DateTime::from_unix_timestamp(wall_clock().as_secs())
+}
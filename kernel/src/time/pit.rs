@@ -0,0 +1,67 @@
+//! 8253/8254 可编程间隔定时器（PIT）。
+//!
+//! PIT 的输入时钟固定为 1.193182 MHz，有三个通道：通道 0 连接 IRQ 0，用作周期性的时钟中断；通道 2 连接扬声器，
+//! 它的输出可以从 0x61 端口读取，不需要中断就能等待一段确定的时间，用于校准其他时钟。
+
+use x86_64::instructions::port::Port;
+
+/// PIT 的输入频率（Hz）。
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// 键盘控制器的 B 端口：第 0 位是通道 2 的门控，第 1 位是扬声器，第 5 位是通道 2 的输出。
+const PORT_B: u16 = 0x61;
+
+/// 命令字：选择通道（第 6-7 位），先写低字节再写高字节（第 4-5 位），工作模式（第 1-3 位），二进制计数。
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const SELECT_CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LOW_HIGH: u8 = 0b11 << 4;
+/// 模式 0：计数到 0 时输出变高，之后不再重复。
+const MODE_ONE_SHOT: u8 = 0b000 << 1;
+/// 模式 2：频率发生器，每次计数到 0 时产生一个脉冲并重新装入初值。
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+/// hz 对应的计数初值。初值为 16 位，0 表示 65536。
+fn divisor(hz: u64) -> u16 {
+    (FREQUENCY / hz).clamp(1, 0xffff) as u16
+}
+
+fn write_count(channel: u16, count: u16) {
+    unsafe {
+        let mut port = Port::new(channel);
+        port.write(count as u8);
+        port.write((count >> 8) as u8);
+    }
+}
+
+/// 让通道 0 以 hz 的频率产生 IRQ 0。
+pub fn set_periodic(hz: u64) {
+    unsafe {
+        Port::new(COMMAND).write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH | MODE_RATE_GENERATOR);
+    }
+    write_count(CHANNEL_0, divisor(hz));
+}
+
+/// 停止通道 0：切换到模式 0 后，在写入新的初值之前不会计数，也不会再产生中断。
+pub fn stop() {
+    unsafe { Port::new(COMMAND).write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH | MODE_ONE_SHOT) };
+}
+
+/// 用通道 2 开始 ms 毫秒（最多约 54 毫秒）的倒计时，之后用 one_shot_expired 查询是否结束。
+pub fn start_one_shot(ms: u64) {
+    unsafe {
+        let mut port_b = Port::<u8>::new(PORT_B);
+        // 打开门控，关闭扬声器。
+        let value = port_b.read();
+        port_b.write((value & !0b10) | 0b1);
+        Port::new(COMMAND).write(SELECT_CHANNEL_2 | ACCESS_LOW_HIGH | MODE_ONE_SHOT);
+    }
+    write_count(CHANNEL_2, (FREQUENCY * ms / 1000).clamp(1, 0xffff) as u16);
+}
+
+/// start_one_shot 开始的倒计时是否已经结束。
+pub fn one_shot_expired() -> bool {
+    unsafe { Port::<u8>::new(PORT_B).read() & (1 << 5) != 0 }
+}
@@ -0,0 +1,215 @@
+//! CMOS 实时时钟（RTC）。
+//!
+//! CMOS 通过 0x70（寄存器下标）和 0x71（数据）两个端口访问。RTC 的日期和时间寄存器可能是 BCD 或二进制、
+//! 12 或 24 小时制，由状态寄存器 B 决定；RTC 每秒更新一次这些寄存器，更新期间（状态寄存器 A 的 UIP 位）读到的
+//! 值可能不一致，所以等更新结束后读取，并且重复读取直到连续两次的结果相同。
+//!
+//! RTC 还可以在 IRQ 8 上产生周期性中断，每次中断都必须读取状态寄存器 C，否则不会再产生下一次中断。
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+use super::DateTime;
+use crate::{
+    acpi,
+    interrupts::{self, IrqError, IrqHandlerId, IrqReturn},
+};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// RTC 的寄存器。
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_C: u8 = 0x0c;
+
+/// 状态寄存器 A：正在更新；低 4 位是周期性中断的频率选择。
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0f;
+/// 状态寄存器 B：24 小时制、二进制格式、允许周期性中断。
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_PERIODIC: u8 = 1 << 6;
+/// 状态寄存器 C：发生了周期性中断。
+const STATUS_C_PERIODIC: u8 = 1 << 6;
+/// 12 小时制时小时寄存器的最高位表示下午。
+const HOUR_PM: u8 = 1 << 7;
+
+/// RTC 的中断线。
+pub const RTC_IRQ: u8 = 8;
+/// RTC 的基准频率（Hz）。
+const BASE_FREQUENCY: u32 = 32768;
+
+/// CMOS 的下标和数据要成对访问，RTC 中断处理函数也会读写 CMOS，只在关闭中断时加锁。
+static CMOS: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// 周期性中断的频率必须是 2 到 8192 之间的 2 的幂。
+    InvalidFrequency,
+    Irq(IrqError),
+}
+
+/// 读取 CMOS 寄存器，调用者需要持有 CMOS 锁。写下标时最高位为 0，保持 NMI 开启。
+unsafe fn read_register(register: u8) -> u8 {
+    Port::new(CMOS_INDEX).write(register);
+    Port::new(CMOS_DATA).read()
+}
+
+unsafe fn write_register(register: u8, value: u8) {
+    Port::new(CMOS_INDEX).write(register);
+    Port::new(CMOS_DATA).write(value)
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// RTC 寄存器的原始值。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn read_raw(century_register: Option<u8>) -> RawTime {
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            while read_register(STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+                core::hint::spin_loop();
+            }
+            RawTime {
+                second: read_register(SECONDS),
+                minute: read_register(MINUTES),
+                hour: read_register(HOURS),
+                day: read_register(DAY),
+                month: read_register(MONTH),
+                year: read_register(YEAR),
+                century: century_register.map_or(0, |register| read_register(register)),
+            }
+        }
+    })
+}
+
+/// 读取 RTC 的当前时间（RTC 通常设置为 UTC）。
+pub fn read() -> DateTime {
+    // FADT 中有世纪寄存器时使用它，否则认为是 21 世纪。
+    let century_register = acpi::century_register();
+    let mut raw = read_raw(century_register);
+    loop {
+        let again = read_raw(century_register);
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+    let status_b = without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe { read_register(STATUS_B) }
+    });
+    let pm = raw.hour & HOUR_PM != 0;
+    let mut hour = raw.hour & !HOUR_PM;
+    let [second, minute, day, month, year, century] = [
+        raw.second,
+        raw.minute,
+        raw.day,
+        raw.month,
+        raw.year,
+        raw.century,
+    ]
+    .map(|value| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    });
+    if status_b & STATUS_B_BINARY == 0 {
+        hour = bcd_to_binary(hour);
+    }
+    // 12 小时制中 12 点是 0 点（或 12 点），其余下午的时间加 12。
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+    let century = if century_register.is_some() {
+        century as u16
+    } else {
+        20
+    };
+    DateTime {
+        year: century * 100 + year as u16,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+/// 周期性中断的次数。
+static PERIODIC_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// RTC 周期性中断的次数。
+pub fn periodic_interrupts() -> u64 {
+    PERIODIC_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+fn rtc_interrupt() -> IrqReturn {
+    let _cmos = CMOS.lock();
+    // 读取状态寄存器 C 才能收到下一次中断。
+    let status_c = unsafe { read_register(STATUS_C) };
+    if status_c & STATUS_C_PERIODIC == 0 {
+        return IrqReturn::None;
+    }
+    PERIODIC_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    IrqReturn::Handled
+}
+
+/// 以 hz 的频率在 IRQ 8 上产生周期性中断，返回的处理函数交给 disable_periodic_interrupt 关闭中断。
+pub fn enable_periodic_interrupt(hz: u32) -> Result<IrqHandlerId, RtcError> {
+    if !hz.is_power_of_two() || !(2..=8192).contains(&hz) {
+        return Err(RtcError::InvalidFrequency);
+    }
+    // 频率为 32768 >> (rate - 1)。
+    let rate = (BASE_FREQUENCY / hz).trailing_zeros() as u8 + 1;
+    let handler = interrupts::register_irq(RTC_IRQ, "rtc", rtc_interrupt).map_err(RtcError::Irq)?;
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_a = read_register(STATUS_A);
+            write_register(STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+            let status_b = read_register(STATUS_B);
+            write_register(STATUS_B, status_b | STATUS_B_PERIODIC);
+            // 清除之前可能已经产生的中断。
+            read_register(STATUS_C);
+        }
+    });
+    Ok(handler)
+}
+
+/// 关闭周期性中断并注销处理函数。
+pub fn disable_periodic_interrupt(handler: IrqHandlerId) {
+    without_interrupts(|| {
+        let _cmos = CMOS.lock();
+        unsafe {
+            let status_b = read_register(STATUS_B);
+            write_register(STATUS_B, status_b & !STATUS_B_PERIODIC);
+            read_register(STATUS_C);
+        }
+    });
+    interrupts::unregister_irq(handler);
+}
@@ -0,0 +1,183 @@
+//! 基于时间轮的异步定时器：sleep、interval 和 timeout，可以在执行器的任务中 await。
+
+use core::{
+    fmt,
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+
+use super::{
+    now, ticks,
+    wheel::{self, TimerHandle},
+    Duration, Instant, NANOS_PER_SEC, TICK_HZ,
+};
+
+/// 等待到 deadline 需要推进到的 tick。tick 与单调时钟不完全同步，提前唤醒时 Sleep 会重新等待。
+fn deadline_tick(deadline: Instant) -> u64 {
+    let remaining = deadline.duration_since(now()).as_nanos() as u64;
+    let tick_nanos = NANOS_PER_SEC / TICK_HZ;
+    ticks() + remaining.div_ceil(tick_nanos).max(1)
+}
+
+/// sleep 和 sleep_until 返回的 Future。
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Sleep {
+    deadline: Instant,
+    timer: Option<TimerHandle>,
+}
+
+/// 等待 duration。
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(now() + duration)
+}
+
+/// 等待到 deadline。
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        timer: None,
+    }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        now() >= self.deadline
+    }
+
+    /// 修改到期时间，已经完成的 Sleep 可以重新等待。
+    pub fn reset(&mut self, deadline: Instant) {
+        self.deadline = deadline;
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_elapsed() {
+            if let Some(timer) = self.timer.take() {
+                wheel::cancel(timer);
+            }
+            return Poll::Ready(());
+        }
+        let tick = deadline_tick(self.deadline);
+        match &self.timer {
+            Some(timer) => wheel::update(timer, tick, cx.waker()),
+            None => self.timer = Some(wheel::register(tick, cx.waker())),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            wheel::cancel(timer);
+        }
+    }
+}
+
+/// 周期性的定时器，由 interval 创建。
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+/// 每隔 period 完成一次的定时器，第一次立即完成。period 为 0 时 panic。
+/// 任务没有及时等待而错过了若干个周期时，不会连续补上，而是从当前时刻重新开始计算周期。
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        period,
+        sleep: sleep_until(now()),
+    }
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// 等待下一个周期，返回这个周期预定的时刻。
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let deadline = self.sleep.deadline();
+        let now = now();
+        let mut next = deadline + self.period;
+        if next <= now {
+            next = now + self.period;
+        }
+        self.sleep.reset(next);
+        Poll::Ready(deadline)
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+
+/// timeout 超时时返回的错误。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+/// timeout 和 timeout_at 返回的 Future。
+#[derive(Debug)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// 等待 future 完成，最多等待 duration，超时后 future 被丢弃。
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    timeout_at(now() + duration, future)
+}
+
+/// 等待 future 完成，最多等待到 deadline。
+pub fn timeout_at<F: Future>(deadline: Instant, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep_until(deadline),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // future 可能不是 Unpin 的，只通过 Pin 访问；sleep 是 Unpin 的。
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep)
+            .poll(cx)
+            .map(|()| Err(Elapsed(())))
+    }
+}
@@ -0,0 +1,27 @@
+//! 时间戳计数器（TSC）：每个时钟周期加一的 64 位计数器，用 rdtsc 读取，开销很小。
+//! TSC 的频率没有可靠的方法直接得到，需要用已知频率的时钟（HPET 或 PIT）校准。
+
+/// CPU 是否有 TSC（CPUID.01H:EDX 的第 4 位）。
+pub fn is_supported() -> bool {
+    // 较新的编译器中 __cpuid 不再是 unsafe 的。
+    #[allow(unused_unsafe)]
+    let edx = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+    edx & (1 << 4) != 0
+}
+
+/// TSC 是否是恒定的（CPUID.80000007H:EDX 的第 8 位）：频率不随 CPU 的频率和电源状态变化，可以作为时钟源。
+pub fn is_invariant() -> bool {
+    #[allow(unused_unsafe)]
+    unsafe {
+        use core::arch::x86_64::__cpuid;
+        __cpuid(0x8000_0000).eax >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+    }
+}
+
+/// 读取 TSC。
+pub fn read() -> u64 {
+    #[allow(unused_unsafe)]
+    unsafe {
+        core::arch::x86_64::_rdtsc()
+    }
+}
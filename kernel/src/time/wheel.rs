@@ -0,0 +1,242 @@
+//! 分层时间轮：保存等待某个 tick 的 Waker，由时钟中断推进。
+//!
+//! 共 LEVELS 层，每层 SLOTS 个槽，第 l 层的一个槽覆盖 SLOTS^l 个 tick。定时器按到期时间与当前 tick 最高的不同位
+//! 放入对应层的槽中；低层转完一圈时，把高层下一个槽中的定时器重新插入（逐层下移），到期的定时器在第 0 层被触发。
+//! 插入、删除和每个 tick 的推进都是 O(1) 的。
+//!
+//! 定时器保存在 entries 中，槽里是以下标串起来的双向链表，推进时只修改下标，中断中不会分配或释放内存。
+//! 触发时只调用 wake_by_ref，Waker 由定时器的所有者在任务中取消定时器时释放。
+//! 所有操作都在关闭中断时加锁，时钟中断中调用 Waker 时持有锁，所以 Waker 不能再访问时间轮
+//! （执行器的 TaskWaker 只是把任务放回队列）。
+
+use alloc::vec::Vec;
+use core::task::Waker;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// 6 层可以表示 2^36 个 tick，1000 Hz 时约两年，更远的定时器先放在最高层，转到时重新插入。
+const LEVELS: usize = 6;
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// 空闲，串在空闲链表中。
+    Free { next: Option<u32> },
+    /// 在第 level 层的 slot 槽的链表中。
+    Pending {
+        level: u8,
+        slot: u8,
+        prev: Option<u32>,
+        next: Option<u32>,
+    },
+    /// 已经到期。
+    Fired,
+}
+
+struct Entry {
+    deadline: u64,
+    waker: Option<Waker>,
+    state: State,
+}
+
+struct Wheel {
+    /// 已经处理到的 tick。
+    elapsed: u64,
+    entries: Vec<Entry>,
+    free: Option<u32>,
+    slots: [[Option<u32>; SLOTS]; LEVELS],
+}
+
+static WHEEL: Mutex<Wheel> = Mutex::new(Wheel {
+    elapsed: 0,
+    entries: Vec::new(),
+    free: None,
+    slots: [[None; SLOTS]; LEVELS],
+});
+
+/// 时间轮中的一个定时器，由 register 返回，必须用 cancel 释放。
+#[derive(Debug)]
+pub(super) struct TimerHandle(u32);
+
+impl Wheel {
+    fn entry(&mut self, index: u32) -> &mut Entry {
+        &mut self.entries[index as usize]
+    }
+
+    fn set_prev(&mut self, index: u32, value: Option<u32>) {
+        if let State::Pending { prev, .. } = &mut self.entry(index).state {
+            *prev = value;
+        }
+    }
+
+    fn set_next(&mut self, index: u32, value: Option<u32>) {
+        if let State::Pending { next, .. } = &mut self.entry(index).state {
+            *next = value;
+        }
+    }
+
+    /// 如果定时器在某个槽中，把它从链表中摘下来。
+    fn unlink(&mut self, index: u32) {
+        if let State::Pending {
+            level,
+            slot,
+            prev,
+            next,
+        } = self.entry(index).state
+        {
+            match prev {
+                Some(prev) => self.set_next(prev, next),
+                None => self.slots[level as usize][slot as usize] = next,
+            }
+            if let Some(next) = next {
+                self.set_prev(next, prev);
+            }
+            self.entry(index).state = State::Fired;
+        }
+    }
+
+    fn fire(&mut self, index: u32) {
+        let entry = self.entry(index);
+        entry.state = State::Fired;
+        if let Some(waker) = &entry.waker {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// 按到期时间把定时器放进对应的槽，已经到期的直接触发。
+    fn insert(&mut self, index: u32) {
+        let deadline = self.entry(index).deadline;
+        if deadline <= self.elapsed {
+            self.fire(index);
+            return;
+        }
+        let highest_bit = 63 - (self.elapsed ^ deadline).leading_zeros();
+        let level = (highest_bit / SLOT_BITS) as usize;
+        let (level, slot) = if level < LEVELS {
+            (
+                level,
+                (deadline >> (level as u32 * SLOT_BITS)) as usize % SLOTS,
+            )
+        } else {
+            // 超出范围：放在最高层最晚转到的槽。
+            let level = LEVELS - 1;
+            let current = (self.elapsed >> (level as u32 * SLOT_BITS)) as usize;
+            (level, (current + SLOTS - 1) % SLOTS)
+        };
+        let head = self.slots[level][slot];
+        self.entry(index).state = State::Pending {
+            level: level as u8,
+            slot: slot as u8,
+            prev: None,
+            next: head,
+        };
+        if let Some(head) = head {
+            self.set_prev(head, Some(index));
+        }
+        self.slots[level][slot] = Some(index);
+    }
+
+    /// 推进一个 tick。
+    fn tick(&mut self) {
+        self.elapsed += 1;
+        let now = self.elapsed;
+        // 从高到低，把转到的槽中的定时器重新插入到更低的层。
+        for level in (1..LEVELS).rev() {
+            let shift = level as u32 * SLOT_BITS;
+            if now & ((1 << shift) - 1) != 0 {
+                continue;
+            }
+            let slot = (now >> shift) as usize % SLOTS;
+            let mut next = self.slots[level][slot].take();
+            while let Some(index) = next {
+                next = match self.entry(index).state {
+                    State::Pending { next, .. } => next,
+                    _ => None,
+                };
+                self.insert(index);
+            }
+        }
+        let mut next = self.slots[0][now as usize % SLOTS].take();
+        while let Some(index) = next {
+            next = match self.entry(index).state {
+                State::Pending { next, .. } => next,
+                _ => None,
+            };
+            self.fire(index);
+        }
+    }
+}
+
+/// 推进时间轮到 now，由时钟中断调用。
+pub(super) fn advance(now: u64) {
+    let mut wheel = WHEEL.lock();
+    while wheel.elapsed < now {
+        wheel.tick();
+    }
+}
+
+/// 注册一个在 deadline（tick）到期时唤醒 waker 的定时器。
+pub(super) fn register(deadline: u64, waker: &Waker) -> TimerHandle {
+    without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let entry = Entry {
+            deadline,
+            waker: Some(waker.clone()),
+            state: State::Fired,
+        };
+        let index = match wheel.free {
+            Some(index) => {
+                wheel.free = match wheel.entry(index).state {
+                    State::Free { next } => next,
+                    _ => None,
+                };
+                *wheel.entry(index) = entry;
+                index
+            }
+            None => {
+                wheel.entries.push(entry);
+                (wheel.entries.len() - 1) as u32
+            }
+        };
+        wheel.insert(index);
+        TimerHandle(index)
+    })
+}
+
+/// 修改定时器的到期时间和要唤醒的 Waker，已经触发的定时器重新开始等待。
+pub(super) fn update(timer: &TimerHandle, deadline: u64, waker: &Waker) {
+    let old = without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        let entry = wheel.entry(timer.0);
+        let fired = matches!(entry.state, State::Fired);
+        let old = match &entry.waker {
+            Some(old) if old.will_wake(waker) => None,
+            _ => entry.waker.replace(waker.clone()),
+        };
+        if fired || entry.deadline != deadline {
+            wheel.unlink(timer.0);
+            wheel.entry(timer.0).deadline = deadline;
+            wheel.insert(timer.0);
+        }
+        old
+    });
+    // 在锁外释放旧的 Waker。
+    drop(old);
+}
+
+/// 删除定时器。
+pub(super) fn cancel(timer: TimerHandle) {
+    let waker = without_interrupts(|| {
+        let mut wheel = WHEEL.lock();
+        wheel.unlink(timer.0);
+        let free = wheel.free;
+        let entry = wheel.entry(timer.0);
+        entry.state = State::Free { next: free };
+        let waker = entry.waker.take();
+        wheel.free = Some(timer.0);
+        waker
+    });
+    drop(waker);
+}
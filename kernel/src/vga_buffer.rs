@@ -148,6 +148,17 @@ pub fn _print(args: fmt::Arguments) {
     });
 }
 
+/// 不等待锁的输出：锁被占用时丢弃这条信息并返回 false。用于 NMI 这类不受 cli 影响、可能打断正在输出的代码的场合，
+/// 这时等待锁会死锁。
+pub fn try_print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    match WRITER.try_lock() {
+        Some(mut writer) => writer.write_fmt(args).is_ok(),
+        None => false,
+    }
+}
+
 #[macro_export] // 使得 print! 和 println! 宏可以在其他模块中使用
 macro_rules! print {
     ($($arg:tt)*) => {
@@ -0,0 +1,195 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::{self, AddressSpace, BuddyFrameAllocator, USER_SPACE_START};
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{Page, PageTableFlags, PhysFrame},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT.union(PageTableFlags::WRITABLE);
+
+fn user_page() -> Page {
+    Page::containing_address(VirtAddr::new(USER_SPACE_START))
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap()
+}
+
+#[test_case]
+fn user_mappings_are_private() {
+    let page = user_page();
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let mut a = AddressSpace::new().unwrap();
+    let mut b = AddressSpace::new().unwrap();
+    a.map(page, FLAGS).unwrap();
+    b.map(page, FLAGS).unwrap();
+    assert_ne!(
+        a.translate(page.start_address()),
+        b.translate(page.start_address())
+    );
+    unsafe {
+        a.switch();
+        ptr.write_volatile(1);
+        b.switch();
+        ptr.write_volatile(2);
+        a.switch();
+        assert_eq!(ptr.read_volatile(), 1);
+        memory::switch_to_kernel();
+    }
+    // 内核页表中没有用户区域的映射。
+    assert_eq!(
+        memory::translate_addr(page.start_address(), memory::phys_offset()),
+        None
+    );
+}
+
+#[test_case]
+fn kernel_half_is_shared() {
+    let space = AddressSpace::new().unwrap();
+    let value = 42u64;
+    let addr = VirtAddr::from_ptr(&value);
+    assert_eq!(
+        space.translate(addr),
+        memory::translate_addr(addr, memory::phys_offset())
+    );
+    unsafe {
+        space.switch();
+        assert_eq!(core::ptr::read_volatile(&value), 42);
+        memory::switch_to_kernel();
+    }
+}
+
+#[test_case]
+fn protect_and_unmap() {
+    let page = user_page();
+    let mut space = AddressSpace::new().unwrap();
+    space.map(page, FLAGS).unwrap();
+    space.protect(page, PageTableFlags::PRESENT).unwrap();
+    assert!(space.translate(page.start_address()).is_some());
+    space.unmap(page).unwrap();
+    assert_eq!(space.translate(page.start_address()), None);
+    assert!(space.unmap(page).is_err());
+}
+
+#[test_case]
+fn drop_frees_frames() {
+    let free = free_frames();
+    {
+        let mut space = AddressSpace::new().unwrap();
+        for i in 0..4 {
+            space.map(user_page() + i, FLAGS).unwrap();
+        }
+        assert!(free_frames() < free);
+    }
+    assert_eq!(free_frames(), free);
+}
+
+fn ref_count(space: &AddressSpace, page: Page) -> usize {
+    let frame = PhysFrame::containing_address(space.translate(page.start_address()).unwrap());
+    memory::with_kernel_memory(|mem| mem.frame_allocator.ref_count(frame)).unwrap()
+}
+
+#[test_case]
+fn fork_copies_on_write() {
+    let page = user_page();
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let free = free_frames();
+    {
+        let mut parent = AddressSpace::new().unwrap();
+        parent.map(page, FLAGS).unwrap();
+        unsafe {
+            parent.switch();
+            ptr.write_volatile(1);
+            memory::switch_to_kernel();
+        }
+        let shared = parent.translate(page.start_address());
+        let child = parent.fork().unwrap();
+        assert_eq!(child.translate(page.start_address()), shared);
+        assert_eq!(ref_count(&parent, page), 2);
+        unsafe {
+            child.switch();
+            assert_eq!(ptr.read_volatile(), 1);
+            // 写入时复制出私有的页帧。
+            ptr.write_volatile(2);
+            parent.switch();
+            assert_eq!(ptr.read_volatile(), 1);
+            // 父地址空间已经是唯一的所有者，直接恢复写权限。
+            ptr.write_volatile(3);
+            child.switch();
+            assert_eq!(ptr.read_volatile(), 2);
+            memory::switch_to_kernel();
+        }
+        assert_ne!(child.translate(page.start_address()), shared);
+        assert_eq!(parent.translate(page.start_address()), shared);
+        assert_eq!(ref_count(&parent, page), 1);
+        assert_eq!(ref_count(&child, page), 1);
+    }
+    assert_eq!(free_frames(), free);
+}
+
+/// 在活跃的地址空间中 fork：刷新 TLB 时不能丢掉 CR3 中的 PCID，父地址空间之后的写入也必须触发写时复制。
+#[test_case]
+fn fork_active_with_pcid() {
+    let page = user_page();
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let mut parent = AddressSpace::new().unwrap();
+    let pcid = parent.pcid().expect("PCID is not enabled");
+    parent.map(page, FLAGS).unwrap();
+    unsafe {
+        parent.switch();
+        // 让可写的页表项进入 TLB。
+        ptr.write_volatile(1);
+        let child = parent.fork().unwrap();
+        assert_eq!(Cr3::read_raw().1, pcid.value());
+        // TLB 中的旧项已经清除，这次写入会复制出私有的页帧，子地址空间看不到。
+        ptr.write_volatile(2);
+        child.switch();
+        assert_eq!(ptr.read_volatile(), 1);
+        parent.switch();
+        assert_eq!(ptr.read_volatile(), 2);
+        memory::switch_to_kernel();
+    }
+}
+
+#[test_case]
+fn dropping_a_fork_keeps_shared_frames() {
+    let page = user_page();
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let mut parent = AddressSpace::new().unwrap();
+    parent.map(page, FLAGS).unwrap();
+    drop(parent.fork().unwrap());
+    assert_eq!(ref_count(&parent, page), 1);
+    unsafe {
+        parent.switch();
+        ptr.write_volatile(7);
+        assert_eq!(ptr.read_volatile(), 7);
+        memory::switch_to_kernel();
+    }
+}
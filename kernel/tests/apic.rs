@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    acpi,
+    interrupts::{self, ControllerKind, InterruptIndex},
+    memory::{self, BuddyFrameAllocator},
+};
+use x86_64::instructions::port::Port;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+    interrupts::init_controller();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_is_parsed() {
+    let madt = acpi::madt().expect("QEMU provides a MADT");
+    assert!(madt.processors().any(|cpu| cpu.enabled));
+    assert!(madt.io_apics().any(|io| io.gsi_base == 0));
+    // QEMU 把 PIT（IRQ 0）连接到 GSI 2。
+    assert_eq!(madt.isa_interrupt(0).gsi, 2);
+    assert_eq!(madt.isa_interrupt(1).gsi, 1);
+}
+
+#[test_case]
+fn apic_replaces_the_pic() {
+    assert_eq!(interrupts::controller(), ControllerKind::Apic);
+    // 8259 的所有中断都被屏蔽。
+    let masks: [u8; 2] = unsafe { [Port::new(0x21).read(), Port::new(0xa1).read()] };
+    assert_eq!(masks, [0xff, 0xff]);
+    assert!(!interrupts::is_irq_masked(0));
+    assert!(!interrupts::is_irq_masked(1));
+}
+
+#[test_case]
+fn timer_interrupts_arrive_through_the_io_apic() {
+    let start = interrupts::timer_ticks();
+    while interrupts::timer_ticks() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn irqs_can_be_masked() {
+    interrupts::disable_irq(0);
+    assert!(interrupts::is_irq_masked(0));
+    let start = interrupts::timer_ticks();
+    // PIT 大约每 55ms 中断一次，屏蔽期间忙等一段时间不应该收到时钟中断。
+    for _ in 0..10_000_000 {
+        core::hint::spin_loop();
+    }
+    assert_eq!(interrupts::timer_ticks(), start);
+    interrupts::enable_irq(0);
+    while interrupts::timer_ticks() == start {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn vectors_are_unchanged() {
+    assert_eq!(interrupts::irq_vector(0), InterruptIndex::Timer.as_u8());
+    assert_eq!(interrupts::irq_vector(1), InterruptIndex::Keyboard.as_u8());
+}
+
+/// 屏蔽的 8259 仍然可能在 IRQ 7 的向量上发出伪中断，它没有经过 Local APIC，不能被当作 IRQ 7 分发，也不能发送 EOI。
+#[test_case]
+fn pic_spurious_irq_is_ignored() {
+    let before = interrupts::irq_count(7);
+    // 软件中断和 8259 的伪中断一样不经过 Local APIC。
+    unsafe { core::arch::asm!("int {vector}", vector = const interrupts::PIC_1_OFFSET + 7) };
+    assert_eq!(interrupts::irq_count(7), before);
+}
@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    backtrace::{self, Frame, MAX_FRAMES},
+    memory,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    memory::init(boot_info.physical_memory_offset);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[inline(never)]
+fn innermost() -> [Option<Frame>; MAX_FRAMES] {
+    let mut frames = [None; MAX_FRAMES];
+    for (slot, frame) in frames.iter_mut().zip(backtrace::capture()) {
+        *slot = Some(frame);
+    }
+    frames
+}
+
+#[inline(never)]
+fn middle() -> [Option<Frame>; MAX_FRAMES] {
+    let frames = innermost();
+    core::hint::black_box(frames)
+}
+
+#[inline(never)]
+fn outer() -> [Option<Frame>; MAX_FRAMES] {
+    let frames = middle();
+    core::hint::black_box(frames)
+}
+
+#[test_case]
+fn symbol_table_is_embedded() {
+    assert!(backtrace::is_available());
+}
+
+#[test_case]
+fn lookup_resolves_function_start() {
+    let addr = VirtAddr::new(outer as fn() -> [Option<Frame>; MAX_FRAMES] as u64);
+    let symbol = backtrace::lookup(addr).expect("no symbol for outer");
+    assert!(symbol.name.ends_with("backtrace::outer"));
+    assert_eq!(symbol.start, addr);
+    assert_eq!(symbol.offset, 0);
+    let symbol = backtrace::lookup(addr + 1u64).expect("no symbol inside outer");
+    assert_eq!(symbol.offset, 1);
+}
+
+#[test_case]
+fn lookup_outside_text_fails() {
+    assert!(backtrace::lookup(VirtAddr::new(0x1000)).is_none());
+}
+
+#[test_case]
+fn capture_walks_frame_pointers() {
+    let frames = outer();
+    let names = frames
+        .iter()
+        .flatten()
+        .map(|frame| frame.symbol().expect("unresolved frame").name);
+    let mut expected = ["backtrace::middle", "backtrace::outer"].into_iter();
+    let mut next = expected.next();
+    for name in names {
+        if next.map_or(false, |suffix| name.ends_with(suffix)) {
+            next = expected.next();
+        }
+    }
+    assert!(next.is_none(), "callers missing from the backtrace");
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::{
+    self,
+    vma::{self, VmaError, VmaKind},
+    AddressSpace, BuddyFrameAllocator, USER_SPACE_START,
+};
+use x86_64::{structures::paging::PageTableFlags, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+const FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+/// 测试使用的内核区域，位于 P4 的第 160 项，没有被内核或 bootloader 使用。
+const KERNEL_REGION: u64 = 0x0000_5000_0000_0000;
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap()
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::translate_addr(addr, memory::phys_offset()).is_some()
+}
+
+#[test_case]
+fn pages_are_mapped_on_first_access() {
+    let start = VirtAddr::new(KERNEL_REGION);
+    vma::register_vma(start, 16 * 4096, FLAGS, VmaKind::Anonymous).unwrap();
+    assert!(!is_mapped(start));
+    assert!(!is_mapped(start + 5 * 4096u64));
+
+    let ptr: *mut u64 = (start + 5 * 4096u64).as_mut_ptr();
+    unsafe {
+        // 新分配的页是清零的。
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(0xdead_beef);
+        assert_eq!(ptr.read_volatile(), 0xdead_beef);
+    }
+    assert!(is_mapped(start + 5 * 4096u64));
+    // 只有访问过的页被映射。
+    assert!(!is_mapped(start));
+    assert!(!is_mapped(start + 6 * 4096u64));
+
+    let before = free_frames();
+    let vma = vma::unregister_vma(start).unwrap();
+    assert_eq!(vma.kind, VmaKind::Anonymous);
+    assert_eq!(free_frames(), before + 1);
+    assert!(!is_mapped(start + 5 * 4096u64));
+}
+
+#[test_case]
+fn invalid_regions_are_rejected() {
+    let start = VirtAddr::new(KERNEL_REGION);
+    assert_eq!(
+        vma::register_vma(start + 1u64, 4096, FLAGS, VmaKind::Heap),
+        Err(VmaError::Unaligned)
+    );
+    assert_eq!(
+        vma::register_vma(VirtAddr::new(USER_SPACE_START), 4096, FLAGS, VmaKind::Heap),
+        Err(VmaError::OutOfRange)
+    );
+    vma::register_vma(start, 4 * 4096, FLAGS, VmaKind::Heap).unwrap();
+    assert_eq!(
+        vma::register_vma(start + 3 * 4096u64, 4096, FLAGS, VmaKind::Heap),
+        Err(VmaError::Overlap)
+    );
+    vma::unregister_vma(start).unwrap();
+    assert!(vma::unregister_vma(start).is_none());
+}
+
+#[test_case]
+fn user_regions_belong_to_their_address_space() {
+    let start = VirtAddr::new(USER_SPACE_START);
+    let ptr: *mut u64 = start.as_mut_ptr();
+    let mut space = AddressSpace::new().unwrap();
+    space
+        .add_vma(start, 4 * 4096, FLAGS, VmaKind::Stack)
+        .unwrap();
+    assert!(space.translate(start).is_none());
+    unsafe {
+        space.switch();
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(
+            vma::find_vma(start).map(|vma| vma.kind),
+            Some(VmaKind::Stack)
+        );
+        memory::switch_to_kernel();
+    }
+    // 内核页表中看不到这个区域。
+    assert!(vma::find_vma(start).is_none());
+    assert!(space.translate(start).is_some());
+
+    let before = free_frames();
+    space.remove_vma(start).unwrap();
+    assert!(space.translate(start).is_none());
+    assert_eq!(free_frames(), before + 1);
+}
@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, panic::PanicInfo};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn debug_exception_returns() {
+    unsafe { asm!("int 1") };
+}
+
+#[test_case]
+fn nmi_returns() {
+    unsafe { asm!("int 2") };
+}
@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::BuddyFrameAllocator;
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size2MiB},
+    PhysAddr,
+};
+
+entry_point!(main);
+
+/// 测试函数没有参数，所以分配器放在全局变量中。
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let frame_allocator = unsafe {
+        BuddyFrameAllocator::init(&boot_info.memory_map, boot_info.physical_memory_offset)
+    };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let free = frames.free_frames();
+    let frame: PhysFrame = frames.allocate_frame().expect("out of frames");
+    assert_eq!(frames.free_frames(), free - 1);
+    unsafe { frames.deallocate_frame(frame) };
+    assert_eq!(frames.free_frames(), free);
+    let reused: Option<PhysFrame> = frames.allocate_frame();
+    assert_eq!(reused, Some(frame));
+    unsafe { frames.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn frames_are_distinct() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let mut allocated: [Option<PhysFrame>; 64] = [None; 64];
+    for slot in allocated.iter_mut() {
+        *slot = frames.allocate_frame();
+        assert!(slot.is_some());
+    }
+    for (i, a) in allocated.iter().enumerate() {
+        for b in &allocated[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+    for frame in allocated.iter().flatten() {
+        unsafe { frames.deallocate_frame(*frame) };
+    }
+}
+
+#[test_case]
+fn contiguous_allocation() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let free = frames.free_frames();
+    let range = frames.allocate_contiguous(5).expect("out of frames");
+    assert_eq!(range.count(), 5);
+    // 按 2^order 个页帧对齐。
+    assert!(range.start.start_address().is_aligned(8 * 4096u64));
+    assert_eq!(frames.free_frames(), free - 5);
+    unsafe { frames.deallocate_contiguous(range) };
+    assert_eq!(frames.free_frames(), free);
+}
+
+#[test_case]
+fn huge_frame_allocation() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let free = frames.free_frames();
+    let frame: PhysFrame<Size2MiB> = frames.allocate_frame().expect("out of frames");
+    assert!(frame.start_address().is_aligned(2 * 1024 * 1024u64));
+    assert_eq!(frames.free_frames(), free - 512);
+    unsafe { frames.deallocate_frame(frame) };
+    assert_eq!(frames.free_frames(), free);
+}
+
+/// 超出物理内存范围的页帧（例如 MMIO）不由分配器管理，引用计数操作都被忽略。
+#[test_case]
+fn unmanaged_frame_is_ignored() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let frames = guard.as_mut().unwrap();
+    let free = frames.free_frames();
+    let frame = PhysFrame::containing_address(PhysAddr::new(1 << 40));
+    assert_eq!(frames.ref_count(frame), 0);
+    frames.add_ref(frame);
+    assert_eq!(frames.ref_count(frame), 0);
+    unsafe { frames.deallocate_frame(frame) };
+    assert_eq!(frames.free_frames(), free);
+}
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, panic::PanicInfo};
+use kernel::{
+    qemu::{exit_qemu, QemuExitCode},
+    serial_print, serial_println,
+    test_support::{expect_panic_message, Buffer},
+};
+
+entry_point!(main);
+
+/// 出错指令的地址，由触发异常的汇编代码写入。
+static mut FAULT_RIP: u64 = 0;
+
+/// 加载到 DS 的选择子：LDT 的第 0x246 项，而内核没有 LDT。
+const SELECTOR: u16 = 0x1234;
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("general_protection::fault_is_decoded...\t");
+    kernel::init();
+    unsafe {
+        asm!(
+            "lea rax, [rip + 2f]",
+            "mov [rip + {rip}], rax",
+            "2:",
+            "mov ds, {selector:x}",
+            rip = sym FAULT_RIP,
+            selector = in(reg) SELECTOR,
+            out("rax") _,
+        );
+    }
+    serial_println!("[failed]");
+    serial_println!("Error: loading an invalid selector did not fault");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let rip = Buffer::format(format_args!("Instruction Pointer: {:#x}", unsafe {
+        FAULT_RIP
+    }));
+    let expected = [
+        "EXCEPTION: GENERAL PROTECTION FAULT (#GP, vector 13)",
+        "Error Code: 0x1234 (selector: LDT index 0x246)",
+        rip.as_str(),
+    ];
+    expect_panic_message(info, &expected, &[])
+}
@@ -3,26 +3,36 @@
 #![feature(custom_test_frameworks)]
 #![test_runner(kernel::test_runner)]
 #![reexport_test_harness_main = "test_main"]
-#![cfg(allocator = "linked_list")]
 extern crate alloc;
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use kernel::allocator::{self, HEAP_SIZE};
+use kernel::{
+    allocator::{self, HEAP_SIZE, HEAP_START},
+    memory,
+};
+use x86_64::{
+    structures::paging::{
+        mapper::{MapToError, MappedFrame, TranslateResult},
+        FrameAllocator, PageSize, PageTableFlags, PhysFrame, Size2MiB, Translate,
+    },
+    VirtAddr,
+};
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use kernel::allocator;
-    use kernel::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
+    use kernel::memory::{self, BuddyFrameAllocator};
 
     kernel::init();
     let phys_mem_offset = boot_info.physical_memory_offset;
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
 
     test_main();
     loop {}
@@ -62,9 +72,121 @@ fn many_boxes() {
     }
 }
 
-#[cfg(allocator = "linked_list")]
+/// 超过初始堆大小的分配会触发堆扩展。
+#[test_case]
+fn heap_grows_on_demand() {
+    let n = HEAP_SIZE * 4;
+    let mut vec = Vec::with_capacity(n);
+    for i in 0..n {
+        vec.push(i as u8);
+    }
+    assert!(allocator::heap_size() > HEAP_SIZE);
+    assert_eq!(vec[n - 1], (n - 1) as u8);
+}
+
+/// 大块的堆扩展在 2 MiB 边界上使用大页。
+#[test_case]
+fn large_growth_uses_huge_pages() {
+    // 5 MiB 的区域中至少有一个完整的、按 2 MiB 对齐的块。
+    let buffer = vec![1u8; 5 * 1024 * 1024];
+    let start = buffer.as_ptr() as u64;
+    let end = start + buffer.len() as u64;
+    let first = (start + Size2MiB::SIZE - 1) & !(Size2MiB::SIZE - 1);
+    let huge = memory::with_kernel_memory(|mem| {
+        (first..end - Size2MiB::SIZE + 1)
+            .step_by(Size2MiB::SIZE as usize)
+            .any(|addr| {
+                matches!(
+                    mem.mapper.translate(VirtAddr::new(addr)),
+                    TranslateResult::Mapped {
+                        frame: MappedFrame::Size2MiB(_),
+                        ..
+                    }
+                )
+            })
+    })
+    .unwrap();
+    assert!(huge);
+    assert!(buffer.iter().all(|&b| b == 1));
+}
+
+/// 映射失败时，已经分配的大页页帧要还给分配器。
+#[test_case]
+fn failed_huge_mapping_returns_frame() {
+    // 堆的起始部分已经用 4 KiB 页映射了，对应的 P2 项指向 1 级页表，不能再映射大页。
+    let start = VirtAddr::new(HEAP_START as u64).align_down(Size2MiB::SIZE);
+    memory::with_kernel_memory(|mem| {
+        let free = mem.frame_allocator.free_frames();
+        let result = memory::map_huge_pages(
+            &mut mem.mapper,
+            start,
+            Size2MiB::SIZE,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            &mut mem.frame_allocator,
+        );
+        assert!(matches!(result, Err(MapToError::PageAlreadyMapped(_))));
+        assert_eq!(mem.frame_allocator.free_frames(), free);
+    })
+    .unwrap();
+}
+
+/// 用大页把一块物理内存映射到新的虚拟地址，通过物理内存映射写入的数据可以从新地址读到。
+#[test_case]
+fn physical_huge_pages_alias_memory() {
+    // 内核没有使用的虚拟地址。
+    let virt = VirtAddr::new(0x6666_0000_0000);
+    let frame: PhysFrame<Size2MiB> =
+        memory::with_kernel_memory(|mem| mem.frame_allocator.allocate_frame())
+            .unwrap()
+            .expect("out of huge frames");
+    memory::with_kernel_memory(|mem| unsafe {
+        memory::map_physical_huge_pages(
+            &mut mem.mapper,
+            frame.start_address(),
+            virt,
+            Size2MiB::SIZE,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            &mut mem.frame_allocator,
+        )
+    })
+    .unwrap()
+    .expect("failed to map physical huge page");
+    let offset = Size2MiB::SIZE - 8;
+    let window = memory::phys_to_virt(frame.start_address() + offset);
+    unsafe {
+        window.as_mut_ptr::<u64>().write_volatile(0xdead_beef);
+        assert_eq!((virt + offset).as_ptr::<u64>().read_volatile(), 0xdead_beef);
+    }
+    assert_eq!(
+        memory::translate_addr(virt + offset, memory::phys_offset()),
+        Some(frame.start_address() + offset)
+    );
+}
+
+#[test_case]
+fn stats_track_allocations() {
+    let before = allocator::stats();
+    let x = Box::new([0u8; 64]);
+    let during = allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.bytes_in_use, before.bytes_in_use + 64);
+    assert!(during.peak_bytes_in_use >= during.bytes_in_use);
+    drop(x);
+    let after = allocator::stats();
+    assert_eq!(after.deallocations, before.deallocations + 1);
+    assert_eq!(after.bytes_in_use, before.bytes_in_use);
+    assert!(after.largest_free_region <= after.free_bytes);
+    assert_eq!(after.heap_size, allocator::heap_size());
+}
+
 #[test_case]
 fn memory_reuse_for_linked_list_allocator() {
+    // 调试堆会在每次分配前后增加 Header 和红区，地址不再从堆起始处开始。
+    if allocator::allocator_kind() != allocator::AllocatorKind::LinkedList
+        || allocator::debug_heap_enabled()
+    {
+        return;
+    }
     // 第一个堆分配肯定发生在堆起始处。
     let x = Box::new(41);
     let x_ptr = &*x as *const i32;
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    qemu::exit_qemu,
+    qemu::QemuExitCode,
+    serial_print, serial_println,
+    test_support::{expect_panic_message, Buffer},
+};
+
+entry_point!(main);
+
+/// 被重复释放的地址，用于检查 panic 信息。
+static mut DOUBLE_FREE_ADDR: usize = 0;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use kernel::allocator;
+    use kernel::memory::{self, BuddyFrameAllocator};
+
+    serial_print!("heap_double_free::double_free... ");
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    // 只有调试堆能检测出重复释放。
+    allocator::enable_debug_heap();
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+
+    let x = Box::into_raw(Box::new(41u64));
+    unsafe {
+        DOUBLE_FREE_ADDR = x as usize;
+        drop(Box::from_raw(x));
+        drop(Box::from_raw(x));
+    }
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let expected = Buffer::format(format_args!("heap double free at {:#x}", unsafe {
+        DOUBLE_FREE_ADDR
+    }));
+    expect_panic_message(info, &[expected.as_str()], &[])
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, panic::PanicInfo};
+use kernel::{
+    qemu::{exit_qemu, QemuExitCode},
+    serial_print, serial_println,
+    test_support::{expect_panic_message, Buffer},
+};
+
+entry_point!(main);
+
+/// 出错指令的地址，由触发异常的汇编代码写入。
+static mut FAULT_RIP: u64 = 0;
+
+/// 出错前写入 R12 的值，报告中应该原样出现。
+const R12_MARKER: u64 = 0x0123_4567_89ab_cdef;
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("invalid_opcode::fault_is_decoded...\t");
+    kernel::init();
+    unsafe {
+        asm!(
+            "lea rax, [rip + 2f]",
+            "mov [rip + {rip}], rax",
+            "mov r12, {marker}",
+            "2:",
+            "ud2",
+            rip = sym FAULT_RIP,
+            marker = const R12_MARKER,
+            out("rax") _,
+            out("r12") _,
+        );
+    }
+    serial_println!("[failed]");
+    serial_println!("Error: ud2 did not fault");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let rip = Buffer::format(format_args!("Instruction Pointer: {:#x}", unsafe {
+        FAULT_RIP
+    }));
+    let r12 = Buffer::format(format_args!("R12={:#018x}", R12_MARKER));
+    let expected = [
+        "EXCEPTION: INVALID OPCODE (#UD, vector 6)",
+        rip.as_str(),
+        r12.as_str(),
+    ];
+    // #UD 没有错误码。
+    expect_panic_message(info, &expected, &["Error Code"])
+}
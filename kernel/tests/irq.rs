@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use kernel::{
+    allocator,
+    interrupts::{self, IrqError, IrqReturn, MAX_SHARED_HANDLERS},
+    memory::{self, BuddyFrameAllocator},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// 测试使用的 IRQ，没有设备使用它，只用 int 指令触发。
+const TEST_IRQ: u8 = 5;
+
+/// 用软件中断触发 TEST_IRQ 的向量。
+fn raise_test_irq() {
+    unsafe { asm!("int {}", const 32 + TEST_IRQ as usize) };
+}
+
+#[test_case]
+fn builtin_handlers_are_registered() {
+    assert_eq!(interrupts::irq_handlers(0)[0], Some("timer"));
+    assert_eq!(interrupts::irq_handlers(1)[0], Some("keyboard"));
+    assert!(!interrupts::is_irq_masked(0));
+    // 没有处理函数的 IRQ 是屏蔽的。
+    assert!(interrupts::is_irq_masked(TEST_IRQ));
+}
+
+#[test_case]
+fn registered_closure_is_called() {
+    let count = Arc::new(AtomicU64::new(0));
+    let handler_count = count.clone();
+    let handler = interrupts::register_irq(TEST_IRQ, "test", move || {
+        handler_count.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    assert!(!interrupts::is_irq_masked(TEST_IRQ));
+    let before = interrupts::irq_count(TEST_IRQ);
+    raise_test_irq();
+    raise_test_irq();
+    assert_eq!(count.load(Ordering::Relaxed), 2);
+    assert_eq!(interrupts::irq_count(TEST_IRQ), before + 2);
+
+    assert!(interrupts::unregister_irq(handler));
+    assert!(!interrupts::unregister_irq(handler));
+    assert!(interrupts::is_irq_masked(TEST_IRQ));
+    raise_test_irq();
+    assert_eq!(count.load(Ordering::Relaxed), 2);
+    // 已经注销的处理函数持有的闭包被释放。
+    assert_eq!(Arc::strong_count(&count), 1);
+}
+
+#[test_case]
+fn shared_handlers_are_chained() {
+    static FIRST: AtomicU64 = AtomicU64::new(0);
+    static SECOND: AtomicU64 = AtomicU64::new(0);
+    let first = interrupts::register_irq(TEST_IRQ, "first", || {
+        FIRST.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::None
+    })
+    .unwrap();
+    let second = interrupts::register_irq(TEST_IRQ, "second", || {
+        SECOND.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    assert_eq!(
+        interrupts::irq_handlers(TEST_IRQ)[..2],
+        [Some("first"), Some("second")]
+    );
+    let unhandled = interrupts::unhandled_irq_count(TEST_IRQ);
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::Relaxed), 1);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 1);
+    assert_eq!(interrupts::unhandled_irq_count(TEST_IRQ), unhandled);
+
+    // 只剩一个处理函数时中断仍然是允许的；它不认领中断时计入未处理的次数。
+    assert!(interrupts::unregister_irq(second));
+    assert!(!interrupts::is_irq_masked(TEST_IRQ));
+    raise_test_irq();
+    assert_eq!(FIRST.load(Ordering::Relaxed), 2);
+    assert_eq!(SECOND.load(Ordering::Relaxed), 1);
+    assert_eq!(interrupts::unhandled_irq_count(TEST_IRQ), unhandled + 1);
+    assert!(interrupts::unregister_irq(first));
+}
+
+#[test_case]
+fn timer_irq_can_be_shared() {
+    static TICKS: AtomicU64 = AtomicU64::new(0);
+    let handler = interrupts::register_irq(0, "test", || {
+        TICKS.fetch_add(1, Ordering::Relaxed);
+        IrqReturn::Handled
+    })
+    .unwrap();
+    let start = interrupts::timer_ticks();
+    while TICKS.load(Ordering::Relaxed) < 3 {
+        x86_64::instructions::hlt();
+    }
+    assert!(interrupts::timer_ticks() >= start + 3);
+    assert!(interrupts::unregister_irq(handler));
+    // 内置的时钟处理函数仍然在，时钟中断没有被屏蔽。
+    assert!(!interrupts::is_irq_masked(0));
+    let start = interrupts::timer_ticks();
+    while interrupts::timer_ticks() == start {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn registration_limits() {
+    assert_eq!(
+        interrupts::register_irq(16, "invalid", || IrqReturn::None),
+        Err(IrqError::InvalidIrq)
+    );
+    let handlers: [_; MAX_SHARED_HANDLERS] = core::array::from_fn(|_| {
+        interrupts::register_irq(TEST_IRQ, "filler", || IrqReturn::None).unwrap()
+    });
+    assert_eq!(
+        interrupts::register_irq(TEST_IRQ, "extra", || IrqReturn::None),
+        Err(IrqError::TooManyHandlers)
+    );
+    for handler in handlers {
+        assert!(interrupts::unregister_irq(handler));
+    }
+    assert!(interrupts::is_irq_masked(TEST_IRQ));
+}
@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    allocator,
+    memory::{self, BuddyFrameAllocator, KernelSection},
+};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr0Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{mapper::TranslateResult, PageTableFlags, Translate},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+static DATA: [u8; 16] = [1; 16];
+static mut BSS: [u8; 16] = [0; 16];
+
+fn code() -> u64 {
+    42
+}
+/// 页表中 addr 所在页的权限（各级页表项合并之前的最后一级）。
+fn flags(addr: VirtAddr) -> PageTableFlags {
+    memory::with_kernel_memory(|mem| match mem.mapper.translate(addr) {
+        TranslateResult::Mapped { flags, .. } => flags,
+        _ => panic!("{:?} is not mapped", addr),
+    })
+    .unwrap()
+}
+
+const RIGHTS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+#[test_case]
+fn nxe_and_wp_are_enabled() {
+    assert!(Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE));
+    assert!(Cr0::read().contains(Cr0Flags::WRITE_PROTECT));
+}
+
+#[test_case]
+fn sections_are_page_aligned() {
+    for section in [
+        KernelSection::Text,
+        KernelSection::Rodata,
+        KernelSection::Data,
+    ] {
+        let (start, end) = section.range();
+        assert!(start.is_aligned(4096u64) && end.is_aligned(4096u64));
+        assert!(start < end);
+    }
+    assert!(KernelSection::Text.range().1 <= KernelSection::Rodata.range().0);
+    assert!(KernelSection::Rodata.range().1 <= KernelSection::Data.range().0);
+}
+
+#[test_case]
+fn code_is_read_only_and_executable() {
+    let addr = VirtAddr::new(code as fn() -> u64 as u64);
+    assert_eq!(KernelSection::containing(addr), Some(KernelSection::Text));
+    assert_eq!(flags(addr) & RIGHTS, PageTableFlags::PRESENT);
+}
+
+#[test_case]
+fn read_only_data_is_not_executable() {
+    let addr = VirtAddr::from_ptr(&DATA);
+    assert_eq!(KernelSection::containing(addr), Some(KernelSection::Rodata));
+    assert_eq!(
+        flags(addr) & RIGHTS,
+        PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE
+    );
+}
+
+#[test_case]
+fn writable_data_is_not_executable() {
+    #[allow(unused_unsafe)]
+    let addr = VirtAddr::from_ptr(unsafe { core::ptr::addr_of!(BSS) });
+    assert_eq!(KernelSection::containing(addr), Some(KernelSection::Data));
+    assert_eq!(flags(addr) & RIGHTS, RIGHTS);
+    unsafe { core::ptr::addr_of_mut!(BSS).cast::<u8>().write_volatile(1) };
+}
+
+#[test_case]
+fn heap_is_not_executable() {
+    let value = Box::new(42u64);
+    let addr = VirtAddr::from_ptr(&*value);
+    assert_eq!(KernelSection::containing(addr), None);
+    assert_eq!(flags(addr) & RIGHTS, RIGHTS);
+}
+
+#[test_case]
+fn boot_stack_is_not_executable() {
+    let local = 0u64;
+    let addr = VirtAddr::from_ptr(&local);
+    assert_eq!(KernelSection::containing(addr), None);
+    assert_eq!(flags(addr) & RIGHTS, RIGHTS);
+}
+
+#[test_case]
+fn physical_alias_of_kernel_image_is_read_only() {
+    for addr in [
+        VirtAddr::new(code as fn() -> u64 as u64),
+        VirtAddr::from_ptr(&DATA),
+    ] {
+        let phys = memory::translate_addr(addr, memory::phys_offset()).unwrap();
+        let alias = memory::phys_to_virt(phys);
+        assert!(!flags(alias).contains(PageTableFlags::WRITABLE));
+        // 别名仍然可以读。
+        let (original, aliased) = unsafe {
+            (
+                addr.as_ptr::<u8>().read_volatile(),
+                alias.as_ptr::<u8>().read_volatile(),
+            )
+        };
+        assert_eq!(original, aliased);
+    }
+}
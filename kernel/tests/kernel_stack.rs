@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::{self, BuddyFrameAllocator, KernelStack};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap()
+}
+
+fn is_mapped(addr: x86_64::VirtAddr) -> bool {
+    memory::translate_addr(addr, memory::phys_offset()).is_some()
+}
+
+#[test_case]
+fn stack_is_mapped_below_guard_page() {
+    let stack = KernelStack::new(4).unwrap();
+    assert_eq!(stack.size(), 4 * 4096);
+    assert_eq!(stack.top() - stack.bottom(), stack.size());
+    assert!(is_mapped(stack.bottom()));
+    assert!(is_mapped(stack.top() - 1u64));
+    let guard = stack.guard_page().start_address();
+    assert!(!is_mapped(guard));
+    assert!(memory::is_guard_page(guard));
+    assert!(!memory::is_guard_page(stack.bottom()));
+
+    let ptr: *mut u64 = (stack.top() - 8u64).as_mut_ptr();
+    unsafe {
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+}
+
+#[test_case]
+fn stacks_do_not_overlap() {
+    let a = KernelStack::new(KernelStack::MAX_PAGES).unwrap();
+    let b = KernelStack::new(KernelStack::MAX_PAGES).unwrap();
+    assert!(a.top() <= b.guard_page().start_address() || b.top() <= a.guard_page().start_address());
+}
+
+#[test_case]
+fn drop_frees_frames() {
+    // 第一次使用某个栈槽时会分配页表，之后不再释放，先预热一次，释放后同一个栈槽会被再次使用。
+    drop(KernelStack::new(8).unwrap());
+    let free = free_frames();
+    let stack = KernelStack::new(8).unwrap();
+    let bottom = stack.bottom();
+    assert!(free_frames() <= free - 8);
+    drop(stack);
+    assert_eq!(free_frames(), free);
+    assert!(!is_mapped(bottom));
+}
@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{
+    bootinfo::{MemoryMap, MemoryRegionType},
+    entry_point, BootInfo,
+};
+use core::panic::PanicInfo;
+use kernel::memory::{self, BuddyFrameAllocator, ReserveError};
+use spin::Once;
+use x86_64::{
+    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame},
+    PhysAddr,
+};
+
+entry_point!(main);
+
+static MEMORY_MAP: Once<&'static MemoryMap> = Once::new();
+/// 在页帧分配器初始化之前保留的范围。
+static EARLY: Once<PhysAddr> = Once::new();
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    MEMORY_MAP.call_once(|| &boot_info.memory_map);
+    // 保留最后一段可用内存的第一页。
+    let early = usable_regions().last().unwrap().0;
+    memory::reserve_phys(early, 4096, "early").unwrap();
+    EARLY.call_once(|| early);
+
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn usable_regions() -> impl Iterator<Item = (PhysAddr, PhysAddr)> {
+    MEMORY_MAP
+        .get()
+        .unwrap()
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| {
+            (
+                PhysAddr::new(r.range.start_addr()),
+                PhysAddr::new(r.range.end_addr()),
+            )
+        })
+}
+
+fn is_free(frame: PhysFrame) -> bool {
+    let end = frame.start_address() + 4096u64;
+    memory::with_kernel_memory(|mem| {
+        mem.frame_allocator
+            .is_range_free(frame.start_address(), end)
+    })
+    .unwrap()
+}
+
+#[test_case]
+fn boot_regions_are_recorded() {
+    for r in MEMORY_MAP.get().unwrap().iter() {
+        if r.range.start_addr() != r.range.end_addr() {
+            let addr = PhysAddr::new(r.range.start_addr());
+            assert_eq!(memory::region_type(addr), Some(r.region_type));
+        }
+    }
+    memory::print_memory_map();
+}
+
+#[test_case]
+fn early_reservation_is_never_free() {
+    let early = *EARLY.get().unwrap();
+    let reservation = memory::find_reservation(early).unwrap();
+    assert_eq!(reservation.owner, "early");
+    assert_eq!(reservation.end - reservation.start, 4096);
+    assert!(!is_free(PhysFrame::containing_address(early)));
+}
+
+#[test_case]
+fn runtime_reservation_removes_free_frames() {
+    // 找一段连续的 4 个空闲页帧：分配后释放，它们会回到空闲链表中。
+    let frame = memory::with_kernel_memory(|mem| {
+        let range = mem.frame_allocator.allocate_contiguous(4).unwrap();
+        unsafe { mem.frame_allocator.deallocate_contiguous(range) };
+        range.start
+    })
+    .unwrap();
+    let free = memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap();
+    memory::reserve_phys(frame.start_address() + 100u64, 3 * 4096, "test").unwrap();
+    let free_after = memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap();
+    // 跨越 4 个页帧。
+    assert_eq!(free_after, free - 4);
+    for i in 0..4 {
+        assert!(!is_free(frame + i));
+    }
+    assert_eq!(
+        memory::reserve_phys(frame.start_address() + 4096u64, 1, "again"),
+        Err(ReserveError::Overlap)
+    );
+    // 之后分配的页帧不会落在保留的范围内。
+    memory::with_kernel_memory(|mem| {
+        for _ in 0..64 {
+            let allocated: PhysFrame = mem.frame_allocator.allocate_frame().unwrap();
+            assert!(memory::find_reservation(allocated.start_address()).is_none());
+            unsafe { mem.frame_allocator.deallocate_frame(allocated) };
+        }
+    });
+}
+
+#[test_case]
+fn allocated_frames_cannot_be_reserved() {
+    let frame: PhysFrame = memory::with_kernel_memory(|mem| mem.frame_allocator.allocate_frame())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        memory::reserve_phys(frame.start_address(), 4096, "busy"),
+        Err(ReserveError::InUse)
+    );
+    memory::with_kernel_memory(|mem| unsafe { mem.frame_allocator.deallocate_frame(frame) });
+}
+
+#[test_case]
+fn device_memory_can_be_reserved() {
+    // VGA 文本缓冲区不是可用内存，保留它不会影响空闲页帧。
+    let free = memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap();
+    memory::reserve_phys(PhysAddr::new(0xb8000), 4000, "vga").unwrap();
+    let free_after = memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap();
+    assert_eq!(free_after, free);
+}
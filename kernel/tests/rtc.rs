@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    allocator, interrupts,
+    memory::{self, BuddyFrameAllocator},
+    time::{self, rtc, DateTime, RtcError},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    interrupts::init_controller();
+    time::init_clocksource();
+    time::init_wall_clock();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn unix_timestamp_round_trip() {
+    let cases = [
+        (
+            0,
+            DateTime {
+                year: 1970,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0,
+            },
+        ),
+        (
+            951_782_400,
+            DateTime {
+                year: 2000,
+                month: 2,
+                day: 29,
+                hour: 0,
+                minute: 0,
+                second: 0,
+            },
+        ),
+        (
+            1_700_000_000,
+            DateTime {
+                year: 2023,
+                month: 11,
+                day: 14,
+                hour: 22,
+                minute: 13,
+                second: 20,
+            },
+        ),
+    ];
+    for (secs, date_time) in cases {
+        assert_eq!(DateTime::from_unix_timestamp(secs), date_time);
+        assert_eq!(date_time.unix_timestamp(), secs);
+    }
+}
+
+#[test_case]
+fn rtc_date_is_valid() {
+    let now = rtc::read();
+    assert!((2020..2100).contains(&now.year), "{}", now);
+    assert!((1..=12).contains(&now.month), "{}", now);
+    assert!((1..=31).contains(&now.day), "{}", now);
+    assert!(
+        now.hour < 24 && now.minute < 60 && now.second < 60,
+        "{}",
+        now
+    );
+}
+
+#[test_case]
+fn wall_clock_advances() {
+    let start = time::wall_clock();
+    // 墙上时间来自 RTC，肯定晚于 2020 年。
+    assert!(start.as_secs() > 1_577_836_800);
+    let deadline = time::now() + time::Duration::from_millis(20);
+    while time::now() < deadline {
+        x86_64::instructions::hlt();
+    }
+    assert!(time::wall_clock() - start >= time::Duration::from_millis(20));
+}
+
+#[test_case]
+fn periodic_interrupt() {
+    assert_eq!(
+        rtc::enable_periodic_interrupt(1000),
+        Err(RtcError::InvalidFrequency)
+    );
+    let handler = rtc::enable_periodic_interrupt(1024).unwrap();
+    let start = rtc::periodic_interrupts();
+    let deadline = time::now() + time::Duration::from_millis(50);
+    while time::now() < deadline {
+        x86_64::instructions::hlt();
+    }
+    rtc::disable_periodic_interrupt(handler);
+    assert!(rtc::periodic_interrupts() > start);
+}
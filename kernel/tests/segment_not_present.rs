@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::{arch::asm, panic::PanicInfo};
+use kernel::{
+    qemu::{exit_qemu, QemuExitCode},
+    serial_print, serial_println,
+    test_support::{expect_panic_message, Buffer},
+};
+
+entry_point!(main);
+
+/// 出错指令的地址，由触发异常的汇编代码写入。
+static mut FAULT_RIP: u64 = 0;
+
+/// 没有安装处理函数的向量，IDT 中对应的表项不存在。
+const VECTOR: u8 = 0x80;
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("segment_not_present::fault_is_decoded...\t");
+    kernel::init();
+    unsafe {
+        asm!(
+            "lea rax, [rip + 2f]",
+            "mov [rip + {rip}], rax",
+            "2:",
+            "int {vector}",
+            rip = sym FAULT_RIP,
+            vector = const VECTOR,
+            out("rax") _,
+        );
+    }
+    serial_println!("[failed]");
+    serial_println!("Error: int through a missing IDT entry did not fault");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let rip = Buffer::format(format_args!("Instruction Pointer: {:#x}", unsafe {
+        FAULT_RIP
+    }));
+    // 通过不存在的 IDT 表项触发中断时产生 #NP，错误码指向 IDT 中的表项。
+    let error_code = Buffer::format(format_args!(
+        "Error Code: {:#x} (selector: IDT index {:#x})",
+        ((VECTOR as u64) << 3) | 2,
+        VECTOR
+    ));
+    let expected = [
+        "EXCEPTION: SEGMENT NOT PRESENT (#NP, vector 11)",
+        error_code.as_str(),
+        rip.as_str(),
+    ];
+    expect_panic_message(info, &expected, &[])
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    allocator::{self, AllocatorKind, SLAB_AREA_END, SLAB_AREA_START},
+    memory::{self, BuddyFrameAllocator},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap_with(AllocatorKind::Slab, &mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap()
+}
+
+/// 分配 count 个 64 字节的对象，占用若干个 slab。
+fn allocate_objects(count: usize) -> Vec<Box<[u8; 64]>> {
+    (0..count).map(|i| Box::new([i as u8; 64])).collect()
+}
+
+#[test_case]
+fn slabs_are_mapped_in_slab_area() {
+    let objects = allocate_objects(256);
+    assert!(objects.iter().any(|object| {
+        let addr = object.as_ptr() as usize;
+        (SLAB_AREA_START..SLAB_AREA_END).contains(&addr)
+    }));
+    assert!(objects
+        .iter()
+        .enumerate()
+        .all(|(i, object)| object.iter().all(|&b| b == i as u8)));
+}
+
+#[test_case]
+fn empty_slabs_return_frames() {
+    // 第一次使用时会分配页表，之后不再释放，先预热一次。
+    drop(allocate_objects(1024));
+    let free = free_frames();
+
+    let objects = allocate_objects(1024);
+    // 1024 个 64 字节的对象至少需要 16 个 slab。
+    assert!(free_frames() + 16 <= free);
+    drop(objects);
+    // 每个缓存最多保留一个空闲的 slab。
+    assert!(free_frames() + 1 >= free);
+}
@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+    arch::asm,
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::control::Cr2,
+    structures::{
+        idt::{InterruptDescriptorTable, InterruptStackFrame},
+        paging::Page,
+    },
+};
+
+use kernel::{
+    memory::{self, BuddyFrameAllocator, KernelStack},
+    qemu::{exit_qemu, QemuExitCode},
+    serial_print, serial_println,
+};
+
+entry_point!(main);
+
+/// 测试栈的保护页地址。
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(kernel::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let addr = Cr2::read();
+    let page = Page::<x86_64::structures::paging::Size4KiB>::containing_address(addr);
+    if memory::is_guard_page(addr)
+        && page.start_address().as_u64() == GUARD_PAGE.load(Ordering::Relaxed)
+    {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: double fault at {:?}, not in the guard page", addr);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_guard::overflow_hits_guard_page...\t");
+
+    kernel::gdt::init();
+    TEST_IDT.load();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    // 同时把 double fault 的 IST 换成带保护页的栈。
+    memory::init_global(mapper, frame_allocator);
+
+    let stack = KernelStack::new(4).unwrap();
+    GUARD_PAGE.store(
+        stack.guard_page().start_address().as_u64(),
+        Ordering::Relaxed,
+    );
+    let top = stack.leak();
+    // 切换到新的栈上递归，直到访问保护页。
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "call {entry}",
+            top = in(reg) top.as_u64(),
+            entry = sym overflow_entry,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn overflow_entry() -> ! {
+    stack_overflow();
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow(); // for each recursion, the return address is pushed
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
@@ -0,0 +1,297 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use bootloader::{entry_point, BootInfo};
+use core::{
+    cell::Cell,
+    future::{pending, poll_fn},
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    task::Poll,
+};
+use kernel::{
+    allocator,
+    interrupts::{self, IrqReturn},
+    memory::{self, BuddyFrameAllocator},
+    task::{
+        self,
+        executor::{Executor, SpawnError},
+        JoinError, Task,
+    },
+    time::{self, Duration},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// 被丢弃时设置标志。
+struct DropFlag(Rc<Cell<bool>>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+/// 第一次 poll 时唤醒自己 wakes 次并返回 Pending，第二次返回 Ready。
+async fn yield_with_wakes(wakes: usize) {
+    let mut first = true;
+    poll_fn(|cx| {
+        if !first {
+            return Poll::Ready(());
+        }
+        first = false;
+        for _ in 0..wakes {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+#[test_case]
+fn join_handle_returns_output() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(Task::new(async {
+        time::sleep(Duration::from_millis(5)).await;
+        42
+    }));
+    let result = Rc::new(Cell::new(None));
+    let output = result.clone();
+    executor.spawn(Task::new(async move {
+        output.set(Some(handle.await));
+    }));
+    executor.run_until_complete();
+    assert_eq!(result.get(), Some(Ok(42)));
+}
+
+#[test_case]
+fn abort_removes_pending_task() {
+    let dropped = Rc::new(Cell::new(false));
+    let flag = DropFlag(dropped.clone());
+    let mut executor = Executor::new();
+    let handle = executor.spawn(Task::new(async move {
+        let _flag = flag;
+        pending::<()>().await
+    }));
+    let result = Rc::new(Cell::new(None));
+    let output = result.clone();
+    executor.spawn(Task::new(async move {
+        time::sleep(Duration::from_millis(1)).await;
+        assert!(!handle.is_finished());
+        handle.abort();
+        output.set(Some(handle.await));
+    }));
+    // 被 abort 的任务必须从执行器中移除，否则 run_until_complete 不会返回。
+    executor.run_until_complete();
+    assert!(dropped.get());
+    assert_eq!(result.get(), Some(Err(JoinError::Cancelled)));
+}
+
+#[test_case]
+fn abort_after_completion_keeps_output() {
+    let mut executor = Executor::new();
+    let handle = executor.spawn(Task::new(async { 7 }));
+    executor.run_until_complete();
+    assert!(handle.is_finished());
+    handle.abort();
+    executor.spawn(Task::new(async move {
+        assert_eq!(handle.await, Ok(7));
+    }));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn dropped_handle_detaches_task() {
+    let ran = Rc::new(Cell::new(false));
+    let flag = ran.clone();
+    let mut executor = Executor::new();
+    drop(executor.spawn(Task::new(async move {
+        time::sleep(Duration::from_millis(1)).await;
+        flag.set(true);
+    })));
+    executor.run_until_complete();
+    assert!(ran.get());
+}
+
+#[test_case]
+fn repeated_wakes_are_coalesced() {
+    let polls = Rc::new(Cell::new(0));
+    let counter = polls.clone();
+    let mut executor = Executor::new();
+    // 远多于就绪队列的容量，同一个任务的重复唤醒只入队一次。
+    executor.spawn(Task::new(async move {
+        counter.set(counter.get() + 1);
+        yield_with_wakes(1000).await;
+        counter.set(counter.get() + 1);
+    }));
+    executor.run_until_complete();
+    assert_eq!(polls.get(), 2);
+}
+
+#[test_case]
+fn more_ready_tasks_than_queue_capacity() {
+    let finished = Rc::new(Cell::new(0));
+    let mut executor = Executor::new();
+    // 就绪队列只能容纳 100 个任务，放不下的唤醒在队列清空之后补上。
+    for _ in 0..250 {
+        let finished = finished.clone();
+        executor.spawn(Task::new(async move {
+            yield_with_wakes(1).await;
+            finished.set(finished.get() + 1);
+        }));
+    }
+    executor.run_until_complete();
+    assert_eq!(finished.get(), 250);
+}
+
+#[test_case]
+fn current_task_is_set_while_polling() {
+    assert_eq!(task::current_task(), None);
+    let mut executor = Executor::new();
+    let id = Rc::new(Cell::new(None));
+    let current = id.clone();
+    let handle = executor.spawn(Task::new(async move {
+        current.set(task::current_task());
+    }));
+    executor.run_until_complete();
+    assert_eq!(id.get(), Some(handle.id()));
+    assert_eq!(task::current_task(), None);
+}
+
+#[test_case]
+fn running_task_spawns_task() {
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let result = Rc::new(Cell::new(None));
+    let output = result.clone();
+    executor.spawn(Task::new(async move {
+        let inner = spawner.clone();
+        let handle = spawner
+            .spawn(async move {
+                // 被创建的任务也可以继续创建任务。
+                inner.spawn(async { 20 }).unwrap().await.unwrap() + 1
+            })
+            .unwrap();
+        output.set(Some(handle.await));
+    }));
+    executor.run_until_complete();
+    assert_eq!(result.get(), Some(Ok(21)));
+}
+
+#[test_case]
+fn spawn_before_run_and_after_drop() {
+    static RAN: AtomicBool = AtomicBool::new(false);
+    let executor = Executor::new();
+    let spawner = executor.spawner();
+    let handle = spawner
+        .spawn(async {
+            RAN.store(true, Ordering::Relaxed);
+        })
+        .unwrap();
+    // 执行器没有运行过，注入的任务随执行器一起被丢弃。
+    drop(executor);
+    assert!(!RAN.load(Ordering::Relaxed));
+    assert!(handle.is_finished());
+    assert_eq!(spawner.spawn(async {}).map(drop), Err(SpawnError::Closed));
+}
+
+#[test_case]
+fn spawn_from_interrupt_handler() {
+    static SPAWNED: AtomicBool = AtomicBool::new(false);
+    static RAN_AT_TICK: AtomicU64 = AtomicU64::new(0);
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    let handler = interrupts::register_irq(0, "spawn-test", move || {
+        if !SPAWNED.swap(true, Ordering::Relaxed) {
+            // 在中断处理函数中只创建任务，工作推迟到任务中完成。
+            drop(spawner.spawn(async {
+                RAN_AT_TICK.store(time::ticks(), Ordering::Relaxed);
+            }));
+        }
+        IrqReturn::Handled
+    })
+    .unwrap();
+    while !SPAWNED.load(Ordering::Relaxed) {
+        x86_64::instructions::hlt();
+    }
+    assert!(interrupts::unregister_irq(handler));
+    executor.run_until_complete();
+    assert_ne!(RAN_AT_TICK.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn injector_and_ready_queue_both_full() {
+    static INJECTED_FINISHED: AtomicUsize = AtomicUsize::new(0);
+    let finished = Rc::new(Cell::new(0));
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    // 两个队列的容量都是 100。
+    for _ in 0..100 {
+        let finished = finished.clone();
+        executor.spawn(Task::new(async move {
+            finished.set(finished.get() + 1);
+        }));
+    }
+    for _ in 0..100 {
+        drop(
+            spawner
+                .spawn(async {
+                    INJECTED_FINISHED.fetch_add(1, Ordering::Relaxed);
+                })
+                .unwrap(),
+        );
+    }
+    assert_eq!(
+        spawner.spawn(async {}).map(drop),
+        Err(SpawnError::QueueFull)
+    );
+    // 就绪队列满时注入的任务留在注入队列中，等就绪队列有空间之后再取出。
+    executor.run_until_complete();
+    assert_eq!(finished.get(), 100);
+    assert_eq!(INJECTED_FINISHED.load(Ordering::Relaxed), 100);
+}
+
+#[test_case]
+fn abort_injected_task_while_ready_queue_full() {
+    static INJECTED_RAN: AtomicBool = AtomicBool::new(false);
+    let mut executor = Executor::new();
+    let spawner = executor.spawner();
+    // 占满就绪队列。
+    for _ in 0..100 {
+        executor.spawn(Task::new(async {}));
+    }
+    let handle = spawner
+        .spawn(async {
+            INJECTED_RAN.store(true, Ordering::Relaxed);
+        })
+        .unwrap();
+    // 任务还在注入队列中，就绪队列放不下这次唤醒。
+    handle.abort();
+    // 被 abort 的任务必须从执行器中移除，否则 run_until_complete 不会返回。
+    executor.run_until_complete();
+    assert!(handle.is_finished());
+    assert!(!INJECTED_RAN.load(Ordering::Relaxed));
+}
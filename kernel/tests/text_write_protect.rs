@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use lazy_static::lazy_static;
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+};
+
+use kernel::{
+    memory::{self, BuddyFrameAllocator},
+    qemu::{exit_qemu, QemuExitCode},
+    serial_print, serial_println,
+};
+
+entry_point!(main);
+
+/// 测试写入的代码地址。
+static TARGET: AtomicU64 = AtomicU64::new(0);
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let expected = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if Cr2::read().as_u64() == TARGET.load(Ordering::Relaxed) && error_code.contains(expected) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!(
+            "Error: unexpected page fault at {:?}: {:?}",
+            Cr2::read(),
+            error_code
+        );
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("text_write_protect::write_to_code_faults...\t");
+
+    kernel::gdt::init();
+    TEST_IDT.load();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    let target = main as fn(&'static BootInfo) -> ! as *mut u8;
+    TARGET.store(target as u64, Ordering::Relaxed);
+    // 开启 CR0.WP 之后，内核态写只读的代码页也会触发 page fault。
+    unsafe { target.write_volatile(0xcc) };
+
+    serial_println!("[failed]");
+    serial_println!("Error: writing to kernel code did not fault");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::{
+    interrupts::{self, ControllerKind},
+    memory::{self, BuddyFrameAllocator},
+    time::{self, ClockSource, Duration, Instant, TickSource},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+    interrupts::init_controller();
+    time::init_clocksource();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// 等待 n 个时钟中断。
+fn wait_ticks(n: u64) {
+    let start = time::ticks();
+    while time::ticks() < start + n {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn clock_source_is_calibrated() {
+    if let Some(hz) = time::tsc_frequency() {
+        // 任何能运行这个内核的 CPU 的 TSC 都在 100 MHz 以上。
+        assert!(hz > 100_000_000, "TSC frequency {} Hz", hz);
+    }
+    assert!(time::tsc_frequency().is_some() || time::clock_source() != ClockSource::Tsc);
+}
+
+#[test_case]
+fn local_apic_drives_ticks_with_apic() {
+    let expected = match interrupts::controller() {
+        ControllerKind::Apic => TickSource::LocalApic,
+        ControllerKind::Pic => TickSource::Pit,
+    };
+    assert_eq!(time::tick_source(), expected);
+}
+
+#[test_case]
+fn now_is_monotonic() {
+    let mut last = time::now();
+    for _ in 0..10_000 {
+        let now = time::now();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn ticks_match_the_clock() {
+    wait_ticks(1);
+    let start = Instant::now();
+    wait_ticks(20);
+    let elapsed = start.elapsed();
+    // 20 个 tick 是 20 毫秒，模拟器中的时间不够精确，只检查数量级。
+    assert!(elapsed >= Duration::from_millis(10), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(200), "{:?}", elapsed);
+}
+
+#[test_case]
+fn instant_arithmetic() {
+    let start = Instant::from_nanos(1_000);
+    let later = start + Duration::from_micros(5);
+    assert_eq!(later.as_nanos(), 6_000);
+    assert_eq!(later - start, Duration::from_micros(5));
+    assert_eq!(start - later, Duration::ZERO);
+    assert_eq!(start.checked_duration_since(later), None);
+    assert_eq!(later - Duration::from_micros(5), start);
+    assert_eq!(start.checked_sub(Duration::from_micros(2)), None);
+    assert_eq!(
+        Instant::from_nanos(u64::MAX).checked_add(Duration::from_nanos(1)),
+        None
+    );
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{rc::Rc, vec::Vec};
+use bootloader::{entry_point, BootInfo};
+use core::{cell::RefCell, future::pending, panic::PanicInfo};
+use futures_util::StreamExt;
+use kernel::{
+    allocator,
+    memory::{self, BuddyFrameAllocator},
+    task::{executor::Executor, Task},
+    time::{self, Duration, Instant},
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mut mapper = memory::init(phys_mem_offset);
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_global(mapper, frame_allocator);
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn sleep_waits_for_the_duration() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let start = Instant::now();
+        time::sleep(Duration::from_millis(20)).await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn sleep_until_past_deadline_is_ready() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let sleep = time::sleep_until(Instant::ZERO);
+        assert!(sleep.is_elapsed());
+        sleep.await;
+    }));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn sleepers_wake_in_deadline_order() {
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    // 超过 64 个 tick 的定时器在时间轮的第 1 层，转到时才移到第 0 层。
+    for ms in [150, 70, 5, 64, 1] {
+        let order = order.clone();
+        executor.spawn(Task::new(async move {
+            time::sleep(Duration::from_millis(ms)).await;
+            order.borrow_mut().push(ms);
+        }));
+    }
+    executor.run_until_complete();
+    assert_eq!(*order.borrow(), [1, 5, 64, 70, 150]);
+}
+
+#[test_case]
+fn interval_ticks_periodically() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let period = Duration::from_millis(10);
+        let mut interval = time::interval(period);
+        let first = interval.tick().await;
+        let second = interval.tick().await;
+        assert_eq!(second - first, period);
+        let third = interval.next().await.unwrap();
+        // 任务没有及时等待时会跳过错过的周期。
+        assert!(third - second >= period);
+        assert!(Instant::now() >= third);
+    }));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn timeout_expires() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let start = Instant::now();
+        let result = time::timeout(Duration::from_millis(10), pending::<()>()).await;
+        assert!(result.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn timeout_returns_output() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        let sleep = async {
+            time::sleep(Duration::from_millis(5)).await;
+            42
+        };
+        assert_eq!(time::timeout(Duration::from_secs(10), sleep).await, Ok(42));
+    }));
+    executor.run_until_complete();
+}
+
+#[test_case]
+fn dropped_sleep_does_not_wake() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async {
+        // 超时后内部的 Sleep 被丢弃，它的定时器必须被取消，之后的 sleep 仍然正常工作。
+        let inner = time::sleep(Duration::from_millis(50));
+        assert!(time::timeout(Duration::from_millis(5), inner)
+            .await
+            .is_err());
+        time::sleep(Duration::from_millis(60)).await;
+    }));
+    executor.run_until_complete();
+}
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::{
+    self, vma::VmaKind, AddressSpace, BuddyFrameAllocator, UserAccessError, USER_SPACE_END,
+    USER_SPACE_START,
+};
+use x86_64::{
+    registers::control::{Cr4, Cr4Flags},
+    structures::paging::{Page, PageTableFlags},
+    VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+const USER_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::USER_ACCESSIBLE)
+    .union(PageTableFlags::NO_EXECUTE);
+
+fn user_page() -> Page {
+    Page::containing_address(VirtAddr::new(USER_SPACE_START))
+}
+
+#[test_case]
+fn smep_and_smap_follow_cpuid() {
+    #[allow(unused_unsafe)]
+    let features = unsafe { core::arch::x86_64::__cpuid_count(7, 0) }.ebx;
+    assert_eq!(memory::smep_enabled(), features & (1 << 7) != 0);
+    assert_eq!(memory::smap_enabled(), features & (1 << 20) != 0);
+    assert_eq!(
+        memory::smap_enabled(),
+        Cr4::read().contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION)
+    );
+}
+
+#[test_case]
+fn copy_round_trip() {
+    let page = user_page();
+    let mut space = AddressSpace::new().unwrap();
+    space.map(page, USER_FLAGS).unwrap();
+    let addr = page.start_address() + 100u64;
+    let mut buf = [0u8; 32];
+    unsafe { space.switch() };
+    let written = memory::copy_to_user(addr, b"hello from the kernel");
+    let read = memory::copy_from_user(&mut buf[..21], addr);
+    unsafe { memory::switch_to_kernel() };
+    assert_eq!(written, Ok(()));
+    assert_eq!(read, Ok(()));
+    assert_eq!(&buf[..21], b"hello from the kernel");
+}
+
+#[test_case]
+fn copy_pages_in_user_regions_on_demand() {
+    let start = VirtAddr::new(USER_SPACE_START);
+    let mut space = AddressSpace::new().unwrap();
+    space
+        .add_vma(start, 2 * 4096, USER_FLAGS, VmaKind::Anonymous)
+        .unwrap();
+    let data = [0x5au8; 4096];
+    let mut buf = [0u8; 4096];
+    unsafe { space.switch() };
+    // 跨越两页，两页都在复制过程中按需映射。
+    let written = memory::copy_to_user(start + 2048u64, &data);
+    let read = memory::copy_from_user(&mut buf, start + 2048u64);
+    unsafe { memory::switch_to_kernel() };
+    assert_eq!(written, Ok(()));
+    assert_eq!(read, Ok(()));
+    assert!(buf.iter().all(|&b| b == 0x5a));
+    assert!(space.translate(start + 4096u64).is_some());
+}
+
+#[test_case]
+fn faults_are_fixed_up() {
+    let page = user_page();
+    let mut space = AddressSpace::new().unwrap();
+    space.map(page, USER_FLAGS).unwrap();
+    // 第二页没有映射，复制在页边界处停止。
+    let addr = page.start_address() + 4000u64;
+    let mut buf = [0u8; 200];
+    unsafe { space.switch() };
+    let read = memory::copy_from_user(&mut buf, addr);
+    let written = memory::copy_to_user(addr, &buf);
+    let unmapped = memory::copy_from_user(&mut buf, page.start_address() + 8192u64);
+    unsafe { memory::switch_to_kernel() };
+    assert_eq!(read, Err(UserAccessError::Fault { copied: 96 }));
+    assert_eq!(written, Err(UserAccessError::Fault { copied: 96 }));
+    assert_eq!(unmapped, Err(UserAccessError::Fault { copied: 0 }));
+}
+
+#[test_case]
+fn kernel_addresses_are_rejected() {
+    let value = 42u64;
+    let mut buf = [0u8; 8];
+    assert_eq!(
+        memory::copy_from_user(&mut buf, VirtAddr::from_ptr(&value)),
+        Err(UserAccessError::OutOfRange)
+    );
+    assert_eq!(
+        memory::copy_to_user(VirtAddr::new(USER_SPACE_END - 4), &buf),
+        Err(UserAccessError::OutOfRange)
+    );
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::memory::{self, BuddyFrameAllocator, CacheMode, VMALLOC_END, VMALLOC_START};
+use x86_64::{
+    structures::paging::{
+        mapper::TranslateResult, FrameAllocator, FrameDeallocator, PageTableFlags, PhysFrame,
+        Translate,
+    },
+    PhysAddr, VirtAddr,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    kernel::init();
+    let phys_mem_offset = boot_info.physical_memory_offset;
+    let mapper = memory::init(phys_mem_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::with_kernel_memory(|mem| mem.frame_allocator.free_frames()).unwrap()
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::translate_addr(addr, memory::phys_offset()).is_some()
+}
+
+#[test_case]
+fn vmalloc_is_contiguous_and_zeroed() {
+    // 第一次使用时会分配页表，之后不再释放，先预热一次。
+    memory::vfree(memory::vmalloc(4096).unwrap());
+    let free = free_frames();
+
+    let len = 5 * 4096 + 1;
+    let addr = memory::vmalloc(len).unwrap();
+    assert!((VMALLOC_START..VMALLOC_END).contains(&addr.as_u64()));
+    assert_eq!(free_frames(), free - 6);
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr.as_mut_ptr::<u8>(), 6 * 4096) };
+    assert!(buf.iter().all(|&b| b == 0));
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    assert!(buf.iter().enumerate().all(|(i, &b)| b == i as u8));
+
+    memory::vfree(addr);
+    assert!(!is_mapped(addr));
+    assert_eq!(free_frames(), free);
+}
+
+#[test_case]
+fn areas_are_separated_by_guard_pages() {
+    let a = memory::vmalloc(4096).unwrap();
+    let b = memory::vmalloc(4096).unwrap();
+    assert!(b >= a + 2 * 4096u64 || a >= b + 2 * 4096u64);
+    assert!(!is_mapped(a + 4096u64));
+    memory::vfree(a);
+    // 释放后的空隙可以被重新使用。
+    let c = memory::vmalloc(4096).unwrap();
+    assert_eq!(c, a);
+    memory::vfree(b);
+    memory::vfree(c);
+}
+
+#[test_case]
+fn vmap_maps_given_frames() {
+    let frames: [PhysFrame; 2] = memory::with_kernel_memory(|mem| {
+        [
+            mem.frame_allocator.allocate_frame().unwrap(),
+            mem.frame_allocator.allocate_frame().unwrap(),
+        ]
+    })
+    .unwrap();
+    for (i, frame) in frames.iter().enumerate() {
+        let ptr: *mut u64 = memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+        unsafe { ptr.write_volatile(i as u64 + 1) };
+    }
+    // 以相反的顺序映射。
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let addr = unsafe { memory::vmap(&[frames[1], frames[0]], flags) }.unwrap();
+    unsafe {
+        assert_eq!(addr.as_ptr::<u64>().read_volatile(), 2);
+        assert_eq!((addr + 4096u64).as_ptr::<u64>().read_volatile(), 1);
+    }
+    memory::vunmap(addr);
+    assert!(!is_mapped(addr));
+    // 页帧仍然属于调用者。
+    memory::with_kernel_memory(|mem| {
+        for frame in frames {
+            unsafe { mem.frame_allocator.deallocate_frame(frame) };
+        }
+    });
+}
+
+#[test_case]
+fn ioremap_uses_cache_mode() {
+    // VGA 文本缓冲区，最后一个字符。
+    let phys = PhysAddr::new(0xb8000 + 25 * 80 * 2 - 2);
+    let addr = unsafe { memory::ioremap(phys, 2, CacheMode::Uncached) }.unwrap();
+    assert_eq!(addr.as_u64() % 4096, phys.as_u64() % 4096);
+    let flags = memory::with_kernel_memory(|mem| match mem.mapper.translate(addr) {
+        TranslateResult::Mapped { flags, .. } => flags,
+        _ => panic!("ioremap did not map {:?}", addr),
+    })
+    .unwrap();
+    assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::NO_EXECUTE));
+    let identity = phys.as_u64() as *const u16;
+    unsafe {
+        addr.as_mut_ptr::<u16>().write_volatile(0x0f21);
+        assert_eq!(identity.read_volatile(), 0x0f21);
+    }
+    memory::iounmap(addr);
+    assert!(!is_mapped(addr));
+}
+
+#[test_case]
+fn ioremap_write_combining() {
+    let phys = PhysAddr::new(0xb8000 + 25 * 80 * 2 - 4);
+    let addr = unsafe { memory::ioremap(phys, 2, CacheMode::WriteCombining) }.unwrap();
+    // 设置了 PAT 位的 4 KiB 页也能被正确转换。
+    assert_eq!(
+        memory::translate_addr(addr, memory::phys_offset()),
+        Some(phys)
+    );
+    unsafe {
+        addr.as_mut_ptr::<u16>().write_volatile(0x0f22);
+        core::arch::asm!("sfence");
+        assert_eq!((phys.as_u64() as *const u16).read_volatile(), 0x0f22);
+    }
+    memory::iounmap(addr);
+    assert!(!is_mapped(addr));
+}
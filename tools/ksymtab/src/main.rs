@@ -0,0 +1,102 @@
+//! 把内核 ELF 中的函数符号写入镜像中预留的符号表区域（链接脚本中的 __ksymtab_start..__ksymtab_end），
+//! 内核回溯时用它把返回地址解析为 函数名+偏移。格式见 kernel/src/backtrace/symbols.rs。
+//!
+//! 用法：ksymtab <kernel-elf>，原地修改文件。由 tools/runner.sh 在启动 QEMU 之前自动执行。
+
+use std::{env, fs, path::Path, process};
+
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+
+const MAGIC: &[u8; 8] = b"KSYMTAB\0";
+
+fn main() {
+    let Some(path) = env::args_os().nth(1) else {
+        eprintln!("usage: ksymtab <kernel-elf>");
+        process::exit(2);
+    };
+    if let Err(err) = run(Path::new(&path)) {
+        eprintln!("ksymtab: {}: {}", Path::new(&path).display(), err);
+        process::exit(1);
+    }
+}
+
+fn run(path: &Path) -> Result<(), String> {
+    let mut data = fs::read(path).map_err(|err| err.to_string())?;
+    let (offset, capacity, table) = {
+        let file = object::File::parse(&*data).map_err(|err| err.to_string())?;
+        let start = symbol_address(&file, "__ksymtab_start")?;
+        let end = symbol_address(&file, "__ksymtab_end")?;
+        (
+            file_offset(&file, start)?,
+            (end - start) as usize,
+            encode(&functions(&file)),
+        )
+    };
+    if table.len() > capacity {
+        return Err(format!(
+            "symbol table needs {} bytes but only {} are reserved, enlarge ksymtab_size in kernel/build.rs",
+            table.len(),
+            capacity
+        ));
+    }
+    let region = &mut data[offset..offset + capacity];
+    region.fill(0);
+    region[..table.len()].copy_from_slice(&table);
+    fs::write(path, data).map_err(|err| err.to_string())
+}
+
+fn symbol_address(file: &object::File, name: &str) -> Result<u64, String> {
+    file.symbols()
+        .find(|symbol| symbol.name() == Ok(name))
+        .map(|symbol| symbol.address())
+        .ok_or_else(|| {
+            format!(
+                "symbol {} not found, is the kernel linked with linker.ld?",
+                name
+            )
+        })
+}
+
+/// 虚拟地址 addr 在文件中的偏移。
+fn file_offset(file: &object::File, addr: u64) -> Result<usize, String> {
+    file.sections()
+        .find(|section| (section.address()..section.address() + section.size()).contains(&addr))
+        .and_then(|section| {
+            let (offset, _) = section.file_range()?;
+            Some((offset + addr - section.address()) as usize)
+        })
+        .ok_or_else(|| format!("address {:#x} is not backed by the file", addr))
+}
+
+/// 所有函数符号（地址、大小、去掉哈希的名字），按地址排序，同一地址只保留一个。
+fn functions(file: &object::File) -> Vec<(u64, u64, String)> {
+    let mut functions: Vec<_> = file
+        .symbols()
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+        .filter_map(|symbol| {
+            let name = symbol.name().ok()?;
+            let name = format!("{:#}", rustc_demangle::demangle(name));
+            Some((symbol.address(), symbol.size(), name))
+        })
+        .collect();
+    functions.sort_by_key(|&(addr, _, _)| addr);
+    functions.dedup_by_key(|&mut (addr, _, _)| addr);
+    functions
+}
+
+/// 按内核读取的格式编码：表头、符号数组、以 0 结尾的名字。
+fn encode(functions: &[(u64, u64, String)]) -> Vec<u8> {
+    let mut table = Vec::new();
+    let mut names = Vec::new();
+    table.extend_from_slice(MAGIC);
+    table.extend_from_slice(&(functions.len() as u64).to_le_bytes());
+    for (addr, size, name) in functions {
+        table.extend_from_slice(&addr.to_le_bytes());
+        table.extend_from_slice(&(u32::try_from(*size).unwrap_or(0)).to_le_bytes());
+        table.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    table.extend_from_slice(&names);
+    table
+}
@@ -0,0 +1,9 @@
+#!/bin/sh
+# cargo run / cargo test 的 runner：先用 ksymtab 把符号表写入内核镜像，再交给 bootimage runner 在 QEMU 中运行。
+# 参数和 bootimage runner 相同，第一个参数是内核 ELF 的路径。
+set -e
+TOOLS=$(cd "$(dirname "$0")" && pwd)
+KERNEL=$(cd "$(dirname "$1")" && pwd)/$(basename "$1")
+# 在工具目录中构建，避免使用内核的 .cargo/config.toml（其中的 target 是内核的目标平台）。
+(cd "$TOOLS/ksymtab" && cargo run --quiet --release -- "$KERNEL")
+exec bootimage runner "$@"